@@ -1,18 +1,104 @@
 use super::parser::{Axis, AdvancedFace, FaceElement};
-use super::math::{V3, Mat3x3};
+use super::math::{V3, Mat3x3, Tolerance, fit_circle};
+use super::nurbs::NurbsSurface;
+use super::mesh::{Mesh, Surface};
+use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hole {
+    // through the wall facing the drill, with the width of the hollow behind it
+    Through(f64),
+    // through the wall facing the drill and the opposite one
+    BothWalls,
+    // the depth from the outer surface
+    Blind(f64),
+}
+
+#[derive(Debug, Clone)]
 pub struct Drill {
+    pub r: f64,
     pub d: f64,
     pub theta: f64,
     pub slide: f64,
+    pub hole: Hole,
+    // along the drill from the center of the pipe, where the hole starts and ends
+    pub top: f64,
+    pub bottom: f64,
+}
+
+impl Drill {
+    // the walls the hole goes through, from the top down
+    pub fn spans(&self, section: &Section) -> Vec<(f64, f64)> {
+        let direction = V3([self.theta.cos(), self.theta.sin(), 0.0]);
+        let foot = direction.cross(&V3([0.0, 0.0, 1.0])).scale(self.slide);
+        wall_spans(section, &foot, &direction)
+            .into_iter()
+            .rev()
+            .filter(|(a, b)| *b > self.bottom && *a < self.top)
+            .map(|(a, b)| (a.max(self.bottom), b.min(self.top)))
+            .collect()
+    }
+}
+
+// an oblong hole milled from one end to the other, the ends as in Drill
+#[derive(Debug, Clone)]
+pub struct Slot {
+    pub r: f64,
+    pub theta: f64,
+    // d and slide of the centers of the ends
+    pub ends: [(f64, f64); 2],
+    pub hole: Hole,
+    pub top: f64,
+    pub bottom: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AnalysisError {
+    // the plane faces do not span three orthogonal axes of a square pipe
+    AxesNotFound,
+    // the section changes along the pipe or is not hollow
+    NotTube(String),
+    // the faces have no points on their boundaries to measure the pipe
+    NoPoints,
+}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnalysisError::AxesNotFound => write!(f, "plane faces do not form a square pipe"),
+            AnalysisError::NotTube(reason) => write!(f, "not a constant-section tube: {}", reason),
+            AnalysisError::NoPoints => write!(f, "faces have no boundary points"),
+        }
+    }
+}
+
+// cross-section of a rectangular tube, positions of the walls from the center
+// across x and y of Proc in the order outer, inner, inner, outer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub x: [f64; 4],
+    pub y: [f64; 4],
+    // 0 for sharp corners
+    pub inner_corner_r: f64,
+}
+
+impl Section {
+    pub fn thickness(&self) -> (f64, f64) {
+        (self.x[1] - self.x[0], self.y[1] - self.y[0])
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Proc {
     pub drills: Vec<Drill>,
+    pub slots: Vec<Slot>,
     pub center: V3,
     pub size: V3,
+    pub section: Section,
+    // of the pipe in the aligned coordinates, z along the pipe
+    pub axes: (V3, V3, V3),
+    // from the coordinates of the model to the aligned ones
+    pub rotation: Mat3x3,
     pub report: String,
 }
 
@@ -40,16 +126,19 @@ fn align(mat: &Mat3x3, ax: &Axis) -> Axis {
     }
 }
 
-// TODO: robustize
-fn get_size_and_origin(axes: &(V3, V3, V3), plane_axes: &[Axis]) -> (V3, V3) {
-    let mut mins = [1_000_000.0;3];
-    let mut maxs = [0.0;3];
+// extent of the outer boundaries of faces
+fn get_size_and_origin(axes: &(V3, V3, V3), points: &[V3]) -> Result<(V3, V3), AnalysisError> {
+    if points.is_empty() {
+        return Err(AnalysisError::NoPoints);
+    }
+    let mut mins = [f64::INFINITY;3];
+    let mut maxs = [f64::NEG_INFINITY;3];
     let (x_ax, y_ax, z_ax) = axes;
-    for ax in plane_axes {
+    for p in points {
         let lengthes = [
-            ax.p.dot(x_ax),
-            ax.p.dot(y_ax),
-            ax.p.dot(z_ax),
+            p.dot(x_ax),
+            p.dot(y_ax),
+            p.dot(z_ax),
         ];
         for i in 0..lengthes.len() {
             mins[i] = if lengthes[i] < mins[i] { lengthes[i] } else { mins[i] };
@@ -64,26 +153,30 @@ fn get_size_and_origin(axes: &(V3, V3, V3), plane_axes: &[Axis]) -> (V3, V3) {
     let origin = V3([
         (mins[0] + maxs[0]) / 2.,
         (mins[1] + maxs[1]) / 2.,
-        0.0,
+        // from the end of the pipe, bodies placed in an assembly rarely start at z 0
+        mins[2],
     ]);
-    (size, origin)
+    Ok((size, origin))
 }
 
+// keys are compared as directions
 struct VecMap<V> {
     inner: Vec<(V3, V)>,
+    tol: Tolerance,
 }
 
 impl<V> VecMap<V> {
-    fn new() -> Self {
+    fn new(tol: &Tolerance) -> Self {
         VecMap {
             inner: Vec::new(),
+            tol: *tol,
         }
     }
 
     fn insert(&mut self, k: V3, v: V) {
         for i in 0..self.inner.len() {
             let (k_ref, _) = &self.inner[i];
-            if !k_ref.are_independent(&k) {
+            if !k_ref.are_independent(&k, &self.tol) {
                 self.inner[i] = (k, v);
                 return
             }
@@ -94,14 +187,14 @@ impl<V> VecMap<V> {
     fn get(&self, k: &V3) -> Option<&V> {
         for i in 0..self.inner.len() {
             let (k_ref, v) = &self.inner[i];
-            if !k_ref.are_independent(&k) {
-                return Some(&v)
+            if !k_ref.are_independent(k, &self.tol) {
+                return Some(v)
             }
         }
         None
     }
 
-    fn iter(&self) -> ::std::slice::Iter<(V3, V)> {
+    fn iter(&self) -> ::std::slice::Iter<'_, (V3, V)> {
         self.inner.iter()
     }
 }
@@ -110,8 +203,8 @@ impl<V> VecMap<V> {
 // 側面はそれぞれ4本ある
 // 押出方向次第ではこの仮定も成り立たない?（そんな事は無い気もする）
 // axes -> (x, y, z)
-fn get_axes <'a>(axes: &'a [&'a Axis]) -> (V3, V3, V3) {
-    let mut map = VecMap::new();
+fn get_axes <'a>(axes: &'a [&'a Axis], tol: &Tolerance) -> Result<(V3, V3, V3), AnalysisError> {
+    let mut map = VecMap::new(tol);
     for ax in axes {
         if let Some(cnt) = map.get(&ax.direction) {
             let new_cnt = *cnt + 1;
@@ -135,67 +228,558 @@ fn get_axes <'a>(axes: &'a [&'a Axis]) -> (V3, V3, V3) {
             y_ax = v.normalize();
         }
     }
-    (x_ax, y_ax, z_ax)
+    if x_ax == V3::default() || y_ax == V3::default() || z_ax == V3::default() {
+        return Err(AnalysisError::AxesNotFound);
+    }
+    Ok((x_ax, y_ax, z_ax))
 }
 
-fn cylinders_to_drills(orig: &V3, cylinders: &[(f64, Axis)]) -> Vec<Drill> {
-    if !cylinders.is_empty() {
-        let mut drills = Vec::new();
-        for cylinder in cylinders {
-            let p = cylinder.1.p.sub(orig);
-            let dir = &cylinder.1.direction;
-            let theta = dir.y().atan2(dir.x());
-            let slide = p.dot(&dir.cross(&V3([0.0, 0.0, 1.0])));
-            drills.push(Drill {
-                theta,
-                d: p.z(),
-                slide,
-            });
+// interval of the line c + t dir inside a rectangle of the section
+fn clip(c: &V3, dir: &V3, x: (f64, f64), y: (f64, f64)) -> Option<(f64, f64)> {
+    let mut t = (f64::NEG_INFINITY, f64::INFINITY);
+    for &((lo, hi), c, d) in &[(x, c.x(), dir.x()), (y, c.y(), dir.y())] {
+        if d.abs() < 1e-12 {
+            if c < lo || c > hi {
+                return None;
+            }
+        }
+        else {
+            let (a, b) = ((lo - c) / d, (hi - c) / d);
+            t = (t.0.max(a.min(b)), t.1.min(a.max(b)));
         }
-        drills
+    }
+    if t.0 < t.1 { Some(t) } else { None }
+}
+
+// parts of the line c + t dir in the walls in the order of t, the corners taken sharp
+fn wall_spans(section: &Section, c: &V3, dir: &V3) -> Vec<(f64, f64)> {
+    let (x, y) = (&section.x, &section.y);
+    let outer = match clip(c, dir, (x[0], x[3]), (y[0], y[3])) {
+        Some(outer) => outer,
+        None => return Vec::new(),
+    };
+    match clip(c, dir, (x[1], x[2]), (y[1], y[2])) {
+        Some((a, b)) => vec![(outer.0, a), (b, outer.1)],
+        None => vec![outer],
+    }
+}
+
+// a hole of one or more coaxial faces, relative to the center of the end of the pipe
+struct Bore {
+    r: f64,
+    p: V3,
+    direction: V3,
+    // extent along the direction
+    from: f64,
+    to: f64,
+}
+
+impl Bore {
+    // into the aligned coordinates relative to orig
+    fn aligned(self, mat: &Mat3x3, orig: &V3) -> Bore {
+        let direction = mat.prod_vec(&self.direction);
+        let shift = orig.dot(&direction);
+        Bore { r: self.r, p: mat.prod_vec(&self.p).sub(orig), direction, from: self.from - shift, to: self.to - shift }
+    }
+
+    // q within the radius of the axis, for a slot of the way to the other end, and within the extent
+    fn reaches(&self, other: Option<&Bore>, q: &V3, tol: &Tolerance) -> bool {
+        let along = q.dot(&self.direction);
+        let across = |v: V3| v.sub(&self.direction.scale(v.dot(&self.direction)));
+        let q = across(q.sub(&self.p));
+        let s = other.map(|b| across(b.p.sub(&self.p))).unwrap_or_default();
+        let t = if s.norm() > 0.0 { (q.dot(&s) / s.dot(&s)).clamp(0.0, 1.0) } else { 0.0 };
+        along > self.from - tol.distance && along < self.to + tol.distance
+            && q.sub(&s.scale(t)).norm() < self.r + tol.distance
+    }
+}
+
+// planes within the reach of a hole or a slot are its bottom or steps, not walls
+fn hole_features(planes: &mut Vec<(Axis, Vec<V3>)>, bores: &[Bore], pairs: &[(Bore, Bore)], orig: &V3, tol: &Tolerance) {
+    planes.retain(|(_, points)| {
+        let points = points.iter().map(|q| q.sub(orig)).collect::<Vec<V3>>();
+        let within = |bore: &Bore, other: Option<&Bore>| points.iter().all(|q| bore.reaches(other, q, tol));
+        points.is_empty()
+            || !(bores.iter().any(|bore| within(bore, None)) || pairs.iter().any(|(a, b)| within(a, Some(b))))
+    });
+}
+
+// the center of the end of the pipe in the aligned coordinates
+fn end_center(axes: &(V3, V3, V3), origin: &V3) -> V3 {
+    let (x, y, z) = axes;
+    x.scale(origin.x()).add(&y.scale(origin.y())).add(&z.scale(origin.z()))
+}
+
+// coaxial cylinders are merged, split in halves or on both walls
+fn bores(orig: &V3, cylinders: &[(f64, Axis, Vec<V3>)], tol: &Tolerance) -> Vec<Bore> {
+    let mut bores: Vec<Bore> = Vec::new();
+    for (r, ax, points) in cylinders {
+        let direction = ax.direction.normalize();
+        let p = ax.p.sub(orig);
+        let along = points.iter().map(|q| q.sub(orig).dot(&direction)).collect::<Vec<f64>>();
+        let from = along.iter().cloned().fold(f64::INFINITY, f64::min);
+        let to = along.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let coaxial = bores.iter_mut().find(|bore| {
+            (bore.r - r).abs() < tol.distance
+                && !bore.direction.are_independent(&direction, tol)
+                && p.sub(&bore.p).cross(&bore.direction).norm() < tol.distance
+        });
+        match coaxial {
+            Some(bore) => {
+                let (from, to) = if bore.direction.dot(&direction) > 0.0 { (from, to) } else { (-to, -from) };
+                bore.from = bore.from.min(from);
+                bore.to = bore.to.max(to);
+            },
+            None => bores.push(Bore { r: *r, p, direction, from, to }),
+        }
+    }
+    bores
+}
+
+// indices of the planes tangent to both bores between their axes if there are some on both sides
+fn slot_planes(a: &Bore, b: &Bore, planes: &[(Axis, Vec<V3>)], orig: &V3, tol: &Tolerance) -> Option<Vec<usize>> {
+    if (a.r - b.r).abs() >= tol.distance || a.direction.are_independent(&b.direction, tol) {
+        return None;
+    }
+    let s = b.p.sub(&a.p);
+    let s = s.sub(&a.direction.scale(s.dot(&a.direction)));
+    let length = s.norm();
+    if length < tol.distance {
+        return None;
+    }
+    let u = s.scale(1.0 / length);
+    let n = a.direction.cross(&u);
+    let (from, to) = if a.direction.dot(&b.direction) > 0.0 { (b.from, b.to) } else { (-b.to, -b.from) };
+    let (from, to) = (a.from.min(from), a.to.max(to));
+    let mut sides = (false, false);
+    let mut indices = Vec::new();
+    for (i, (ax, points)) in planes.iter().enumerate() {
+        let points = points.iter().map(|q| q.sub(orig).sub(&a.p)).collect::<Vec<V3>>();
+        let tangent = !ax.direction.are_independent(&n, tol) && !points.is_empty() && points.iter().all(|q| {
+            let (t, along) = (q.dot(&u), q.dot(&a.direction));
+            (q.dot(&n).abs() - a.r).abs() < tol.distance
+                && t > -tol.distance && t < length + tol.distance
+                && along > from - tol.distance && along < to + tol.distance
+        });
+        if tangent {
+            if points[0].dot(&n) > 0.0 { sides.0 = true } else { sides.1 = true }
+            indices.push(i);
+        }
+    }
+    if sides.0 && sides.1 { Some(indices) } else { None }
+}
+
+// slots are pairs of parallel bores of the same radius joined by planes tangent to both,
+// which are taken out of the planes as they are not walls
+fn slots(bores: Vec<Bore>, planes: &mut Vec<(Axis, Vec<V3>)>, orig: &V3, tol: &Tolerance) -> (Vec<Bore>, Vec<(Bore, Bore)>) {
+    let mut bores = bores.into_iter().map(Some).collect::<Vec<Option<Bore>>>();
+    let mut pairs = Vec::new();
+    for i in 0..bores.len() {
+        for j in i + 1..bores.len() {
+            let indices = match (&bores[i], &bores[j]) {
+                (Some(a), Some(b)) => slot_planes(a, b, planes, orig, tol),
+                _ => None,
+            };
+            if let Some(indices) = indices {
+                for index in indices.into_iter().rev() {
+                    planes.remove(index);
+                }
+                if let (Some(a), Some(b)) = (bores[i].take(), bores[j].take()) {
+                    pairs.push((a, b));
+                }
+                break;
+            }
+        }
+    }
+    (bores.into_iter().flatten().collect(), pairs)
+}
+
+// how a bore goes through the walls, drilled from the outer surface it starts at
+struct Passage {
+    direction: V3,
+    // of the center of the pipe on the axis
+    foot: V3,
+    hole: Hole,
+    top: f64,
+    bottom: f64,
+}
+
+fn passage(bore: &Bore, axes: &(V3, V3, V3), section: &Section, tol: &Tolerance) -> Result<Passage, &'static str> {
+    let (x, y, z) = axes;
+    // the section is measured along the axes
+    let across = |v: &V3| V3([v.dot(x), v.dot(y), 0.0]);
+    let foot = bore.p.sub(&bore.direction.scale(bore.p.dot(&bore.direction)));
+    if bore.direction.dot(z).abs() > tol.angle {
+        return Err("not across the pipe");
+    }
+    let spans = wall_spans(section, &across(&foot), &across(&bore.direction));
+    let (lo, hi) = match (spans.first(), spans.last()) {
+        (Some(first), Some(last)) => (first.0, last.1),
+        _ => return Err("off the walls"),
+    };
+    let (direction, from, spans) = if bore.to > hi - tol.distance {
+        (bore.direction.clone(), bore.from, spans)
+    }
+    else if bore.from < lo + tol.distance {
+        (bore.direction.scale(-1.0), -bore.to, spans.iter().rev().map(|(a, b)| (-b, -a)).collect())
     }
     else {
-        Vec::new()
+        return Err("inside the pipe");
+    };
+    let near = spans[spans.len() - 1];
+    let through = |span: &(f64, f64)| from < span.0 + tol.distance;
+    let (hole, bottom) = if spans.len() == 2 && through(&spans[0]) {
+        (Hole::BothWalls, spans[0].0)
+    }
+    else if through(&near) {
+        let hollow = if spans.len() == 2 { near.0 - spans[0].1 } else { f64::INFINITY };
+        (Hole::Through(hollow), near.0)
+    }
+    else {
+        (Hole::Blind(near.1 - from), from)
+    };
+    Ok(Passage { direction, foot, hole, top: near.1, bottom })
+}
+
+// both ends of a slot go through the walls alike
+fn holes(axes: &(V3, V3, V3), section: &Section, bores: Vec<Bore>, pairs: Vec<(Bore, Bore)>, tol: &Tolerance)
+    -> (Vec<Drill>, Vec<Slot>, Vec<String>)
+{
+    let (x, y, z) = axes;
+    let at = |bore: &Bore| {
+        let foot = bore.p.sub(&bore.direction.scale(bore.p.dot(&bore.direction)));
+        format!("({}, {}, {})", foot.dot(x), foot.dot(y), foot.dot(z))
+    };
+    let mut drills = Vec::new();
+    let mut ignored = Vec::new();
+    for bore in bores {
+        match passage(&bore, axes, section, tol) {
+            Ok(Passage { direction, foot, hole, top, bottom }) => drills.push(Drill {
+                r: bore.r,
+                theta: direction.y().atan2(direction.x()),
+                d: foot.dot(z),
+                slide: foot.dot(&direction.cross(z)),
+                hole,
+                top,
+                bottom,
+            }),
+            Err(reason) => ignored.push(format!("ignored hole {} at {}", reason, at(&bore))),
+        }
     }
+    let mut slots = Vec::new();
+    for (a, b) in pairs {
+        match (passage(&a, axes, section, tol), passage(&b, axes, section, tol)) {
+            (Ok(pa), Ok(pb)) if pa.direction.dot(&pb.direction) > 0.0
+                && (pa.top - pb.top).abs() < tol.distance && (pa.bottom - pb.bottom).abs() < tol.distance => {
+                let end = |foot: &V3| (foot.dot(z), foot.dot(&pa.direction.cross(z)));
+                slots.push(Slot {
+                    r: a.r,
+                    theta: pa.direction.y().atan2(pa.direction.x()),
+                    ends: [end(&pa.foot), end(&pb.foot)],
+                    hole: pa.hole,
+                    top: pa.top,
+                    bottom: pa.bottom,
+                });
+            },
+            (Err(reason), _) | (_, Err(reason)) => ignored.push(format!("ignored slot {} at {}", reason, at(&a))),
+            _ => ignored.push(format!("ignored slot of uneven ends at {}", at(&a))),
+        }
+    }
+    (drills, slots, ignored)
 }
 
+// B-spline faces are sampled on a grid to recognize planes and cylinders
+// within the tolerance of the model
+const BSPLINE_SAMPLES: usize = 8;
+
+fn recognize_b_spline(surface: &NurbsSurface, tol: &Tolerance) -> Option<FaceElement> {
+    let samples = surface.samples(BSPLINE_SAMPLES);
+    let points = samples.iter().map(|(p, _)| p).collect::<Vec<&V3>>();
+    let normals = samples.iter().filter_map(|(_, n)| n.as_ref()).collect::<Vec<&V3>>();
+    let n0 = *normals.first()?;
+    let p0 = points[0];
+    if normals.iter().all(|n| n.cross(n0).norm() < tol.angle)
+        && points.iter().all(|p| p.sub(p0).dot(n0).abs() < tol.distance) {
+        return Some(FaceElement::Plane(Axis {
+            p: p0.clone(),
+            direction: n0.clone(),
+            ref_direction: n0.perpendicular(),
+        }));
+    }
+    // the axis of a cylinder is perpendicular to all normals
+    let direction = normals
+        .iter()
+        .map(|n| n0.cross(n))
+        .max_by(|a, b| a.norm().partial_cmp(&b.norm()).unwrap_or(std::cmp::Ordering::Equal))?;
+    if direction.norm() < tol.angle {
+        return None;
+    }
+    let direction = direction.normalize();
+    if normals.iter().any(|n| n.dot(&direction).abs() > tol.angle) {
+        return None;
+    }
+    let e1 = direction.perpendicular();
+    let e2 = direction.cross(&e1);
+    let projected = points.iter().map(|p| (p.dot(&e1), p.dot(&e2))).collect::<Vec<(f64, f64)>>();
+    let ((cx, cy), r) = fit_circle(&projected)?;
+    if projected.iter().any(|(x, y)| (((x - cx).powi(2) + (y - cy).powi(2)).sqrt() - r).abs() > tol.distance) {
+        return None;
+    }
+    Some(FaceElement::Cylinder(r, Axis {
+        p: e1.scale(cx).add(&e2.scale(cy)).add(&direction.scale(p0.dot(&direction))),
+        direction,
+        ref_direction: e1,
+    }))
+}
+
+// B-spline faces are replaced with planes or cylinders if they are
+fn recognize(elem: &FaceElement, tol: &Tolerance) -> FaceElement {
+    match elem {
+        FaceElement::BSpline(surface) => recognize_b_spline(surface, tol).unwrap_or_else(|| elem.clone()),
+        _ => elem.clone(),
+    }
+}
+
+// countersinks, fillets and so on are left to the operator
+fn ignored_faces(r_mat: &Mat3x3, elems: &[FaceElement]) -> Vec<String> {
+    let pos = |ax: &Axis| {
+        let p = r_mat.prod_vec(&ax.p);
+        format!("({}, {}, {})", p.x(), p.y(), p.z())
+    };
+    elems.iter()
+        .filter_map(|elem| match elem {
+            FaceElement::Cone(r, semi_angle, ax) =>
+                Some(format!("ignored cone: r {} semi-angle {} deg at {}", r, semi_angle.to_degrees(), pos(ax))),
+            FaceElement::Sphere(r, ax) =>
+                Some(format!("ignored sphere: r {} at {}", r, pos(ax))),
+            FaceElement::Torus(major_r, minor_r, ax) =>
+                Some(format!("ignored torus: r {} / {} at {}", major_r, minor_r, pos(ax))),
+            FaceElement::BSpline(surface) => {
+                let ((u0, u1), (v0, v1)) = surface.domain();
+                let p = r_mat.prod_vec(&surface.eval((u0 + u1) / 2.0, (v0 + v1) / 2.0));
+                Some(format!("ignored B-spline face around ({}, {}, {})", p.x(), p.y(), p.z()))
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+// positions of the walls across an axis, each with the direction of its normal
+fn walls(mut positions: Vec<(f64, bool)>, name: &str, tol: &Tolerance) -> Result<[f64; 4], AnalysisError> {
+    positions.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    let mut distinct: Vec<(f64, bool)> = Vec::new();
+    for (at, outward) in positions {
+        match distinct.last() {
+            Some(&(last, last_outward)) if at - last < tol.distance => {
+                if outward != last_outward {
+                    return Err(AnalysisError::NotTube(format!("walls facing each other at {} across {}", at, name)));
+                }
+            },
+            _ => distinct.push((at, outward)),
+        }
+    }
+    // normals point out of the material
+    let walls = match distinct.as_slice() {
+        [(_, false), (_, true)] => return Err(AnalysisError::NotTube("no inner walls".to_owned())),
+        [(x0, false), (x1, true), (x2, false), (x3, true)] => [*x0, *x1, *x2, *x3],
+        _ => return Err(AnalysisError::NotTube(format!("walls at {} positions across {}", distinct.len(), name))),
+    };
+    if ((walls[1] - walls[0]) - (walls[3] - walls[2])).abs() > tol.distance {
+        return Err(AnalysisError::NotTube(format!(
+            "walls of {} and {} thick across {}", walls[1] - walls[0], walls[3] - walls[2], name)));
+    }
+    Ok(walls)
+}
+
+// planes and cylinders along the pipe (corner fillets) in the aligned coordinates
+fn section(planes: &[(Axis, Vec<V3>)], fillets: &[(f64, Axis)], axes: &(V3, V3, V3), size: &V3, origin: &V3, tol: &Tolerance)
+    -> Result<Section, AnalysisError>
+{
+    let (x, y, z) = axes;
+    let (z0, z1) = (origin.z(), origin.z() + size.z());
+    let mut across = [Vec::new(), Vec::new()];
+    for (ax, points) in planes {
+        let n = &ax.direction;
+        if !n.are_independent(z, tol) {
+            let at = ax.p.dot(z);
+            if (at - z0).abs() > tol.distance && (at - z1).abs() > tol.distance {
+                return Err(AnalysisError::NotTube(format!("plane across the pipe at {}", at - z0)));
+            }
+            continue;
+        }
+        let k = if !n.are_independent(x, tol) {
+            0
+        }
+        else if !n.are_independent(y, tol) {
+            1
+        }
+        else {
+            return Err(AnalysisError::NotTube(format!("oblique plane at ({}, {}, {})", ax.p.x(), ax.p.y(), ax.p.z())));
+        };
+        let along = points.iter().map(|p| p.dot(z));
+        let from = along.clone().fold(f64::INFINITY, f64::min);
+        let to = along.fold(f64::NEG_INFINITY, f64::max);
+        let axis = [x, y][k];
+        let at = ax.p.dot(axis) - [origin.x(), origin.y()][k];
+        if from > z0 + tol.distance || to < z1 - tol.distance {
+            return Err(AnalysisError::NotTube(format!("wall at {} across {} from {} to {}", at, ["x", "y"][k], from - z0, to - z0)));
+        }
+        across[k].push((at, n.dot(axis) > 0.0));
+    }
+    let [across_x, across_y] = across;
+    let xs = walls(across_x, "x", tol)?;
+    let ys = walls(across_y, "y", tol)?;
+    // fillets are tangent to the walls at the corners
+    let mut inner_corner_r: f64 = 0.0;
+    for (r, ax) in fillets {
+        let (cx, cy) = (ax.p.dot(x) - origin.x(), ax.p.dot(y) - origin.y());
+        let at = |walls: &[f64; 4], i: usize, c: f64| {
+            // the centre is off the wall on the side of the corner
+            let centre = if i < 2 { walls[i] + *r } else { walls[i] - *r };
+            (centre - c).abs() < tol.distance
+        };
+        let corner = |i: usize, j: usize| at(&xs, i, cx) && at(&ys, j, cy);
+        if corner(1, 1) || corner(1, 2) || corner(2, 1) || corner(2, 2) {
+            inner_corner_r = inner_corner_r.max(*r);
+        }
+        else if !(corner(0, 0) || corner(0, 3) || corner(3, 0) || corner(3, 3)) {
+            return Err(AnalysisError::NotTube(format!("cylinder along the pipe at ({}, {})", cx, cy)));
+        }
+    }
+    Ok(Section { x: xs, y: ys, inner_corner_r })
+}
+
+// pipe axes from the planes, each with the points of its boundary
+fn layout(planes: &[(Axis, Vec<V3>)], points: &[V3], cylinders: &[(f64, &Axis, Vec<V3>)], tol: &Tolerance)
+    -> Result<Proc, AnalysisError>
+{
+    // slots and hole features are found ahead, their planes would spoil the axes
+    let cylinders = cylinders.iter().map(|(r, ax, points)| (*r, (*ax).clone(), points.clone())).collect::<Vec<_>>();
+    let mut planes = planes.to_vec();
+    let (bores, pairs) = slots(bores(&V3::default(), &cylinders, tol), &mut planes, &V3::default(), tol);
+    hole_features(&mut planes, &bores, &pairs, &V3::default(), tol);
+    let plane_axes = planes.iter().map(|(ax, _)| ax).collect::<Vec<&Axis>>();
+    let (ax_x, ax_y, ax_z) = get_axes(&plane_axes, tol)?;
+    let r_mat = get_align_mat(&ax_z);
+    let ax_x = r_mat.prod_vec(&ax_x);
+    let ax_y = r_mat.prod_vec(&ax_y);
+    let ax_z = r_mat.prod_vec(&ax_z);
+    let axes = (ax_x, ax_y, ax_z);
+    let points = points.iter().map(|p| r_mat.prod_vec(p)).collect::<Vec<V3>>();
+    let (size, origin) = get_size_and_origin(&axes, points.as_slice())?;
+    let planes = planes
+        .iter()
+        .map(|(ax, points)| (align(&r_mat, ax), points.iter().map(|p| r_mat.prod_vec(p)).collect()))
+        .collect::<Vec<(Axis, Vec<V3>)>>();
+    let orig = end_center(&axes, &origin);
+    // cylinders along the pipe are rounded corners
+    let (fillets, bores): (Vec<_>, Vec<_>) = bores
+        .into_iter()
+        .map(|bore| bore.aligned(&r_mat, &orig))
+        .partition(|bore| !bore.direction.are_independent(&axes.2, tol));
+    let fillets = fillets
+        .into_iter()
+        .map(|bore| (bore.r, Axis { p: bore.p.add(&orig), ref_direction: bore.direction.perpendicular(), direction: bore.direction }))
+        .collect::<Vec<(f64, Axis)>>();
+    let pairs = pairs.into_iter().map(|(a, b)| (a.aligned(&r_mat, &orig), b.aligned(&r_mat, &orig))).collect();
+    let section = section(&planes, &fillets, &axes, &size, &origin, tol)?;
+    let (drills, slots, ignored) = holes(&axes, &section, bores, pairs, tol);
+    let count = |f: fn(&Hole) -> bool| drills.iter().filter(|drill| f(&drill.hole)).count();
+    let (tx, ty) = section.thickness();
+    let report = format!(
+        "size: ({}, {}, {})\norigin: ({}, {}, {})\nwall thickness: ({}, {})\ninner corner radius: {}\n\
+        holes: {} through one wall, {} through both walls, {} blind\nslots: {}",
+        size.x(), size.y(), size.z(), origin.x(), origin.y(), origin.z(), tx, ty, section.inner_corner_r,
+        count(|hole| matches!(hole, Hole::Through(_))), count(|hole| *hole == Hole::BothWalls),
+        count(|hole| matches!(hole, Hole::Blind(_))), slots.len());
+    let report = ignored.iter().fold(report, |report, line| report + "\n" + line);
+    Ok(Proc {
+        size,
+        center: origin.clone(),
+        drills,
+        slots,
+        section,
+        axes,
+        rotation: r_mat,
+        report,
+    })
+}
+
+// FACE_OUTER_BOUND is optional, without one all bounds are taken
+fn outer_points(face: &AdvancedFace) -> Vec<V3> {
+    let outer = face.bounds.iter().any(|bound| bound.outer);
+    face.bounds
+        .iter()
+        .filter(|bound| bound.outer || !outer)
+        .flat_map(|bound| bound.points())
+        .collect()
+}
 
 impl Proc {
-    pub fn new(faces: &[AdvancedFace]) -> Self {
-        let plane_axes=
-            faces.iter()
+    pub fn new(faces: &[AdvancedFace], tol: &Tolerance) -> Result<Self, AnalysisError> {
+        let elems = faces.iter().map(|face| recognize(&face.elem, tol)).collect::<Vec<FaceElement>>();
+        let planes=
+            elems.iter()
+            .zip(faces)
             .filter_map(
-                |face| match &face.elem {
-                    FaceElement::Plane(ax) => Some(ax),
-                    FaceElement::Cylinder(_, _) => None
+                |(elem, face)| match elem {
+                    FaceElement::Plane(ax) => {
+                        // the normal out of the material
+                        let direction = if face.flag { ax.direction.clone() } else { ax.direction.scale(-1.0) };
+                        Some((Axis { direction, ..ax.clone() }, outer_points(face)))
+                    },
+                    _ => None
                 })
-            .collect::<Vec<&Axis>>();
-        let (ax_x, ax_y, ax_z) = get_axes(plane_axes.as_slice());
-        let r_mat = get_align_mat(&ax_z);
-        let ax_x = r_mat.prod_vec(&ax_x);
-        let ax_y = r_mat.prod_vec(&ax_y);
-        let ax_z = r_mat.prod_vec(&ax_z);
-        let axes = (ax_x, ax_y, ax_z);
-        let plane_axes =
-            plane_axes.iter()
-            .map(|ax| align(&r_mat, &ax))
-            .collect::<Vec<Axis>>();
-        let (size, origin) = get_size_and_origin(&axes, plane_axes.as_slice());
-        let cylinders : Vec<(f64, Axis)> =
-            faces.iter()
+            .collect::<Vec<(Axis, Vec<V3>)>>();
+        let points = faces.iter().flat_map(outer_points).collect::<Vec<V3>>();
+        let cylinders =
+            elems.iter()
+            .zip(faces)
             .filter_map(
-                |face| match &face.elem {
-                    FaceElement::Cylinder(r, ax) => Some((*r, align(&r_mat, &ax))),
+                |(elem, face)| match elem {
+                    FaceElement::Cylinder(r, ax) => {
+                        let points = face.bounds.iter().flat_map(|bound| bound.points());
+                        Some((*r, ax, points.collect()))
+                    },
                     _ => None,
                 })
-            .collect();
-        let report = format!("size: ({}, {}, {})\norigin: ({}, {}, {})", size.x(), size.y(), size.z(), origin.x(), origin.y(), origin.z());
-        Proc {
-            size,
-            center: origin.clone(),
-            drills: cylinders_to_drills(&origin, cylinders.as_slice()),
-            report,
+            .collect::<Vec<(f64, &Axis, Vec<V3>)>>();
+        let mut proc = layout(&planes, &points, &cylinders, tol)?;
+        for line in ignored_faces(&proc.rotation, &elems) {
+            proc.report.push('\n');
+            proc.report.push_str(&line);
+        }
+        Ok(proc)
+    }
+
+    // planes and cylinders recognized in the triangles
+    pub fn from_mesh(mesh: &Mesh, tol: &Tolerance) -> Result<Self, AnalysisError> {
+        let regions = mesh.segment(tol);
+        let planes = regions
+            .iter()
+            .filter_map(|region| match &region.surface {
+                Surface::Plane(ax) => Some((ax.clone(), mesh.points(&region.triangles))),
+                _ => None,
+            })
+            .collect::<Vec<(Axis, Vec<V3>)>>();
+        let cylinders = regions
+            .iter()
+            .filter_map(|region| match &region.surface {
+                Surface::Cylinder(r, ax) => Some((*r, ax, mesh.points(&region.triangles))),
+                _ => None,
+            })
+            .collect::<Vec<(f64, &Axis, Vec<V3>)>>();
+        let mut proc = layout(&planes, &mesh.vertices, &cylinders, tol)?;
+        proc.report.push_str(&format!("\ntriangles: {}", mesh.triangles.len()));
+        for region in &regions {
+            if let Surface::Other(p) = &region.surface {
+                let p = proc.rotation.prod_vec(p);
+                proc.report.push_str(&format!(
+                    "\nignored curved region of {} triangles around ({}, {}, {})",
+                    region.triangles.len(), p.x(), p.y(), p.z()));
+            }
         }
+        Ok(proc)
     }
 }
 
@@ -203,12 +787,346 @@ impl Proc {
 mod test {
     use super::*;
 
+    // a plane through the corners of a box
+    fn plane(n: [f64; 3], corners: [[f64; 3]; 2]) -> (Axis, Vec<V3>) {
+        let [lo, hi] = corners;
+        let points = (0..8)
+            .map(|i| V3([0, 1, 2].map(|k| if i >> k & 1 == 0 { lo[k] } else { hi[k] })))
+            .collect::<Vec<V3>>();
+        (Axis { p: V3(lo), direction: V3(n), ref_direction: V3(n).perpendicular() }, points)
+    }
+
+    // 10 x 30 x 100 along z with walls 1.5 thick, the end at z 0
+    fn tube() -> Vec<(Axis, Vec<V3>)> {
+        vec![
+            plane([0.0, 0.0, -1.0], [[-5.0, -15.0, 0.0], [5.0, 15.0, 0.0]]),
+            plane([0.0, 0.0, 1.0], [[-5.0, -15.0, 100.0], [5.0, 15.0, 100.0]]),
+            plane([-1.0, 0.0, 0.0], [[-5.0, -15.0, 0.0], [-5.0, 15.0, 100.0]]),
+            plane([1.0, 0.0, 0.0], [[5.0, -15.0, 0.0], [5.0, 15.0, 100.0]]),
+            plane([0.0, -1.0, 0.0], [[-5.0, -15.0, 0.0], [5.0, -15.0, 100.0]]),
+            plane([0.0, 1.0, 0.0], [[-5.0, 15.0, 0.0], [5.0, 15.0, 100.0]]),
+            plane([1.0, 0.0, 0.0], [[-3.5, -13.5, 0.0], [-3.5, 13.5, 100.0]]),
+            plane([-1.0, 0.0, 0.0], [[3.5, -13.5, 0.0], [3.5, 13.5, 100.0]]),
+            plane([0.0, 1.0, 0.0], [[-3.5, -13.5, 0.0], [3.5, -13.5, 100.0]]),
+            plane([0.0, -1.0, 0.0], [[-3.5, 13.5, 0.0], [3.5, 13.5, 100.0]]),
+        ]
+    }
+
+    // a cylinder from and to along its direction
+    fn cylinder(r: f64, direction: [f64; 3], p: [f64; 3], from: f64, to: f64) -> (f64, Axis, Vec<V3>) {
+        let (direction, p) = (V3(direction), V3(p));
+        let points = vec![p.add(&direction.scale(from)), p.add(&direction.scale(to))];
+        (r, Axis { p, ref_direction: direction.perpendicular(), direction }, points)
+    }
+
+    // the section and the axes of tube()
+    fn tube_section() -> (Section, (V3, V3, V3)) {
+        let section = Section { x: [-5.0, -3.5, 3.5, 5.0], y: [-15.0, -13.5, 13.5, 15.0], inner_corner_r: 0.0 };
+        (section, (V3([1.0, 0.0, 0.0]), V3([0.0, 1.0, 0.0]), V3([0.0, 0.0, 1.0])))
+    }
+
+    fn layout_of(planes: &[(Axis, Vec<V3>)], cylinders: &[(f64, Axis, Vec<V3>)]) -> Result<Proc, AnalysisError> {
+        let points = planes.iter().map(|(_, points)| points)
+            .chain(cylinders.iter().map(|(_, _, points)| points))
+            .flatten()
+            .cloned()
+            .collect::<Vec<V3>>();
+        let cylinders = cylinders.iter().map(|(r, ax, points)| (*r, ax, points.clone())).collect::<Vec<_>>();
+        layout(planes, &points, &cylinders, &Tolerance::default())
+    }
+
     #[test]
     fn test_vecmap() {
-        let mut map = VecMap::new();
+        let mut map = VecMap::new(&Tolerance::default());
         map.insert(V3([1.0, 0.0, 0.0]), 1);
         map.insert(V3([0.0, 0.0, 1.0]), 2);
         assert_eq!(map.get(&V3([3.0, 0.0, 0.0])), Some(&1));
         assert_eq!(map.get(&V3([1.0, 0.0, 1.0])), None);
     }
+
+    #[test]
+    fn test_recognize_b_spline() {
+        // quarter of a cylinder with r 5 along z as a rational quadratic x linear surface
+        let tol = Tolerance::default();
+        let w = std::f64::consts::FRAC_1_SQRT_2;
+        let row = |x: f64, y: f64| vec![V3([x, y, -1.0]), V3([x, y, 3.0])];
+        let cylinder = NurbsSurface::new(
+            2, 1,
+            vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0],
+            vec![0.0, 0.0, 1.0, 1.0],
+            vec![row(15.0, 0.0), row(15.0, 5.0), row(10.0, 5.0)],
+            vec![vec![1.0, 1.0], vec![w, w], vec![1.0, 1.0]]).unwrap();
+        match recognize_b_spline(&cylinder, &tol) {
+            Some(FaceElement::Cylinder(r, ax)) => {
+                assert!((r - 5.0).abs() < 1e-9);
+                assert!(ax.direction.cross(&V3([0.0, 0.0, 1.0])).norm() < 1e-6);
+                assert!((ax.p.x() - 10.0).abs() < 1e-9 && ax.p.y().abs() < 1e-9);
+            },
+            elem => panic!("{:?}", elem),
+        }
+        let plane = NurbsSurface::new(
+            1, 1,
+            vec![0.0, 0.0, 1.0, 1.0],
+            vec![0.0, 0.0, 1.0, 1.0],
+            vec![row(0.0, 0.0), row(0.0, 5.0)],
+            vec![vec![1.0; 2]; 2]).unwrap();
+        match recognize_b_spline(&plane, &tol) {
+            Some(FaceElement::Plane(ax)) => assert!(ax.direction.cross(&V3([1.0, 0.0, 0.0])).norm() < 1e-6),
+            elem => panic!("{:?}", elem),
+        }
+        // saddle
+        let saddle = NurbsSurface::new(
+            1, 1,
+            vec![0.0, 0.0, 1.0, 1.0],
+            vec![0.0, 0.0, 1.0, 1.0],
+            vec![
+                vec![V3([0.0, 0.0, 0.0]), V3([0.0, 1.0, 1.0])],
+                vec![V3([1.0, 0.0, 1.0]), V3([1.0, 1.0, 0.0])],
+            ],
+            vec![vec![1.0; 2]; 2]).unwrap();
+        assert!(recognize_b_spline(&saddle, &tol).is_none());
+    }
+
+    #[test]
+    fn test_get_axes() {
+        let ax = |x, y, z| Axis {
+            p: V3::default(),
+            direction: V3([x, y, z]),
+            ref_direction: V3([1.0, 0.0, 0.0]),
+        };
+        let planes = [
+            ax(0.0, 0.0, 1.0), ax(0.0, 0.0, -1.0),
+            ax(1.0, 0.0, 0.0), ax(-1.0, 0.0, 0.0), ax(1.0, 0.0, 0.0), ax(-1.0, 0.0, 0.0),
+            ax(0.0, 1.0, 0.0), ax(0.0, -1.0, 0.0), ax(0.0, 1.0, 0.0), ax(0.0, -1.0, 0.0),
+        ];
+        let tol = Tolerance::default();
+        let (_, _, z) = get_axes(&planes.iter().collect::<Vec<&Axis>>(), &tol).unwrap();
+        assert!(!z.are_independent(&V3([0.0, 0.0, 1.0]), &tol));
+        // side faces slightly off parallel, as exported with a loose uncertainty
+        let planes = [
+            ax(0.0, 0.0, 1.0), ax(0.0, 0.0, -1.0),
+            ax(1.0, 0.0, 0.0), ax(-1.0, 1e-7, 0.0), ax(1.0, 0.0, 1e-7), ax(-1.0, 0.0, 0.0),
+            ax(0.0, 1.0, 0.0), ax(1e-7, -1.0, 0.0), ax(0.0, 1.0, 0.0), ax(0.0, -1.0, 1e-7),
+        ];
+        let (x, y, z) = get_axes(&planes.iter().collect::<Vec<&Axis>>(), &Tolerance::from_distance(1e-2)).unwrap();
+        assert!(!z.are_independent(&V3([0.0, 0.0, 1.0]), &tol));
+        assert!(x.dot(&z).abs() < 1e-6 && y.dot(&z).abs() < 1e-6 && x.dot(&y).abs() < 1e-6);
+        let planes = [ax(0.0, 0.0, 1.0), ax(0.0, 0.0, -1.0)];
+        assert_eq!(get_axes(&planes.iter().collect::<Vec<&Axis>>(), &tol), Err(AnalysisError::AxesNotFound));
+    }
+
+    #[test]
+    fn test_section() {
+        // 10 x 30 x 100 along z with walls 1.5 thick
+        let plane = |n: [f64; 3], p: [f64; 3], from: f64, to: f64| {
+            let p = V3(p);
+            let points = vec![p.add(&V3([0.0, 0.0, from - p.z()])), p.add(&V3([0.0, 0.0, to - p.z()]))];
+            (Axis { p, direction: V3(n), ref_direction: V3(n).perpendicular() }, points)
+        };
+        let tube = |inner: bool| {
+            let mut planes = vec![
+                plane([0.0, 0.0, -1.0], [0.0, 0.0, 0.0], 0.0, 0.0),
+                plane([0.0, 0.0, 1.0], [0.0, 0.0, 100.0], 100.0, 100.0),
+                plane([-1.0, 0.0, 0.0], [-5.0, 0.0, 0.0], 0.0, 100.0),
+                plane([1.0, 0.0, 0.0], [5.0, 0.0, 0.0], 0.0, 100.0),
+                plane([0.0, -1.0, 0.0], [0.0, -15.0, 0.0], 0.0, 100.0),
+                plane([0.0, 1.0, 0.0], [0.0, 15.0, 0.0], 0.0, 100.0),
+            ];
+            if inner {
+                planes.extend(vec![
+                    plane([1.0, 0.0, 0.0], [-3.5, 0.0, 0.0], 0.0, 100.0),
+                    plane([-1.0, 0.0, 0.0], [3.5, 0.0, 0.0], 0.0, 100.0),
+                    plane([0.0, 1.0, 0.0], [0.0, -13.5, 0.0], 0.0, 100.0),
+                    plane([0.0, -1.0, 0.0], [0.0, 13.5, 0.0], 0.0, 100.0),
+                ]);
+            }
+            planes
+        };
+        let fillet = |r: f64, x: f64, y: f64| (r, Axis {
+            p: V3([x, y, 0.0]),
+            direction: V3([0.0, 0.0, 1.0]),
+            ref_direction: V3([1.0, 0.0, 0.0]),
+        });
+        let axes = (V3([1.0, 0.0, 0.0]), V3([0.0, 1.0, 0.0]), V3([0.0, 0.0, 1.0]));
+        let (size, origin) = (V3([10.0, 30.0, 100.0]), V3::default());
+        let tol = Tolerance::default();
+        let fillets = vec![fillet(0.5, 3.0, 13.0), fillet(0.5, -3.0, -13.0), fillet(2.0, 3.0, -13.0)];
+        let section = section(&tube(true), &fillets, &axes, &size, &origin, &tol).unwrap();
+        assert_eq!(section, Section { x: [-5.0, -3.5, 3.5, 5.0], y: [-15.0, -13.5, 13.5, 15.0], inner_corner_r: 0.5 });
+        assert_eq!(section.thickness(), (1.5, 1.5));
+
+        let not_tube = |planes: &[(Axis, Vec<V3>)], fillets: &[(f64, Axis)]| {
+            match super::section(planes, fillets, &axes, &size, &origin, &tol) {
+                Err(AnalysisError::NotTube(reason)) => reason,
+                section => panic!("{:?}", section),
+            }
+        };
+        assert_eq!(not_tube(&tube(false), &[]), "no inner walls");
+        assert_eq!(not_tube(&tube(true), &[fillet(1.0, 0.0, 0.0)]), "cylinder along the pipe at (0, 0)");
+        let mut planes = tube(true);
+        planes.push(plane([0.0, 0.0, 1.0], [0.0, 0.0, 50.0], 50.0, 50.0));
+        assert_eq!(not_tube(&planes, &[]), "plane across the pipe at 50");
+        let mut planes = tube(true);
+        planes[3] = plane([1.0, 0.0, 0.0], [5.0, 0.0, 0.0], 0.0, 60.0);
+        assert_eq!(not_tube(&planes, &[]), "wall at 5 across x from 0 to 60");
+        let mut planes = tube(true);
+        planes[6] = plane([1.0, 0.0, 0.0], [-3.0, 0.0, 0.0], 0.0, 100.0);
+        assert_eq!(not_tube(&planes, &[]), "walls of 2 and 1.5 thick across x");
+        let mut planes = tube(true);
+        planes.push(plane([1.0, 0.0, 0.0], [0.0, 0.0, 0.0], 0.0, 100.0));
+        assert_eq!(not_tube(&planes, &[]), "walls at 5 positions across x");
+        let mut planes = tube(true);
+        planes.push(plane([1.0, 1.0, 0.0], [4.0, 14.0, 0.0], 0.0, 100.0));
+        assert_eq!(not_tube(&planes, &[]), "oblique plane at (4, 14, 0)");
+    }
+
+    #[test]
+    fn test_layout() {
+        // a flat-bottomed blind hole in the wall at +x
+        let mut planes = tube();
+        let bottom = [[4.0, 1.0, 50.0], [4.0, -1.0, 50.0], [4.0, 0.0, 49.0], [4.0, 0.0, 51.0]].map(V3).to_vec();
+        planes.push((Axis { p: V3([4.0, 0.0, 50.0]), direction: V3([1.0, 0.0, 0.0]), ref_direction: V3([0.0, 1.0, 0.0]) }, bottom));
+        let cylinders = [cylinder(1.0, [1.0, 0.0, 0.0], [0.0, 0.0, 50.0], 4.0, 5.0)];
+        let proc = layout_of(&planes, &cylinders).unwrap();
+        assert_eq!(proc.size, V3([10.0, 30.0, 100.0]));
+        let holes = proc.drills.iter().map(|drill| (drill.d, drill.hole.clone())).collect::<Vec<(f64, Hole)>>();
+        assert_eq!(holes, vec![(50.0, Hole::Blind(1.0))]);
+        // bodies placed in an assembly are measured from their own end
+        let up = V3([0.0, 0.0, 200.0]);
+        let planes = planes.into_iter()
+            .map(|(ax, points)| (Axis { p: ax.p.add(&up), ..ax }, points.iter().map(|p| p.add(&up)).collect()))
+            .collect::<Vec<(Axis, Vec<V3>)>>();
+        let cylinders = [cylinder(1.0, [1.0, 0.0, 0.0], [0.0, 0.0, 250.0], 4.0, 5.0)];
+        let proc = layout_of(&planes, &cylinders).unwrap();
+        assert_eq!(proc.drills.iter().map(|drill| drill.d).collect::<Vec<f64>>(), vec![50.0]);
+        // faces without boundaries cannot be measured
+        let planes = tube().into_iter().map(|(ax, _)| (ax, Vec::new())).collect::<Vec<(Axis, Vec<V3>)>>();
+        assert_eq!(layout_of(&planes, &[]).err(), Some(AnalysisError::NoPoints));
+    }
+
+    #[test]
+    fn test_slot_across() {
+        // across the pipe in the wall at +x, the tangent planes face along the pipe
+        let mut planes = tube();
+        planes.push(plane([0.0, 0.0, 1.0], [[3.5, -5.0, 48.0], [5.0, 5.0, 48.0]]));
+        planes.push(plane([0.0, 0.0, -1.0], [[3.5, -5.0, 52.0], [5.0, 5.0, 52.0]]));
+        let cylinders = [
+            cylinder(2.0, [1.0, 0.0, 0.0], [0.0, -5.0, 50.0], 3.5, 5.0),
+            cylinder(2.0, [1.0, 0.0, 0.0], [0.0, 5.0, 50.0], 3.5, 5.0),
+        ];
+        let proc = layout_of(&planes, &cylinders).unwrap();
+        assert_eq!(proc.axes.2, V3([0.0, 0.0, 1.0]));
+        assert!(proc.drills.is_empty());
+        let slot = &proc.slots[0];
+        assert_eq!((slot.r, slot.ends), (2.0, [(50.0, 5.0), (50.0, -5.0)]));
+        assert_eq!((slot.hole.clone(), slot.top, slot.bottom), (Hole::Through(7.0), 5.0, 3.5));
+    }
+
+    #[test]
+    fn test_holes() {
+        let (section, axes) = tube_section();
+        let tol = Tolerance::default();
+        let cylinders = [
+            // on both walls, one in halves
+            cylinder(1.0, [1.0, 0.0, 0.0], [0.0, 2.0, 50.0], 3.5, 5.0),
+            cylinder(1.0, [-1.0, 0.0, 0.0], [0.0, 2.0, 50.0], 3.5, 5.0),
+            cylinder(1.0, [-1.0, 0.0, 0.0], [10.0, 2.0, 50.0], 13.5, 15.0),
+            // from -x
+            cylinder(1.0, [1.0, 0.0, 0.0], [0.0, 0.0, 100.0], -5.0, -3.5),
+            cylinder(1.0, [0.0, 1.0, 0.0], [0.0, 0.0, 150.0], 14.0, 15.0),
+            cylinder(1.0, [0.0, 1.0, 0.0], [0.0, 0.0, 200.0], 12.0, 13.5),
+            cylinder(1.0, [1.0, 0.0, 1.0], [0.0, 0.0, 250.0], 0.0, 5.0),
+        ];
+        let (drills, _, ignored) = holes(&axes, &section, bores(&V3::default(), &cylinders, &tol), Vec::new(), &tol);
+        let holes = drills
+            .iter()
+            .map(|drill| (drill.d, drill.hole.clone(), drill.top, drill.bottom))
+            .collect::<Vec<(f64, Hole, f64, f64)>>();
+        assert_eq!(holes, vec![
+            (50.0, Hole::BothWalls, 5.0, -5.0),
+            (100.0, Hole::Through(7.0), 5.0, 3.5),
+            (150.0, Hole::Blind(1.0), 15.0, 14.0),
+        ]);
+        assert!((drills[0].theta - 0.0).abs() < 1e-9 && (drills[0].slide + 2.0).abs() < 1e-9);
+        assert!((drills[1].theta.abs() - std::f64::consts::PI).abs() < 1e-9);
+        assert_eq!(ignored[0], "ignored hole inside the pipe at (0, 0, 200)");
+        assert!(ignored[1].starts_with("ignored hole not across the pipe"));
+    }
+
+    #[test]
+    fn test_slots() {
+        let (section, axes) = tube_section();
+        let tol = Tolerance::default();
+        let cylinders = [
+            // 20 long along the pipe in the wall at +x
+            cylinder(2.0, [1.0, 0.0, 0.0], [0.0, 0.0, 40.0], 3.5, 5.0),
+            cylinder(2.0, [1.0, 0.0, 0.0], [0.0, 0.0, 60.0], 3.5, 5.0),
+            // holes alone
+            cylinder(2.0, [1.0, 0.0, 0.0], [0.0, 0.0, 100.0], 3.5, 5.0),
+            cylinder(2.0, [1.0, 0.0, 0.0], [0.0, 0.0, 120.0], 3.5, 5.0),
+            // 30 long through both walls across y
+            cylinder(2.0, [0.0, 1.0, 0.0], [0.0, 0.0, 200.0], 13.5, 15.0),
+            cylinder(2.0, [0.0, -1.0, 0.0], [0.0, 0.0, 200.0], 13.5, 15.0),
+            cylinder(2.0, [0.0, 1.0, 0.0], [0.0, 0.0, 230.0], 13.5, 15.0),
+            cylinder(2.0, [0.0, 1.0, 0.0], [0.0, 0.0, 230.0], -15.0, -13.5),
+        ];
+        let mut planes = vec![
+            plane([1.0, 0.0, 0.0], [[5.0, -15.0, 0.0], [5.0, 15.0, 300.0]]),
+            plane([0.0, 1.0, 0.0], [[3.5, 2.0, 40.0], [5.0, 2.0, 60.0]]),
+            plane([0.0, -1.0, 0.0], [[3.5, -2.0, 40.0], [5.0, -2.0, 60.0]]),
+            plane([1.0, 0.0, 0.0], [[2.0, 13.5, 200.0], [2.0, 15.0, 230.0]]),
+            plane([-1.0, 0.0, 0.0], [[-2.0, 13.5, 200.0], [-2.0, 15.0, 230.0]]),
+            plane([1.0, 0.0, 0.0], [[2.0, -15.0, 200.0], [2.0, -13.5, 230.0]]),
+            plane([-1.0, 0.0, 0.0], [[-2.0, -15.0, 200.0], [-2.0, -13.5, 230.0]]),
+        ];
+        let (bores, pairs) = slots(bores(&V3::default(), &cylinders, &tol), &mut planes, &V3::default(), &tol);
+        assert_eq!((bores.len(), pairs.len(), planes.len()), (2, 2, 1));
+        let (drills, slots, ignored) = holes(&axes, &section, bores, pairs, &tol);
+        assert!(ignored.is_empty());
+        assert_eq!(drills.iter().map(|drill| drill.d).collect::<Vec<f64>>(), vec![100.0, 120.0]);
+        let slots = slots
+            .iter()
+            .map(|slot| (slot.ends, slot.hole.clone(), slot.top, slot.bottom))
+            .collect::<Vec<([(f64, f64); 2], Hole, f64, f64)>>();
+        assert_eq!(slots, vec![
+            ([(40.0, 0.0), (60.0, 0.0)], Hole::Through(7.0), 5.0, 3.5),
+            ([(200.0, 0.0), (230.0, 0.0)], Hole::BothWalls, 15.0, -15.0),
+        ]);
+    }
+
+    #[test]
+    fn test_from_mesh() {
+        // the same part in centimetres
+        let stl = super::super::stl::read(&std::fs::read("example.stl").unwrap()).unwrap();
+        let triangles = stl.triangles
+            .iter()
+            .map(|t| [t[0].scale(10.0), t[1].scale(10.0), t[2].scale(10.0)])
+            .collect::<Vec<[V3; 3]>>();
+        let proc = Proc::from_mesh(&Mesh::new(&triangles), &super::super::mesh::tolerance(None)).unwrap();
+        assert!(proc.size.sub(&V3([10.0, 30.0, 600.0])).norm() < 1e-4);
+        let (tx, ty) = proc.section.thickness();
+        assert!((tx - 1.5).abs() < 1e-4 && (ty - 1.5).abs() < 1e-4);
+        let step = std::fs::read_to_string("example.STEP").unwrap();
+        let (_, bodies) = super::super::parser::parse(&step, &|_| Err(String::new()), None).unwrap();
+        let expected = Proc::new(&bodies[0].faces, &Tolerance::default()).unwrap();
+        // the pipe is found the other way round
+        let positions = |drills: &[Drill], flip: bool| {
+            let mut ds = drills
+                .iter()
+                .map(|drill| (if flip { 600.0 - drill.d } else { drill.d }, drill.slide.abs()))
+                .map(|(d, slide)| ((d * 1000.0).round() as i64, (slide * 1000.0).round() as i64))
+                .collect::<Vec<(i64, i64)>>();
+            ds.sort_unstable();
+            ds
+        };
+        assert_eq!(positions(&proc.drills, true), positions(&expected.drills, false));
+        assert!(proc.drills.iter().chain(&expected.drills).all(|drill| drill.hole == Hole::BothWalls));
+        // every loop written as FACE_BOUND
+        let (_, bodies) = super::super::parser::parse(&step.replace("FACE_OUTER_BOUND(", "FACE_BOUND("), &|_| Err(String::new()), None).unwrap();
+        let plain = Proc::new(&bodies[0].faces, &Tolerance::default()).unwrap();
+        assert_eq!((plain.size, plain.drills.len()), (expected.size.clone(), 14));
+        assert_eq!(proc.drills.len(), 14);
+        let err = Proc::from_mesh(&Mesh::new(&triangles[..10]), &super::super::mesh::tolerance(None));
+        assert_eq!(err.err(), Some(AnalysisError::AxesNotFound));
+    }
 }
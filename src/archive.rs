@@ -0,0 +1,204 @@
+use super::reference;
+use flate2::read::GzDecoder;
+use std::io::{Cursor, Read};
+
+// Compressed STEP files.
+// .stpZ files are gzipped STEP files, and ISO 10303-21 zip archives hold the root
+// file with the files it refers to. The root is the STEP member which no other refers to
+// unless chosen. The XML form of ISO 10303-28 (.stpx) is recognized but not read.
+
+#[derive(Debug, PartialEq)]
+pub enum ArchiveError {
+    Gzip(String),
+    Zip(String),
+    // the archive has no STEP member (of the name)
+    NoStep(Option<String>),
+    // members none of the others refers to, one has to be chosen
+    Roots(Vec<String>),
+    // ISO 10303-28 XML
+    Xml(String),
+    NotUtf8(String),
+    // the decompressed content of the input or the member is over the limit
+    TooLarge(String),
+    // a gzipped file in a gzipped file
+    Nested,
+}
+
+// contents of the input, files other than the root are found by name
+#[derive(Debug, PartialEq)]
+pub struct Contents {
+    pub text: String,
+    pub members: Vec<(String, String)>,
+}
+
+impl Contents {
+    // name as written in a reference, relative to the root of the archive
+    pub fn member(&self, name: &str) -> Option<&String> {
+        let name = name.trim_start_matches("./");
+        self.members.iter().find(|(n, _)| n == name).map(|(_, text)| text)
+    }
+}
+
+// decompressed bytes at most, in total over the members of a zip archive
+const MAX_SIZE: u64 = 1 << 30;
+
+const GZIP: &[u8] = &[0x1f, 0x8b];
+const ZIP: &[u8] = b"PK\x03\x04";
+
+fn is_step(text: &str) -> bool {
+    text.trim_start_matches('\u{feff}').trim_start().starts_with("ISO-10303-21")
+}
+
+fn is_xml(text: &str) -> bool {
+    text.trim_start_matches('\u{feff}').trim_start().starts_with("<?xml")
+}
+
+fn text(bytes: Vec<u8>, name: &str) -> Result<String, ArchiveError> {
+    let text = String::from_utf8(bytes).map_err(|_| ArchiveError::NotUtf8(name.to_owned()))?;
+    if is_xml(&text) {
+        return Err(ArchiveError::Xml(name.to_owned()));
+    }
+    Ok(text)
+}
+
+// None when there are more than limit bytes
+fn read_at_most(reader: impl Read, limit: u64) -> std::io::Result<Option<Vec<u8>>> {
+    let mut buf = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut buf)?;
+    Ok(if buf.len() as u64 > limit { None } else { Some(buf) })
+}
+
+fn unzip(bytes: &[u8], member: Option<&str>, limit: u64) -> Result<Contents, ArchiveError> {
+    let zip_error = |e: zip::result::ZipError| ArchiveError::Zip(e.to_string());
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(zip_error)?;
+    let mut members = Vec::new();
+    let mut xml = None;
+    let mut left = limit;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(zip_error)?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.name().to_owned();
+        let buf = read_at_most(&mut file, left)
+            .map_err(|e| ArchiveError::Zip(format!("{}: {}", name, e)))?
+            .ok_or_else(|| ArchiveError::TooLarge(name.clone()))?;
+        left -= buf.len() as u64;
+        // other files (e.g. documentation) are not needed
+        if let Ok(text) = String::from_utf8(buf) {
+            if is_step(&text) {
+                members.push((name, text));
+            }
+            else if is_xml(&text) && xml.is_none() {
+                xml = Some(name);
+            }
+        }
+    }
+    if let (true, Some(name)) = (members.is_empty(), xml) {
+        return Err(ArchiveError::Xml(name));
+    }
+    let root = match member {
+        Some(m) => members.iter().position(|(name, _)| name == m),
+        None => {
+            let referred = members
+                .iter()
+                .flat_map(|(name, text)| reference::referred_files(text, name))
+                .collect::<Vec<String>>();
+            let roots = (0..members.len()).filter(|i| !referred.contains(&members[*i].0)).collect::<Vec<usize>>();
+            if roots.len() > 1 {
+                return Err(ArchiveError::Roots(roots.into_iter().map(|i| members[i].0.clone()).collect()));
+            }
+            roots.first().copied()
+        },
+    };
+    let root = root.ok_or_else(|| ArchiveError::NoStep(member.map(str::to_owned)))?;
+    let (_, text) = members.remove(root);
+    Ok(Contents { text, members })
+}
+
+// member selects the root file of a zip archive
+pub fn open(bytes: Vec<u8>, member: Option<&str>) -> Result<Contents, ArchiveError> {
+    open_at_most(bytes, member, MAX_SIZE, false)
+}
+
+// a gzipped file may only be a STEP file or a zip archive
+fn open_at_most(bytes: Vec<u8>, member: Option<&str>, limit: u64, gunzipped: bool) -> Result<Contents, ArchiveError> {
+    if bytes.starts_with(GZIP) {
+        if gunzipped {
+            return Err(ArchiveError::Nested);
+        }
+        let buf = read_at_most(GzDecoder::new(&bytes[..]), limit)
+            .map_err(|e| ArchiveError::Gzip(e.to_string()))?
+            .ok_or_else(|| ArchiveError::TooLarge("input".to_owned()))?;
+        // a gzipped archive
+        open_at_most(buf, member, limit, true)
+    }
+    else if bytes.starts_with(ZIP) {
+        unzip(&bytes, member, limit)
+    }
+    else {
+        Ok(Contents { text: text(bytes, "input")?, members: Vec::new() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    const STEP: &str = "ISO-10303-21;\nHEADER;\nFILE_DESCRIPTION((''),'2;1');\nENDSEC;\nEND-ISO-10303-21;\n";
+
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut w = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            w.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            w.write_all(content.as_bytes()).unwrap();
+        }
+        w.finish().unwrap().into_inner()
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut e = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        e.write_all(bytes).unwrap();
+        e.finish().unwrap()
+    }
+
+    #[test]
+    fn test_open() {
+        let plain = Contents { text: STEP.to_owned(), members: Vec::new() };
+        assert_eq!(open(STEP.as_bytes().to_vec(), None), Ok(plain));
+        assert_eq!(open(gzip(STEP.as_bytes()), None).unwrap().text, STEP);
+        assert_eq!(open(vec![0xff, 0xfe], None), Err(ArchiveError::NotUtf8("input".to_owned())));
+        assert!(matches!(open(vec![0x1f, 0x8b, 0], None), Err(ArchiveError::Gzip(_))));
+
+        let other = STEP.replace("HEADER;", "HEADER;FILE_NAME();");
+        let assembly = STEP.replace("ENDSEC;\n", "ENDSEC;\nREFERENCE;\n#1=<parts/part.stp#body>;\nENDSEC;\n");
+        let archive = zip(&[("readme.txt", "parts"), ("assembly.stp", &assembly), ("parts/part.stp", &other)]);
+        let contents = open(archive.clone(), None).unwrap();
+        assert_eq!(contents.text, assembly);
+        assert_eq!(contents.member("./parts/part.stp"), Some(&other));
+        assert_eq!(contents.member("readme.txt"), None);
+        let contents = open(gzip(&archive), Some("parts/part.stp")).unwrap();
+        assert_eq!(contents.text, other);
+        assert_eq!(contents.member("assembly.stp"), Some(&assembly));
+        assert_eq!(open(archive, Some("part.stp")), Err(ArchiveError::NoStep(Some("part.stp".to_owned()))));
+        assert_eq!(open(zip(&[("readme.txt", "parts")]), None), Err(ArchiveError::NoStep(None)));
+
+        // the assembly after the part it refers to
+        let archive = zip(&[("parts/part.stp", &other), ("assembly.stp", &assembly)]);
+        assert_eq!(open(archive, None).unwrap().text, assembly);
+        let archive = zip(&[("a.stp", STEP), ("b.stp", STEP)]);
+        assert_eq!(open(archive, None), Err(ArchiveError::Roots(vec!["a.stp".to_owned(), "b.stp".to_owned()])));
+        let xml = "<?xml version=\"1.0\"?>\n<iso_10303_28/>";
+        assert_eq!(open(xml.as_bytes().to_vec(), None), Err(ArchiveError::Xml("input".to_owned())));
+        assert_eq!(open(zip(&[("model.stpx", xml)]), None), Err(ArchiveError::Xml("model.stpx".to_owned())));
+
+        // bombs
+        let zeros = vec![0; 1 << 16];
+        assert_eq!(open_at_most(gzip(&zeros), None, 1 << 15, false), Err(ArchiveError::TooLarge("input".to_owned())));
+        let archive = zip(&[("assembly.stp", STEP), ("parts/part.stp", &other)]);
+        assert_eq!(open_at_most(archive, None, STEP.len() as u64 + 1, false), Err(ArchiveError::TooLarge("parts/part.stp".to_owned())));
+        assert_eq!(open(gzip(&gzip(STEP.as_bytes())), None), Err(ArchiveError::Nested));
+    }
+}
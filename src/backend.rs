@@ -1,5 +1,7 @@
-use super::analysis::{Proc, Drill};
+use super::analysis::{Proc, Drill, Hole, Section, Slot};
+use super::math::{V3, Tolerance};
 use std::cmp;
+use std::fmt;
 use std::fmt::{Write, Error};
 
 #[derive(Serialize, Deserialize)]
@@ -17,12 +19,38 @@ struct EndmillConfig {
     step: f64,
     offset: f64,
     feed_rate: f64,
+    // left on the wall of a milled hole by the helix for the finishing pass
+    #[serde(default)]
+    allowance: f64,
 }
 
 #[derive(Serialize, Deserialize)]
 struct DrillConfig {
     offset: f64,
     feed_rate: f64,
+    // how far the drill goes past the exit of a through hole
+    #[serde(default)]
+    breakthrough: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ToolConfig {
+    number: u32,
+    diameter: f64,
+    // how deep the tool reaches from the surface
+    length: f64,
+}
+
+// without drills one drill fits every hole and tools are never changed, the same for endmills
+#[derive(Serialize, Deserialize, Default)]
+struct ToolLibraryConfig {
+    // how far the diameter of a tool may differ from the hole, the tolerance of the model if not given
+    #[serde(default)]
+    tolerance: Option<f64>,
+    #[serde(default)]
+    drills: Vec<ToolConfig>,
+    #[serde(default)]
+    endmills: Vec<ToolConfig>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -33,6 +61,80 @@ pub struct CNCConfig {
     endmill: EndmillConfig,
     drill: DrillConfig,
     cut: bool,
+    #[serde(default)]
+    tools: ToolLibraryConfig,
+    // geometric tolerance in millimetres, the uncertainty declared in the model if not given
+    #[serde(default)]
+    pub tolerance: Option<f64>,
+}
+
+impl CNCConfig {
+    // returns the name of the first field which cannot be used
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.feed_rate <= 0.0 {
+            return Err("feed_rate");
+        }
+        if self.endmill.r <= 0.0 {
+            return Err("endmill.r");
+        }
+        if self.endmill.step <= 0.0 {
+            return Err("endmill.step");
+        }
+        if self.endmill.feed_rate <= 0.0 {
+            return Err("endmill.feed_rate");
+        }
+        if self.endmill.allowance < 0.0 {
+            return Err("endmill.allowance");
+        }
+        if self.drill.feed_rate <= 0.0 {
+            return Err("drill.feed_rate");
+        }
+        if self.drill.breakthrough < 0.0 {
+            return Err("drill.breakthrough");
+        }
+        if self.tolerance.map(|tol| tol <= 0.0).unwrap_or(false) {
+            return Err("tolerance");
+        }
+        if self.tools.tolerance.map(|tol| tol < 0.0).unwrap_or(false) {
+            return Err("tools.tolerance");
+        }
+        if self.tools.drills.iter().any(|tool| tool.diameter <= 0.0 || tool.length <= 0.0) {
+            return Err("tools.drills");
+        }
+        if self.tools.endmills.iter().any(|tool| tool.diameter <= 0.0 || tool.length <= 0.0) {
+            return Err("tools.endmills");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BackendError {
+    // no tool of the diameter reaching the depth in the library
+    NoDrill { diameter: f64, depth: f64 },
+    NoEndmill { diameter: f64, depth: f64 },
+    // narrower than the endmill
+    NarrowSlot(f64),
+    Format(Error),
+}
+
+impl From<Error> for BackendError {
+    fn from(e: Error) -> Self {
+        BackendError::Format(e)
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackendError::NoDrill { diameter, depth } =>
+                write!(f, "no drill of diameter {} reaching {} deep in the tool library", diameter, depth),
+            BackendError::NoEndmill { diameter, depth } =>
+                write!(f, "no endmill of diameter {} reaching {} deep in the tool library", diameter, depth),
+            BackendError::NarrowSlot(width) => write!(f, "no endmill fits a slot {} wide", width),
+            BackendError::Format(e) => write!(f, "{}", e),
+        }
+    }
 }
 
 pub struct Move {
@@ -59,6 +161,10 @@ pub enum GCode {
     Comment(String),
     G0(Move),
     G1(Move, f64),
+    // clockwise arc in the XY plane with the offset of the center from the start
+    G2(Move, (f64, f64), f64),
+    // T and M06
+    ToolChange(u32),
     M02,
     M03
 }
@@ -98,6 +204,10 @@ fn output(buf: &mut String, gcodes: &[GCode]) -> Result<(), Error> {
                 buf.write_fmt(format_args!("M03\n"))?;
                 before = gcode;
             },
+            GCode::ToolChange(number) => {
+                buf.write_fmt(format_args!("T{} M06\n", number))?;
+                before = gcode;
+            },
             GCode::G0(m) => {
                 match before {
                     GCode::G0(_) => {},
@@ -126,11 +236,30 @@ fn output(buf: &mut String, gcodes: &[GCode]) -> Result<(), Error> {
                 before_pos = m;
                 before_feed_rate = *feed_rate;
             },
+            GCode::G2(m, (i, j), feed_rate) => {
+                buf.write_str("G2 ")?;
+                print_modified_pos(buf, before_pos, m)?;
+                buf.write_fmt(format_args!("I{:.3}J{:.3}", i, j))?;
+                print_modified_axis(buf, "F", before_feed_rate, *feed_rate)?;
+                buf.write_str("\n")?;
+                before = gcode;
+                before_pos = m;
+                before_feed_rate = *feed_rate;
+            },
         }
     }
     Ok(())
 }
 
+// Z of the tip at the bottom, short of the opposite wall
+fn plunge(cfg: &CNCConfig, hole: &Hole, bottom: f64) -> f64 {
+    match hole {
+        Hole::Through(hollow) => bottom - cfg.drill.breakthrough.min(*hollow),
+        Hole::BothWalls => bottom - cfg.drill.breakthrough,
+        Hole::Blind(_) => bottom,
+    }
+}
+
 fn gcodes_of_drill(cfg: &CNCConfig, drill: &Drill, target_r: f64) -> Vec<GCode> {
     vec![
         GCode::G0(Move {
@@ -143,7 +272,7 @@ fn gcodes_of_drill(cfg: &CNCConfig, drill: &Drill, target_r: f64) -> Vec<GCode>
         GCode::G1(Move {
             x: drill.d,
             y: drill.slide,
-            z: 0.0,
+            z: plunge(cfg, &drill.hole, drill.bottom),
             a: drill.theta * 180.0 / std::f64::consts::PI,
             b: target_r + cfg.endmill.offset,
         },
@@ -211,27 +340,270 @@ fn gcodes_of_cut(cfg: &CNCConfig, cut_pos: f64, target_r: f64) -> Vec<GCode> {
     gcodes
 }
 
+// a helix down to the bottom with the endmill above the hole at x, then the wall is finished
+// in a full circle, B stepping down by endmill.step a turn
+fn gcodes_of_mill(cfg: &CNCConfig, drill: &Drill, section: &Section, x: f64, target_r: f64) -> Vec<GCode> {
+    let drill_waiting = target_r + cfg.drill.offset;
+    let endmill_waiting = target_r + cfg.endmill.offset;
+    let rough = drill.r - cfg.endmill.r - cfg.endmill.allowance;
+    let finish = drill.r - cfg.endmill.r;
+    let bottom = plunge(cfg, &drill.hole, drill.bottom);
+    let at = |dx: f64, b: f64| Move {
+        x: x + dx,
+        y: drill.slide,
+        z: drill_waiting,
+        a: drill.theta * 180.0 / std::f64::consts::PI,
+        b,
+    };
+    let turns = ((drill.top - bottom) / cfg.endmill.step).ceil().max(1.0) as i32;
+    let mut gcodes = vec![
+        GCode::G0(at(rough, endmill_waiting)),
+        GCode::G1(at(rough, drill.top), cfg.feed_rate),
+    ];
+    for i in 1..=turns {
+        let b = drill.top - (drill.top - bottom) * i as f64 / turns as f64;
+        gcodes.push(GCode::G2(at(rough, b), (-rough, 0.0), cfg.endmill.feed_rate));
+    }
+    // flat at the bottom
+    gcodes.push(GCode::G2(at(rough, bottom), (-rough, 0.0), cfg.endmill.feed_rate));
+    // a finishing circle at the end of each wall, from the bottom up
+    let mut b = bottom;
+    if finish > rough {
+        let spans = drill.spans(section);
+        for i in (0..spans.len()).rev() {
+            b = if i + 1 == spans.len() { bottom } else { spans[i].0 };
+            gcodes.push(GCode::G1(at(finish, b), cfg.endmill.feed_rate));
+            gcodes.push(GCode::G2(at(finish, b), (-finish, 0.0), cfg.endmill.feed_rate));
+        }
+    }
+    gcodes.push(GCode::G1(at(0.0, b), cfg.endmill.feed_rate));
+    gcodes.push(GCode::G0(at(0.0, endmill_waiting)));
+    gcodes
+}
+
+// the endmill above the slot ramps between the ends along the center line down to the bottom,
+// then goes round out to the width of the slot
+fn gcodes_of_slot(cfg: &CNCConfig, slot: &Slot, target_r: f64) -> Vec<GCode> {
+    let drill_waiting = target_r + cfg.drill.offset;
+    let endmill_waiting = target_r + cfg.endmill.offset;
+    let bottom = plunge(cfg, &slot.hole, slot.bottom);
+    let at = |(x, y): (f64, f64), b: f64| Move {
+        x: x + cfg.gap_endmill_and_drill,
+        y,
+        z: drill_waiting,
+        a: slot.theta * 180.0 / std::f64::consts::PI,
+        b,
+    };
+    let ends = slot.ends;
+    let passes = ((slot.top - bottom) / cfg.endmill.step).ceil().max(1.0) as usize;
+    let mut gcodes = vec![
+        GCode::G0(at(ends[0], endmill_waiting)),
+        GCode::G1(at(ends[0], slot.top), cfg.feed_rate),
+    ];
+    for i in 1..=passes {
+        let b = slot.top - (slot.top - bottom) * i as f64 / passes as f64;
+        gcodes.push(GCode::G1(at(ends[i % 2], b), cfg.endmill.feed_rate));
+    }
+    // flat at the bottom
+    gcodes.push(GCode::G1(at(ends[(passes + 1) % 2], bottom), cfg.endmill.feed_rate));
+    let (e, f) = (ends[(passes + 1) % 2], ends[passes % 2]);
+    let length = (f.0 - e.0).hypot(f.1 - e.1);
+    // clockwise from the left of e to f, round f and back round e
+    let n = (-(f.1 - e.1) / length, (f.0 - e.0) / length);
+    let width = slot.r - cfg.endmill.r;
+    let rounds = (width / cfg.endmill.r).ceil() as usize;
+    for i in 1..=rounds {
+        let rho = width * i as f64 / rounds as f64;
+        let side = |p: (f64, f64), s: f64| (p.0 + n.0 * rho * s, p.1 + n.1 * rho * s);
+        gcodes.push(GCode::G1(at(side(e, 1.0), bottom), cfg.endmill.feed_rate));
+        gcodes.push(GCode::G1(at(side(f, 1.0), bottom), cfg.endmill.feed_rate));
+        gcodes.push(GCode::G2(at(side(f, -1.0), bottom), (-n.0 * rho, -n.1 * rho), cfg.endmill.feed_rate));
+        gcodes.push(GCode::G1(at(side(e, -1.0), bottom), cfg.endmill.feed_rate));
+        gcodes.push(GCode::G2(at(side(e, 1.0), bottom), (n.0 * rho, n.1 * rho), cfg.endmill.feed_rate));
+    }
+    gcodes.push(GCode::G1(at(e, bottom), cfg.endmill.feed_rate));
+    gcodes.push(GCode::G0(at(e, endmill_waiting)));
+    gcodes
+}
+
 enum Job<'a> {
     Drill(&'a Drill),
+    // a hole larger than the drills with the endmill
+    Mill(&'a Drill),
+    Slot(&'a Slot),
     Cut,
 }
 
-pub fn gen_gcode(proc: Proc, cfg: &CNCConfig) -> Result<String, Error> {
-    let mut jobs = proc.drills.iter().map(|drill| (drill.d, Job::Drill(drill))).collect::<Vec<(f64, Job)>>();
+// the tool closest to the diameter among those long enough, None without a library
+fn find_tool(tools: &[ToolConfig], tolerance: f64, diameter: f64, depth: f64) -> Option<Option<u32>> {
+    if tools.is_empty() {
+        return Some(None);
+    }
+    tools
+        .iter()
+        .filter(|tool| (tool.diameter - diameter).abs() <= tolerance && tool.length >= depth)
+        .min_by(|a, b| {
+            let (da, db) = ((a.diameter - diameter).abs(), (b.diameter - diameter).abs());
+            da.partial_cmp(&db).unwrap_or(cmp::Ordering::Equal)
+        })
+        .map(|tool| Some(tool.number))
+}
+
+fn endmill_tool(cfg: &CNCConfig, tolerance: f64, depth: f64) -> Result<Option<u32>, BackendError> {
+    let diameter = cfg.endmill.r * 2.0;
+    find_tool(&cfg.tools.endmills, tolerance, diameter, depth).ok_or(BackendError::NoEndmill { diameter, depth })
+}
+
+// a hole larger than any drill of the library is milled if the endmill leaves room for the helix,
+// X of the job and the tool
+fn drill_job<'a>(cfg: &CNCConfig, tolerance: f64, drill: &'a Drill) -> Result<(f64, Option<u32>, Job<'a>), BackendError> {
+    let (diameter, depth) = (drill.r * 2.0, drill.top - plunge(cfg, &drill.hole, drill.bottom));
+    if let Some(tool) = find_tool(&cfg.tools.drills, tolerance, diameter, depth) {
+        return Ok((drill.d, tool, Job::Drill(drill)));
+    }
+    let largest = cfg.tools.drills.iter().map(|tool| tool.diameter).fold(0.0, f64::max);
+    if diameter > largest + tolerance && drill.r - cfg.endmill.r - cfg.endmill.allowance > 0.0 {
+        // the endmill is gap_endmill_and_drill ahead of the drill
+        return Ok((drill.d + cfg.gap_endmill_and_drill, endmill_tool(cfg, tolerance, depth)?, Job::Mill(drill)));
+    }
+    Err(BackendError::NoDrill { diameter, depth })
+}
+
+fn slot_job<'a>(cfg: &CNCConfig, tolerance: f64, slot: &'a Slot) -> Result<(f64, Option<u32>, Job<'a>), BackendError> {
+    if slot.r < cfg.endmill.r - tolerance {
+        return Err(BackendError::NarrowSlot(slot.r * 2.0));
+    }
+    let tool = endmill_tool(cfg, tolerance, slot.top - plunge(cfg, &slot.hole, slot.bottom))?;
+    Ok((slot.ends[0].0 + cfg.gap_endmill_and_drill, tool, Job::Slot(slot)))
+}
+
+// X of the endmill cutting off both ends
+fn cut_positions(proc: &Proc, cfg: &CNCConfig) -> Vec<f64> {
     if cfg.cut {
-        jobs.push((cfg.gap_endmill_and_drill - cfg.endmill.r, Job::Cut));
-        jobs.push((cfg.gap_endmill_and_drill + cfg.endmill.r + proc.size.z(), Job::Cut));
+        vec![
+            cfg.gap_endmill_and_drill - cfg.endmill.r,
+            cfg.gap_endmill_and_drill + cfg.endmill.r + proc.size.z(),
+        ]
     }
-    jobs.sort_by(|x, y| if x.0 > y.0 { cmp::Ordering::Greater } else { cmp::Ordering::Less });
-    let target_r = ((proc.size.x() / 2.0).powi(2) + (proc.size.y() / 2.0).powi(2)).sqrt();
-    let mut gcodes = Vec::new();
+    else {
+        Vec::new()
+    }
+}
+
+fn target_r(proc: &Proc) -> f64 {
+    ((proc.size.x() / 2.0).powi(2) + (proc.size.y() / 2.0).powi(2)).sqrt()
+}
+
+// material removed by a job, relative to the center of the end of the pipe
+// along the axes of Proc (z along the pipe)
+#[derive(Debug, PartialEq)]
+pub enum Removal {
+    // the tool from base along direction
+    Cylinder { base: V3, direction: V3, r: f64, length: f64 },
+    // the tool swept from one base to the other
+    Slot { bases: [V3; 2], direction: V3, r: f64, length: f64 },
+    // everything between two positions along the pipe
+    Slab(f64, f64),
+}
+
+pub fn removals(proc: &Proc, cfg: &CNCConfig) -> Vec<Removal> {
+    let target_r = target_r(proc);
+    // the tip at z along the tool
+    let base = |theta: f64, (d, slide): (f64, f64), z: f64| {
+        let (sin, cos) = theta.sin_cos();
+        V3([sin * slide + cos * z, -cos * slide + sin * z, d])
+    };
+    let mut removals = proc.drills
+        .iter()
+        .map(|drill| {
+            let z = plunge(cfg, &drill.hole, drill.bottom);
+            Removal::Cylinder {
+                base: base(drill.theta, (drill.d, drill.slide), z),
+                direction: V3([drill.theta.cos(), drill.theta.sin(), 0.0]),
+                r: drill.r,
+                length: target_r + cfg.drill.offset - z,
+            }
+        })
+        .collect::<Vec<Removal>>();
+    for slot in &proc.slots {
+        let z = plunge(cfg, &slot.hole, slot.bottom);
+        removals.push(Removal::Slot {
+            bases: [base(slot.theta, slot.ends[0], z), base(slot.theta, slot.ends[1], z)],
+            direction: V3([slot.theta.cos(), slot.theta.sin(), 0.0]),
+            r: slot.r,
+            length: target_r + cfg.endmill.offset - z,
+        });
+    }
+    // the endmill is gap_endmill_and_drill ahead of the drill
+    for x in cut_positions(proc, cfg) {
+        let center = x - cfg.gap_endmill_and_drill;
+        removals.push(Removal::Slab(center - cfg.endmill.r, center + cfg.endmill.r));
+    }
+    removals
+}
+
+// comments are written before the program, tol is the one the model was analysed with
+pub fn gen_gcode(proc: &Proc, cfg: &CNCConfig, tol: &Tolerance, comments: &[String]) -> Result<String, BackendError> {
+    let target_r = target_r(proc);
+    let tolerance = cfg.tools.tolerance.unwrap_or(tol.distance);
+    let drills = proc.drills
+        .iter()
+        .map(|drill| drill_job(cfg, tolerance, drill))
+        .chain(proc.slots.iter().map(|slot| slot_job(cfg, tolerance, slot)))
+        .collect::<Result<Vec<(f64, Option<u32>, Job)>, BackendError>>()?;
+    // drills are grouped by tool in the order each is first needed along the pipe
+    let mut along = drills
+        .iter()
+        .filter(|(_, _, job)| matches!(job, Job::Drill(_)))
+        .map(|(x, tool, _)| (*x, *tool))
+        .collect::<Vec<(f64, Option<u32>)>>();
+    along.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(cmp::Ordering::Equal));
+    let mut order = Vec::new();
+    for (_, tool) in along {
+        if !order.contains(&tool) {
+            order.push(tool);
+        }
+    }
+    // milled holes and slots go with the first group
+    let rank = |job: &Job, tool: &Option<u32>| match job {
+        Job::Drill(_) => order.iter().position(|t| t == tool).unwrap_or(0),
+        _ => 0,
+    };
+    let mut jobs = drills
+        .into_iter()
+        .map(|(x, tool, job)| (rank(&job, &tool), x, tool, job))
+        .collect::<Vec<(usize, f64, Option<u32>, Job)>>();
+    // the end is cut off after all drills, the endmill cuts down to the center
+    let endmill = if cfg.cut { endmill_tool(cfg, tolerance, target_r)? } else { None };
+    for (i, x) in cut_positions(proc, cfg).into_iter().enumerate() {
+        let rank = if i == 0 { 0 } else { order.len().saturating_sub(1) };
+        jobs.push((rank, x, endmill, Job::Cut));
+    }
+    jobs.sort_by(|x, y| (x.0, x.1).partial_cmp(&(y.0, y.1)).unwrap_or(cmp::Ordering::Equal));
+    let mut gcodes = comments.iter().cloned().map(GCode::Comment).collect::<Vec<GCode>>();
     gcodes.push(GCode::Comment("init".to_owned()));
     gcodes.push(GCode::M02);
-    for job in jobs {
+    // loaded in the drill and the endmill spindles
+    let (mut drill_loaded, mut endmill_loaded) = (None, None);
+    for (_, p, tool, job) in jobs {
+        let loaded = match job {
+            Job::Drill(_) => &mut drill_loaded,
+            Job::Mill(_) | Job::Slot(_) | Job::Cut => &mut endmill_loaded,
+        };
+        if let Some(number) = tool {
+            if *loaded != tool {
+                gcodes.push(GCode::ToolChange(number));
+                *loaded = tool;
+            }
+        }
         match job {
-            (_, Job::Drill(drill)) =>
-                gcodes.append(&mut gcodes_of_drill(&cfg, &drill, target_r)),
-            (p, Job::Cut) =>
+            Job::Drill(drill) =>
+                gcodes.append(&mut gcodes_of_drill(cfg, drill, target_r)),
+            Job::Mill(drill) =>
+                gcodes.append(&mut gcodes_of_mill(cfg, drill, &proc.section, p, target_r)),
+            Job::Slot(slot) =>
+                gcodes.append(&mut gcodes_of_slot(cfg, slot, target_r)),
+            Job::Cut =>
                 gcodes.append(&mut gcodes_of_cut(cfg, p, target_r)),
         }
     }
@@ -240,3 +612,112 @@ pub fn gen_gcode(proc: Proc, cfg: &CNCConfig) -> Result<String, Error> {
     output(&mut buf, &gcodes)?;
     Ok(buf)
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use super::super::math::Mat3x3;
+
+    // the endmill of r 3 is 153 ahead of the drill, tools is the library as JSON
+    pub(crate) fn cfg(cut: bool, tools: &str) -> CNCConfig {
+        let json = format!(r#"{{
+            "gap_endmill_and_drill": 153.0, "feed_rate": 1000.0,
+            "offsets": {{ "x": 0.0, "y": 0.0, "z": 0.0, "a": 0.0, "b": 0.0 }},
+            "endmill": {{ "step": 0.1, "offset": 5.0, "r": 3.0, "feed_rate": 200.0, "allowance": 0.2 }},
+            "drill": {{ "offset": 5.0, "feed_rate": 50.0, "breakthrough": 1.0 }},
+            "cut": {},
+            "tools": {}
+        }}"#, cut, tools);
+        serde_json::from_str(&json).unwrap()
+    }
+
+    // 10 x 20 x 100 with walls 1 thick along z
+    pub(crate) fn tube(drills: Vec<Drill>, slots: Vec<Slot>) -> Proc {
+        Proc {
+            drills,
+            slots,
+            center: V3::default(),
+            size: V3([10.0, 20.0, 100.0]),
+            section: Section { x: [-5.0, -4.0, 4.0, 5.0], y: [-10.0, -9.0, 9.0, 10.0], inner_corner_r: 0.0 },
+            axes: (V3([1.0, 0.0, 0.0]), V3([0.0, 1.0, 0.0]), V3([0.0, 0.0, 1.0])),
+            rotation: Mat3x3([V3([1.0, 0.0, 0.0]), V3([0.0, 1.0, 0.0]), V3([0.0, 0.0, 1.0])]),
+            report: String::new(),
+        }
+    }
+
+    // from +x through both walls
+    fn drill(r: f64, d: f64) -> Drill {
+        Drill { r, d, theta: 0.0, slide: 0.0, hole: Hole::BothWalls, top: 5.0, bottom: -5.0 }
+    }
+
+    const LIBRARY: &str = r#"{
+        "tolerance": 0.05,
+        "drills": [
+            { "number": 1, "diameter": 5.0, "length": 30.0 },
+            { "number": 2, "diameter": 3.2, "length": 10.0 },
+            { "number": 3, "diameter": 3.23, "length": 30.0 }
+        ],
+        "endmills": [{ "number": 10, "diameter": 6.0, "length": 20.0 }]
+    }"#;
+
+    fn program(proc: &Proc, tools: &str) -> Result<String, BackendError> {
+        gen_gcode(proc, &cfg(true, tools), &Tolerance::default(), &[])
+    }
+
+    #[test]
+    fn test_tools() {
+        let proc = tube(vec![drill(1.6, 60.0), drill(2.5, 20.0), drill(1.6, 10.0), drill(2.5, 80.0)], Vec::new());
+        let gcode = program(&proc, LIBRARY).unwrap();
+        // the short drill 2 cannot go through both walls, the end is cut off last
+        let lines = gcode
+            .lines()
+            .filter(|line| line.starts_with('T') || line.contains('X'))
+            .map(|line| line.split('Y').next().unwrap().trim_start_matches("G0 "))
+            .collect::<Vec<&str>>();
+        assert_eq!(lines, vec!["T3 M06", "X10.000", "X60.000", "T10 M06", "X150.000", "T1 M06", "X20.000", "X80.000", "X256.000"]);
+        assert_eq!(gcode.matches("T10 M06").count(), 1);
+
+        assert_eq!(program(&proc, "{}").unwrap().matches("M06").count(), 0);
+
+        let library = r#"{ "drills": [{ "number": 1, "diameter": 5.0, "length": 30.0 }] }"#;
+        assert_eq!(program(&proc, library).err(), Some(BackendError::NoDrill { diameter: 3.2, depth: 11.0 }));
+        // within the tolerance of the model without one of the library
+        let library = r#"{ "drills": [{ "number": 1, "diameter": 5.0, "length": 30.0 }, { "number": 2, "diameter": 3.23, "length": 30.0 }] }"#;
+        assert!(gen_gcode(&proc, &cfg(true, library), &Tolerance::from_distance(0.05), &[]).unwrap().contains("T2 M06"));
+        let library = r#"{ "endmills": [{ "number": 10, "diameter": 6.0, "length": 10.0 }] }"#;
+        assert_eq!(program(&proc, library).err(), Some(BackendError::NoEndmill { diameter: 6.0, depth: 125f64.sqrt() }));
+    }
+
+    #[test]
+    fn test_mill() {
+        // larger than any drill, 0.8 around the center with 0.2 left for the finishing circle
+        let mut large = tube(vec![drill(4.0, 40.0)], Vec::new());
+        let gcode = program(&large, LIBRARY).unwrap();
+        assert_eq!(gcode.matches("G2 ").count(), 110 + 3);
+        let lines = gcode.lines().skip_while(|line| !line.contains("X193.800")).take(4).collect::<Vec<&str>>();
+        assert_eq!(lines, vec![
+            "G0 X193.800",
+            "G1 B5.000",
+            "G2 B4.900I-0.800J0.000F200.000",
+            "G2 B4.800I-0.800J0.000",
+        ]);
+        // finished at the inner side of the near wall too
+        assert!(gcode.contains(
+            "G2 B-6.000I-0.800J0.000\nG2 I-0.800J0.000\nG1 X194.000\nG2 I-1.000J0.000\nG1 B4.000\nG2 I-1.000J0.000\nG1 X193.000\n"));
+        // the endmill is larger than the hole
+        large.drills[0].r = 3.1;
+        assert_eq!(program(&large, LIBRARY).err(), Some(BackendError::NoDrill { diameter: 6.2, depth: 11.0 }));
+    }
+
+    #[test]
+    fn test_slot() {
+        // ramps 0.1 deep a pass, one round 0.5 out and back to the end it has reached
+        let slot = Slot { r: 3.5, theta: 0.0, ends: [(30.0, 0.0), (50.0, 0.0)], hole: Hole::BothWalls, top: 5.0, bottom: -5.0 };
+        let gcode = program(&tube(Vec::new(), vec![slot.clone()]), LIBRARY).unwrap();
+        assert!(gcode.contains("G0 X183.000\nG1 B5.000\nG1 X203.000B4.900F200.000\nX183.000B4.800\n"));
+        assert!(gcode.contains(
+            "X183.000B-6.000\nX203.000\nY-0.500\nX183.000\nG2 Y0.500I0.000J0.500\nG1 X203.000\nG2 Y-0.500I-0.000J-0.500\nG1 Y0.000\nG0 B16.180\n"));
+        let narrow = tube(Vec::new(), vec![Slot { r: 2.9, ..slot }]);
+        assert_eq!(program(&narrow, LIBRARY).err(), Some(BackendError::NarrowSlot(5.8)));
+    }
+}
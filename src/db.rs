@@ -0,0 +1,149 @@
+use super::preprocess::{Data, Value};
+use std::collections::HashMap;
+
+// A reference to an entity instance which is not in the file.
+// arg is the index of the top-level argument of `from` including the reference
+// (counted through all partial entities for complex instances).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DanglingReference {
+    pub from: u64,
+    pub arg: usize,
+    pub to: u64,
+}
+
+pub struct DataDB {
+    map: HashMap<u64, Data>,
+    // simple entity instances and the argument referring to each id, in ascending order
+    referrers: HashMap<u64, Vec<(u64, usize)>>,
+}
+
+fn collect_ids(v: &Value, ids: &mut Vec<u64>) {
+    match v {
+        Value::Id(id) => ids.push(*id),
+        Value::Tuple(vs) => vs.iter().for_each(|v| collect_ids(v, ids)),
+        Value::Desc(_, vs) => vs.iter().for_each(|v| collect_ids(v, ids)),
+        _ => (),
+    }
+}
+
+impl DataDB {
+    pub fn new(data: Vec<Data>) -> Self {
+        let mut map = HashMap::new();
+        let mut referrers: HashMap<u64, Vec<(u64, usize)>> = HashMap::new();
+        for d in data {
+            match d {
+                Data::Single(id, _, ref args) => {
+                    for (i, v) in args.iter().enumerate() {
+                        if let Value::Id(to) = v {
+                            referrers.entry(*to).or_default().push((id, i));
+                        }
+                    }
+                    map.insert(id, d);
+                },
+                Data::Aggregate(id, _) => {
+                    map.insert(id, d);
+                },
+            }
+        }
+        referrers.values_mut().for_each(|from| from.sort());
+        DataDB { map, referrers }
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Data> {
+        self.map.get(&id)
+    }
+
+    // ids of simple entity instances of the type in ascending order
+    pub fn find(&self, name: &str) -> Vec<u64> {
+        let mut ids = self.map
+            .iter()
+            .filter_map(|(id, d)| match d {
+                Data::Single(_, n, _) if n == name => Some(*id),
+                _ => None,
+            })
+            .collect::<Vec<u64>>();
+        ids.sort();
+        ids
+    }
+
+    // ids of simple entity instances of the type whose i-th argument refers to id in ascending order
+    pub fn referrers(&self, name: &str, i: usize, id: u64) -> Vec<u64> {
+        self.referrers
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .filter(|(from, arg)| *arg == i && matches!(self.map.get(from), Some(Data::Single(_, n, _)) if n == name))
+            .map(|(from, _)| *from)
+            .collect()
+    }
+
+    // all references to missing entities, sorted by referencing entity
+    pub fn dangling_references(&self) -> Vec<DanglingReference> {
+        let mut dangling = Vec::new();
+        for (from, d) in self.map.iter() {
+            let args: Vec<&Value> = match d {
+                Data::Single(_, _, args) => args.iter().collect(),
+                Data::Aggregate(_, partials) => partials.iter().flat_map(|(_, args)| args.iter()).collect(),
+            };
+            for (arg, v) in args.iter().enumerate() {
+                let mut ids = Vec::new();
+                collect_ids(v, &mut ids);
+                for to in ids {
+                    if !self.map.contains_key(&to) {
+                        dangling.push(DanglingReference { from: *from, arg, to });
+                    }
+                }
+            }
+        }
+        dangling.sort_by_key(|r| (r.from, r.arg, r.to));
+        dangling
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<DanglingReference>> {
+        let dangling = self.dangling_references();
+        if dangling.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(dangling)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::preprocess;
+
+    #[test]
+    fn test_dangling_references() {
+        let step = preprocess::parse(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            DATA;
+            #1=CARTESIAN_POINT('',(0.,0.,0.));
+            #2=DIRECTION('',(0.,0.,1.));
+            #3=AXIS2_PLACEMENT_3D('',#1,#2,#4);
+            #5=CLOSED_SHELL('',(#6,#3,#7));
+            #8=(
+            REPRESENTATION_CONTEXT('','3D')
+            GLOBAL_UNIT_ASSIGNED_CONTEXT((#9))
+            );
+            ENDSEC;
+            END-ISO-10303-21;").unwrap();
+        let db = DataDB::new(step.data);
+        assert!(db.get(3).is_some());
+        assert_eq!(db.find("DIRECTION"), vec![2]);
+        assert_eq!(db.find("REPRESENTATION_CONTEXT"), Vec::<u64>::new());
+        assert_eq!(db.referrers("AXIS2_PLACEMENT_3D", 2, 2), vec![3]);
+        assert_eq!(db.referrers("AXIS2_PLACEMENT_3D", 1, 2), Vec::<u64>::new());
+        assert!(db.get(4).is_none());
+        assert_eq!(db.validate(), Err(vec![
+            DanglingReference { from: 3, arg: 3, to: 4 },
+            DanglingReference { from: 5, arg: 1, to: 6 },
+            DanglingReference { from: 5, arg: 1, to: 7 },
+            DanglingReference { from: 8, arg: 2, to: 9 },
+        ]));
+    }
+}
@@ -0,0 +1,437 @@
+use super::preprocess::{Data, Value};
+use super::db::DataDB;
+use super::parser::ParseError;
+
+// Typed entity instances of the subset of AP203/AP214/AP242 used by the parser.
+// Attributes are in the order of the EXPRESS schema, attributes of supertypes first.
+// A complex instance is read by concatenating its partial entities along the supertypes,
+// and a single instance of a declared subtype is read as the supertype.
+
+// a value of an attribute, u64 for entity references
+pub trait Attribute: Sized {
+    // REAL or an aggregate of REAL
+    const REAL: bool = false;
+
+    fn from_value(v: &Value) -> Option<Self>;
+}
+
+impl Attribute for f64 {
+    const REAL: bool = true;
+
+    fn from_value(v: &Value) -> Option<Self> {
+        v.float().copied()
+    }
+}
+
+impl Attribute for i64 {
+    fn from_value(v: &Value) -> Option<Self> {
+        v.int().copied()
+    }
+}
+
+impl Attribute for bool {
+    fn from_value(v: &Value) -> Option<Self> {
+        v.boolean().copied()
+    }
+}
+
+impl Attribute for String {
+    fn from_value(v: &Value) -> Option<Self> {
+        v.str().cloned()
+    }
+}
+
+impl Attribute for u64 {
+    fn from_value(v: &Value) -> Option<Self> {
+        v.id().copied()
+    }
+}
+
+// SELECT types and measures which are interpreted by the user
+impl Attribute for Value {
+    fn from_value(v: &Value) -> Option<Self> {
+        Some(v.clone())
+    }
+}
+
+// LIST, SET and ARRAY
+impl<T: Attribute> Attribute for Vec<T> {
+    const REAL: bool = T::REAL;
+
+    fn from_value(v: &Value) -> Option<Self> {
+        v.tuple()?.iter().map(T::from_value).collect()
+    }
+}
+
+// OPTIONAL attributes
+impl<T: Attribute> Attribute for Option<T> {
+    const REAL: bool = T::REAL;
+
+    fn from_value(v: &Value) -> Option<Self> {
+        match v {
+            Value::Undefined => Some(None),
+            v => T::from_value(v).map(Some),
+        }
+    }
+}
+
+// ENUMERATION
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enumeration(pub String);
+
+impl Attribute for Enumeration {
+    fn from_value(v: &Value) -> Option<Self> {
+        match v {
+            Value::Enum(e) => Some(Enumeration(e.clone())),
+            _ => None,
+        }
+    }
+}
+
+// LOGICAL, None for .U.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Logical(pub Option<bool>);
+
+impl Attribute for Logical {
+    fn from_value(v: &Value) -> Option<Self> {
+        match v {
+            Value::Bool(b) => Some(Logical(Some(*b))),
+            Value::Enum(e) if e == "U" => Some(Logical(None)),
+            _ => None,
+        }
+    }
+}
+
+// attributes redeclared as DERIVE in a subtype, written as *
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Derived;
+
+impl Attribute for Derived {
+    fn from_value(v: &Value) -> Option<Self> {
+        match v {
+            Value::Xplicit => Some(Derived),
+            _ => None,
+        }
+    }
+}
+
+pub trait Entity: Sized {
+    const NAME: &'static str;
+    // subtypes read as this entity, their own attributes are ignored
+    const SUBTYPES: &'static [&'static str];
+    // partial entities having the attributes in a complex instance, supertypes first
+    const PARTIALS: &'static [&'static str];
+    // for each attribute, true if it is read as a real
+    const REALS: &'static [bool];
+
+    // None if the number or a type of the attributes is wrong
+    fn from_args(args: &[Value], subtype: bool) -> Option<Self>;
+
+    fn accepts(name: &str) -> bool {
+        name == Self::NAME || Self::SUBTYPES.contains(&name)
+    }
+}
+
+// T from an instance of T or its subtypes
+pub fn from_data<T: Entity>(d: &Data) -> Result<T, ParseError> {
+    match d {
+        Data::Single(id, name, args) => {
+            if !T::accepts(name) {
+                return Err(ParseError::UnsupportedEntity(*id, name.clone()));
+            }
+            T::from_args(args, name != T::NAME).ok_or(ParseError::InvalidEntity(*id, T::NAME))
+        },
+        Data::Aggregate(id, partials) => {
+            let find = |name: &str| partials.iter().find(|(n, _)| n == name).map(|(_, args)| args);
+            if find(T::NAME).is_none() {
+                let names = partials.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>();
+                return Err(ParseError::UnsupportedEntity(*id, format!("({})", names.join(" "))));
+            }
+            let mut args = Vec::new();
+            for name in T::PARTIALS {
+                args.extend(find(name).ok_or(ParseError::InvalidEntity(*id, T::NAME))?.iter().cloned());
+            }
+            // the attributes of other partials are not read
+            T::from_args(&args, false).ok_or(ParseError::InvalidEntity(*id, T::NAME))
+        },
+    }
+}
+
+pub fn read<T: Entity>(map: &DataDB, id: u64) -> Result<T, ParseError> {
+    from_data(map.get(id).ok_or(ParseError::MissingReference(id))?)
+}
+
+// name (subtypes) [supertypes with attributes] { attributes }
+macro_rules! entities {
+    ($($ty:ident $name:literal ($($subtype:literal),*) [$($supertype:literal),*] { $($attr:ident: $t:ty),* $(,)? })*) => {
+        $(
+            #[derive(Debug, Clone, PartialEq)]
+            pub struct $ty {
+                $(pub $attr: $t),*
+            }
+
+            impl Entity for $ty {
+                const NAME: &'static str = $name;
+                const SUBTYPES: &'static [&'static str] = &[$($subtype),*];
+                const PARTIALS: &'static [&'static str] = &[$($supertype,)* $name];
+                const REALS: &'static [bool] = &[$(<$t as Attribute>::REAL),*];
+
+                fn from_args(args: &[Value], subtype: bool) -> Option<Self> {
+                    let mut rest = args.iter();
+                    let entity = $ty {
+                        $($attr: <$t as Attribute>::from_value(rest.next()?)?),*
+                    };
+                    // attributes of subtypes follow
+                    if rest.next().is_some() && !subtype {
+                        return None;
+                    }
+                    Some(entity)
+                }
+            }
+        )*
+
+        // Entity::REALS of an entity by its name
+        pub fn reals(name: &str) -> Option<&'static [bool]> {
+            match name {
+                $($name => Some(<$ty as Entity>::REALS),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+entities! {
+    CartesianPoint "CARTESIAN_POINT" () ["REPRESENTATION_ITEM"] {
+        name: String,
+        coordinates: Vec<f64>,
+    }
+    Direction "DIRECTION" () ["REPRESENTATION_ITEM"] {
+        name: String,
+        direction_ratios: Vec<f64>,
+    }
+    Axis2Placement3d "AXIS2_PLACEMENT_3D" () ["REPRESENTATION_ITEM", "PLACEMENT"] {
+        name: String,
+        location: u64,
+        axis: Option<u64>,
+        ref_direction: Option<u64>,
+    }
+    Plane "PLANE" () ["REPRESENTATION_ITEM", "ELEMENTARY_SURFACE"] {
+        name: String,
+        position: u64,
+    }
+    CylindricalSurface "CYLINDRICAL_SURFACE" () ["REPRESENTATION_ITEM", "ELEMENTARY_SURFACE"] {
+        name: String,
+        position: u64,
+        radius: f64,
+    }
+    ConicalSurface "CONICAL_SURFACE" () ["REPRESENTATION_ITEM", "ELEMENTARY_SURFACE"] {
+        name: String,
+        position: u64,
+        radius: f64,
+        semi_angle: f64,
+    }
+    SphericalSurface "SPHERICAL_SURFACE" () ["REPRESENTATION_ITEM", "ELEMENTARY_SURFACE"] {
+        name: String,
+        position: u64,
+        radius: f64,
+    }
+    ToroidalSurface "TOROIDAL_SURFACE" () ["REPRESENTATION_ITEM", "ELEMENTARY_SURFACE"] {
+        name: String,
+        position: u64,
+        major_radius: f64,
+        minor_radius: f64,
+    }
+    BSplineSurfaceWithKnots "B_SPLINE_SURFACE_WITH_KNOTS" () ["REPRESENTATION_ITEM", "B_SPLINE_SURFACE"] {
+        name: String,
+        u_degree: i64,
+        v_degree: i64,
+        control_points_list: Vec<Vec<u64>>,
+        surface_form: Enumeration,
+        u_closed: Logical,
+        v_closed: Logical,
+        self_intersect: Logical,
+        u_multiplicities: Vec<i64>,
+        v_multiplicities: Vec<i64>,
+        u_knots: Vec<f64>,
+        v_knots: Vec<f64>,
+        knot_spec: Enumeration,
+    }
+    RationalBSplineSurface "RATIONAL_B_SPLINE_SURFACE" () ["REPRESENTATION_ITEM", "B_SPLINE_SURFACE"] {
+        name: String,
+        u_degree: i64,
+        v_degree: i64,
+        control_points_list: Vec<Vec<u64>>,
+        surface_form: Enumeration,
+        u_closed: Logical,
+        v_closed: Logical,
+        self_intersect: Logical,
+        weights_data: Vec<Vec<f64>>,
+    }
+    Line "LINE" () ["REPRESENTATION_ITEM"] {
+        name: String,
+        pnt: u64,
+        dir: u64,
+    }
+    Circle "CIRCLE" () ["REPRESENTATION_ITEM", "CONIC"] {
+        name: String,
+        position: u64,
+        radius: f64,
+    }
+    SurfaceCurve "SURFACE_CURVE" ("SEAM_CURVE") ["REPRESENTATION_ITEM"] {
+        name: String,
+        curve_3d: u64,
+        associated_geometry: Vec<u64>,
+        master_representation: Enumeration,
+    }
+    BSplineCurveWithKnots "B_SPLINE_CURVE_WITH_KNOTS" () ["REPRESENTATION_ITEM", "B_SPLINE_CURVE"] {
+        name: String,
+        degree: i64,
+        control_points_list: Vec<u64>,
+        curve_form: Enumeration,
+        closed_curve: Logical,
+        self_intersect: Logical,
+        knot_multiplicities: Vec<i64>,
+        knots: Vec<f64>,
+        knot_spec: Enumeration,
+    }
+    RationalBSplineCurve "RATIONAL_B_SPLINE_CURVE" () ["REPRESENTATION_ITEM", "B_SPLINE_CURVE"] {
+        name: String,
+        degree: i64,
+        control_points_list: Vec<u64>,
+        curve_form: Enumeration,
+        closed_curve: Logical,
+        self_intersect: Logical,
+        weights_data: Vec<f64>,
+    }
+    VertexPoint "VERTEX_POINT" () ["REPRESENTATION_ITEM"] {
+        name: String,
+        vertex_geometry: u64,
+    }
+    EdgeCurve "EDGE_CURVE" () ["REPRESENTATION_ITEM", "EDGE"] {
+        name: String,
+        edge_start: u64,
+        edge_end: u64,
+        edge_geometry: u64,
+        same_sense: bool,
+    }
+    OrientedEdge "ORIENTED_EDGE" () ["REPRESENTATION_ITEM", "EDGE"] {
+        name: String,
+        edge_start: Derived,
+        edge_end: Derived,
+        edge_element: u64,
+        orientation: bool,
+    }
+    EdgeLoop "EDGE_LOOP" () ["REPRESENTATION_ITEM"] {
+        name: String,
+        edge_list: Vec<u64>,
+    }
+    VertexLoop "VERTEX_LOOP" () ["REPRESENTATION_ITEM"] {
+        name: String,
+        loop_vertex: u64,
+    }
+    FaceBound "FACE_BOUND" ("FACE_OUTER_BOUND") ["REPRESENTATION_ITEM"] {
+        name: String,
+        bound: u64,
+        orientation: bool,
+    }
+    AdvancedFace "ADVANCED_FACE" () ["REPRESENTATION_ITEM", "FACE"] {
+        name: String,
+        bounds: Vec<u64>,
+        face_geometry: u64,
+        same_sense: bool,
+    }
+    ClosedShell "CLOSED_SHELL" () ["REPRESENTATION_ITEM", "CONNECTED_FACE_SET"] {
+        name: String,
+        cfs_faces: Vec<u64>,
+    }
+    OpenShell "OPEN_SHELL" () ["REPRESENTATION_ITEM", "CONNECTED_FACE_SET"] {
+        name: String,
+        cfs_faces: Vec<u64>,
+    }
+    OrientedClosedShell "ORIENTED_CLOSED_SHELL" () ["REPRESENTATION_ITEM", "CONNECTED_FACE_SET"] {
+        name: String,
+        cfs_faces: Derived,
+        closed_shell_element: u64,
+        orientation: bool,
+    }
+    ManifoldSolidBrep "MANIFOLD_SOLID_BREP" ("BREP_WITH_VOIDS") ["REPRESENTATION_ITEM"] {
+        name: String,
+        outer: u64,
+    }
+    BrepWithVoids "BREP_WITH_VOIDS" () ["REPRESENTATION_ITEM", "MANIFOLD_SOLID_BREP"] {
+        name: String,
+        outer: u64,
+        voids: Vec<u64>,
+    }
+    ColourRgb "COLOUR_RGB" () ["COLOUR_SPECIFICATION"] {
+        name: String,
+        red: f64,
+        green: f64,
+        blue: f64,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::stream;
+
+    #[test]
+    fn test_read() {
+        let step = stream::parse(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            DATA;
+            #1=CARTESIAN_POINT('',(0.,1.,2.));
+            #2=AXIS2_PLACEMENT_3D('',#1,$,$);
+            #3=FACE_OUTER_BOUND('',#4,.T.);
+            #4=CARTESIAN_POINT('',(0.,1.));
+            #5=DIRECTION('',(0.,1.,0),'extra');
+            #6=(
+            BOUNDED_CURVE()
+            B_SPLINE_CURVE(1,(#1,#4),.UNSPECIFIED.,.F.,.U.)
+            B_SPLINE_CURVE_WITH_KNOTS((2,2),(0.,1.),.UNSPECIFIED.)
+            CURVE()
+            GEOMETRIC_REPRESENTATION_ITEM()
+            RATIONAL_B_SPLINE_CURVE((1.,2.))
+            REPRESENTATION_ITEM('')
+            );
+            #7=(B_SPLINE_CURVE_WITH_KNOTS((2,2),(0.,1.),.UNSPECIFIED.) REPRESENTATION_ITEM(''));
+            #8=BREP_WITH_VOIDS('',#9,(#10));
+            #9=(
+            BOUNDED_SURFACE()
+            B_SPLINE_SURFACE(1,1,((#1,#4),(#1,#4)),.UNSPECIFIED.,.F.,.F.,.F.)
+            B_SPLINE_SURFACE_WITH_KNOTS((2),(2),(0.),(0.),.UNSPECIFIED.,'extra')
+            GEOMETRIC_REPRESENTATION_ITEM()
+            REPRESENTATION_ITEM('')
+            SURFACE()
+            );
+            ENDSEC;
+            END-ISO-10303-21;").unwrap();
+        let map = DataDB::new(step.data);
+        assert_eq!(read::<CartesianPoint>(&map, 1).unwrap().coordinates, vec![0.0, 1.0, 2.0]);
+        assert_eq!(read::<Axis2Placement3d>(&map, 2).unwrap(), Axis2Placement3d {
+            name: String::new(),
+            location: 1,
+            axis: None,
+            ref_direction: None,
+        });
+        // a subtype
+        assert_eq!(read::<FaceBound>(&map, 3).unwrap().bound, 4);
+        assert_eq!(read::<EdgeLoop>(&map, 3), Err(ParseError::UnsupportedEntity(3, "FACE_OUTER_BOUND".to_owned())));
+        assert_eq!(read::<Direction>(&map, 5), Err(ParseError::InvalidEntity(5, "DIRECTION")));
+        assert_eq!(read::<Direction>(&map, 11), Err(ParseError::MissingReference(11)));
+        // supertypes of a complex instance
+        let curve = read::<BSplineCurveWithKnots>(&map, 6).unwrap();
+        assert_eq!((curve.degree, curve.control_points_list, curve.self_intersect), (1, vec![1, 4], Logical(None)));
+        assert_eq!(read::<RationalBSplineCurve>(&map, 6).unwrap().weights_data, vec![1.0, 2.0]);
+        assert_eq!(read::<BSplineCurveWithKnots>(&map, 7), Err(ParseError::InvalidEntity(7, "B_SPLINE_CURVE_WITH_KNOTS")));
+        assert_eq!(read::<ManifoldSolidBrep>(&map, 8).unwrap().outer, 9);
+        assert_eq!(read::<BrepWithVoids>(&map, 8).unwrap().voids, vec![10]);
+        assert_eq!(read::<BSplineSurfaceWithKnots>(&map, 9), Err(ParseError::InvalidEntity(9, "B_SPLINE_SURFACE_WITH_KNOTS")));
+        assert_eq!(reals("CONICAL_SURFACE"), Some(&[false, false, true, true][..]));
+        assert_eq!(reals("RATIONAL_B_SPLINE_SURFACE").unwrap().iter().rposition(|r| *r), Some(8));
+        assert_eq!(reals("VECTOR"), None);
+    }
+}
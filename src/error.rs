@@ -0,0 +1,233 @@
+use super::preprocess::{PreprocessError, PreprocessErrorInfo};
+use super::parser::ParseError;
+use super::db::DanglingReference;
+use super::writer::WriteError;
+use super::reference::ReferenceError;
+use super::archive::ArchiveError;
+use super::stl::StlError;
+use super::export::ExportError;
+use super::backend::BackendError;
+use std::fmt;
+
+// line and column, both 1-origin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl From<PreprocessErrorInfo> for Span {
+    fn from(info: PreprocessErrorInfo) -> Self {
+        match info {
+            PreprocessErrorInfo::LineCol(p) => Span { start: p, end: p },
+            PreprocessErrorInfo::Span(start, end) => Span { start, end },
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}:{}", self.start.0, self.start.1)
+        }
+        else {
+            write!(f, "{}:{} -- {}:{}", self.start.0, self.start.1, self.end.0, self.end.1)
+        }
+    }
+}
+
+// a problem skipped in the tolerant mode, span and id of the entity if known
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub span: Option<Span>,
+    pub id: Option<u64>,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(span) = self.span {
+            write!(f, "{}: ", span)?;
+        }
+        if let Some(id) = self.id {
+            write!(f, "#{}: ", id)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    // the input cannot be decompressed
+    Archive(String),
+    // the file is not a well-formed ISO 10303-21 exchange structure
+    Syntax(Span),
+    InvalidString(Span),
+    // the file is not a well-formed STL file
+    Stl(String),
+    // a header entity (FILE_NAME, ...) has unexpected arguments
+    InvalidHeader(String),
+    // the file has no entity of this type
+    MissingEntity(String),
+    UnsupportedEntity { id: u64, name: String },
+    // FILE_SCHEMA is not AP203, AP214 or AP242
+    UnsupportedSchema(String),
+    InvalidEntity { id: u64, name: String },
+    // references to entity instances which are not in the file
+    DanglingReferences(Vec<DanglingReference>),
+    MissingReference(u64),
+    // an external reference to another file cannot be resolved
+    Reference(String),
+    NotSquarePipe(String),
+    InvalidConfig(String),
+    // no tool in the library fits a hole or the cut
+    NoTool(String),
+    // a value which cannot be written to a STEP file
+    Unrepresentable(String),
+    Format(fmt::Error),
+    Internal(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Archive(reason) => write!(f, "cannot read archive: {}", reason),
+            Error::Syntax(span) => write!(f, "irregular syntax at {}", span),
+            Error::InvalidString(span) => write!(f, "invalid string escape at {}", span),
+            Error::Stl(reason) => write!(f, "invalid STL: {}", reason),
+            Error::InvalidHeader(name) => write!(f, "invalid header entity {}", name),
+            Error::MissingEntity(name) => write!(f, "no {} found", name),
+            Error::UnsupportedEntity { id, name } => write!(f, "unsupported entity #{} {}", id, name),
+            Error::UnsupportedSchema(name) =>
+                write!(f, "unsupported application protocol {} (only AP203, AP214 and AP242 are supported)", name),
+            Error::InvalidEntity { id, name } => write!(f, "invalid arguments of #{} {}", id, name),
+            Error::DanglingReferences(refs) => {
+                write!(f, "dangling references:")?;
+                for r in refs {
+                    write!(f, "\n  #{} (argument {}) refers to missing #{}", r.from, r.arg, r.to)?;
+                }
+                Ok(())
+            },
+            Error::MissingReference(id) => write!(f, "missing entity #{}", id),
+            Error::Reference(reason) => write!(f, "unresolved reference: {}", reason),
+            Error::NotSquarePipe(reason) => write!(f, "not a square pipe: {}", reason),
+            Error::InvalidConfig(reason) => write!(f, "invalid config: {}", reason),
+            Error::NoTool(reason) => write!(f, "{}", reason),
+            Error::Unrepresentable(what) => write!(f, "cannot write {} to STEP", what),
+            Error::Format(e) => write!(f, "failed to format output: {}", e),
+            Error::Internal(msg) => write!(f, "internal error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Format(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<PreprocessError> for Error {
+    fn from(e: PreprocessError) -> Self {
+        match e {
+            PreprocessError::Fail(info) => Error::Syntax(info.into()),
+            PreprocessError::InvalidString(info, _) => Error::InvalidString(info.into()),
+            PreprocessError::InternalError => Error::Internal("preprocess".to_owned()),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        match e {
+            ParseError::Preprocess(e) => e.into(),
+            ParseError::Reference(e) => e.into(),
+            ParseError::Header(name) => Error::InvalidHeader(name.to_owned()),
+            ParseError::MissingEntity(name) => Error::MissingEntity(name.to_owned()),
+            ParseError::UnsupportedEntity(id, name) => Error::UnsupportedEntity { id, name },
+            ParseError::UnsupportedSchema(name) => Error::UnsupportedSchema(name),
+            ParseError::InvalidEntity(id, name) => Error::InvalidEntity { id, name: name.to_owned() },
+            ParseError::DanglingReferences(refs) => Error::DanglingReferences(refs),
+            ParseError::MissingReference(id) => Error::MissingReference(id),
+        }
+    }
+}
+
+impl From<ReferenceError> for Error {
+    fn from(e: ReferenceError) -> Self {
+        match e {
+            ReferenceError::NotFound(id, uri, reason) => Error::Reference(format!("cannot read <{}> of #{}: {}", uri, id, reason)),
+            ReferenceError::Preprocess(file, e) => Error::Reference(format!("{}: {}", file, Error::from(e))),
+            ReferenceError::MissingAnchor(id, uri) => Error::Reference(format!("no entity anchored at <{}> of #{}", uri, id)),
+            ReferenceError::Cyclic(file) => Error::Reference(format!("{} refers to itself", file)),
+        }
+    }
+}
+
+impl From<ArchiveError> for Error {
+    fn from(e: ArchiveError) -> Self {
+        match e {
+            ArchiveError::Gzip(e) => Error::Archive(format!("gzip: {}", e)),
+            ArchiveError::Zip(e) => Error::Archive(format!("zip: {}", e)),
+            ArchiveError::NoStep(None) => Error::Archive("no STEP file in the archive".to_owned()),
+            ArchiveError::NoStep(Some(name)) => Error::Archive(format!("no STEP file {} in the archive", name)),
+            ArchiveError::Roots(names) =>
+                Error::Archive(format!("STEP files {} in the archive refer to none of the others, choose one with --member", names.join(", "))),
+            ArchiveError::Xml(name) => Error::Archive(format!("{} is ISO 10303-28 XML, which is not supported", name)),
+            ArchiveError::NotUtf8(name) => Error::Archive(format!("{} is not text", name)),
+            ArchiveError::TooLarge(name) => Error::Archive(format!("{} is too large decompressed", name)),
+            ArchiveError::Nested => Error::Archive("gzip in gzip".to_owned()),
+        }
+    }
+}
+
+impl From<StlError> for Error {
+    fn from(e: StlError) -> Self {
+        match e {
+            StlError::Truncated => Error::Stl("truncated binary file".to_owned()),
+            StlError::Syntax(line) => Error::Stl(format!("irregular syntax at line {}", line)),
+            StlError::NonFinite(line) => Error::Stl(format!("non-finite coordinate at line {}", line)),
+        }
+    }
+}
+
+impl From<ExportError> for Error {
+    fn from(e: ExportError) -> Self {
+        match e {
+            ExportError::Resolution(r) => Error::InvalidConfig(format!("resolution {} is not positive or too fine for the stock", r)),
+        }
+    }
+}
+
+impl From<BackendError> for Error {
+    fn from(e: BackendError) -> Self {
+        match e {
+            BackendError::Format(e) => Error::Format(e),
+            e => Error::NoTool(e.to_string()),
+        }
+    }
+}
+
+impl From<WriteError> for Error {
+    fn from(e: WriteError) -> Self {
+        match e {
+            WriteError::NonFiniteFloat(f) => Error::Unrepresentable(format!("float {}", f)),
+            WriteError::InvalidName(name) => Error::Unrepresentable(format!("name {:?}", name)),
+            WriteError::Format(e) => Error::Format(e),
+        }
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(e: fmt::Error) -> Self {
+        Error::Format(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::InvalidConfig(e.to_string())
+    }
+}
@@ -0,0 +1,278 @@
+// ISO 10303-21 string encoding.
+// Strings in a STEP file are restricted to printable ASCII, everything else is
+// written with control directives:
+//   ''                  apostrophe
+//   \\                  reverse solidus
+//   \X\HH               ISO 8859-1 character
+//   \S\c                character c + 0x80 of the current ISO 8859 code page
+//   \P?\                switch the code page (A = ISO 8859-1 ... I = ISO 8859-9)
+//   \X2\HHHH...\X0\     UCS-2 (UTF-16) characters
+//   \X4\HHHHHHHH...\X0\ UCS-4 characters
+
+#[derive(Debug, PartialEq)]
+pub enum EscapeError {
+    // byte offset of the directive in the undecoded string
+    InvalidHex(usize),
+    Unterminated(usize),
+    InvalidCodePoint(usize),
+}
+
+fn hex(s: &str, pos: usize) -> Result<u32, EscapeError> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(EscapeError::InvalidHex(pos));
+    }
+    u32::from_str_radix(s, 16).map_err(|_| EscapeError::InvalidHex(pos))
+}
+
+// returns hex digits between the directive and \X0\, and the rest of string
+fn hex_run(s: &str, pos: usize) -> Result<(&str, &str), EscapeError> {
+    let end = s.find("\\X0\\").ok_or(EscapeError::Unterminated(pos))?;
+    Ok((&s[..end], &s[end + 4..]))
+}
+
+fn code_page(page: u8) -> Option<&'static [u16; 96]> {
+    match page {
+        b'B' => Some(&LATIN_2),
+        b'C' => Some(&LATIN_3),
+        b'D' => Some(&LATIN_4),
+        b'E' => Some(&CYRILLIC),
+        b'F' => Some(&ARABIC),
+        b'G' => Some(&GREEK),
+        b'H' => Some(&HEBREW),
+        b'I' => Some(&LATIN_5),
+        _ => None,
+    }
+}
+
+pub fn decode(s: &str) -> Result<String, EscapeError> {
+    let mut decoded = String::with_capacity(s.len());
+    // None means ISO 8859-1, the default code page
+    let mut page: Option<&'static [u16; 96]> = None;
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let pos = s.len() - rest.len();
+        let bytes = rest.as_bytes();
+        if rest.starts_with("''") {
+            decoded.push('\'');
+            rest = &rest[2..];
+        }
+        else if rest.starts_with("\\\\") {
+            decoded.push('\\');
+            rest = &rest[2..];
+        }
+        else if rest.starts_with("\\X2\\") {
+            let (run, remain) = hex_run(&rest[4..], pos)?;
+            // slices of other characters would not fall on char boundaries
+            if !run.is_ascii() || run.len() % 4 != 0 {
+                return Err(EscapeError::InvalidHex(pos));
+            }
+            let units = (0..run.len() / 4)
+                .map(|i| hex(&run[i * 4..i * 4 + 4], pos).map(|u| u as u16))
+                .collect::<Result<Vec<u16>, EscapeError>>()?;
+            for c in std::char::decode_utf16(units) {
+                decoded.push(c.map_err(|_| EscapeError::InvalidCodePoint(pos))?);
+            }
+            rest = remain;
+        }
+        else if rest.starts_with("\\X4\\") {
+            let (run, remain) = hex_run(&rest[4..], pos)?;
+            // slices of other characters would not fall on char boundaries
+            if !run.is_ascii() || run.len() % 8 != 0 {
+                return Err(EscapeError::InvalidHex(pos));
+            }
+            for i in 0..run.len() / 8 {
+                let code = hex(&run[i * 8..i * 8 + 8], pos)?;
+                decoded.push(std::char::from_u32(code).ok_or(EscapeError::InvalidCodePoint(pos))?);
+            }
+            rest = remain;
+        }
+        else if rest.starts_with("\\X\\") {
+            let code = hex(rest.get(3..5).ok_or(EscapeError::InvalidHex(pos))?, pos)?;
+            decoded.push(std::char::from_u32(code).ok_or(EscapeError::InvalidCodePoint(pos))?);
+            rest = &rest[5..];
+        }
+        else if rest.starts_with("\\S\\") {
+            let c = *bytes.get(3).ok_or(EscapeError::Unterminated(pos))?;
+            if !(0x20..0x7f).contains(&c) {
+                return Err(EscapeError::InvalidCodePoint(pos));
+            }
+            let code = match page {
+                Some(table) => table[c as usize - 0x20] as u32,
+                None => c as u32 + 0x80,
+            };
+            decoded.push(std::char::from_u32(code).ok_or(EscapeError::InvalidCodePoint(pos))?);
+            rest = &rest[4..];
+        }
+        else if bytes.len() >= 4 && bytes[0] == b'\\' && bytes[1] == b'P' && bytes[3] == b'\\' && (b'A'..=b'I').contains(&bytes[2]) {
+            page = code_page(bytes[2]);
+            rest = &rest[4..];
+        }
+        else {
+            // a reverse solidus which does not start a directive is kept as is,
+            // some exporters do not escape it in file paths
+            decoded.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Ok(decoded)
+}
+
+// inverse of decode, characters out of printable ASCII are written as \X2\ runs
+// (\X4\ for characters beyond the basic multilingual plane)
+pub fn encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => encoded.push_str("''"),
+            '\\' => encoded.push_str("\\\\"),
+            ' '..='~' => encoded.push(c),
+            _ => {
+                let wide = c as u32 > 0xFFFF;
+                encoded.push_str(if wide { "\\X4\\" } else { "\\X2\\" });
+                let mut c = c;
+                loop {
+                    if wide {
+                        encoded.push_str(&format!("{:08X}", c as u32));
+                    }
+                    else {
+                        encoded.push_str(&format!("{:04X}", c as u32));
+                    }
+                    match chars.peek() {
+                        Some(next) if !(' '..='~').contains(next) && (*next as u32 > 0xFFFF) == wide => {
+                            c = *next;
+                            chars.next();
+                        },
+                        _ => break,
+                    }
+                }
+                encoded.push_str("\\X0\\");
+            },
+        }
+    }
+    encoded
+}
+
+// upper halves (0xA0 - 0xFF) of ISO 8859-2 to 8859-9
+const LATIN_2: [u16; 96] = [
+    0x00A0, 0x0104, 0x02D8, 0x0141, 0x00A4, 0x013D, 0x015A, 0x00A7, 0x00A8, 0x0160, 0x015E, 0x0164,
+    0x0179, 0x00AD, 0x017D, 0x017B, 0x00B0, 0x0105, 0x02DB, 0x0142, 0x00B4, 0x013E, 0x015B, 0x02C7,
+    0x00B8, 0x0161, 0x015F, 0x0165, 0x017A, 0x02DD, 0x017E, 0x017C, 0x0154, 0x00C1, 0x00C2, 0x0102,
+    0x00C4, 0x0139, 0x0106, 0x00C7, 0x010C, 0x00C9, 0x0118, 0x00CB, 0x011A, 0x00CD, 0x00CE, 0x010E,
+    0x0110, 0x0143, 0x0147, 0x00D3, 0x00D4, 0x0150, 0x00D6, 0x00D7, 0x0158, 0x016E, 0x00DA, 0x0170,
+    0x00DC, 0x00DD, 0x0162, 0x00DF, 0x0155, 0x00E1, 0x00E2, 0x0103, 0x00E4, 0x013A, 0x0107, 0x00E7,
+    0x010D, 0x00E9, 0x0119, 0x00EB, 0x011B, 0x00ED, 0x00EE, 0x010F, 0x0111, 0x0144, 0x0148, 0x00F3,
+    0x00F4, 0x0151, 0x00F6, 0x00F7, 0x0159, 0x016F, 0x00FA, 0x0171, 0x00FC, 0x00FD, 0x0163, 0x02D9,
+];
+const LATIN_3: [u16; 96] = [
+    0x00A0, 0x0126, 0x02D8, 0x00A3, 0x00A4, 0xFFFD, 0x0124, 0x00A7, 0x00A8, 0x0130, 0x015E, 0x011E,
+    0x0134, 0x00AD, 0xFFFD, 0x017B, 0x00B0, 0x0127, 0x00B2, 0x00B3, 0x00B4, 0x00B5, 0x0125, 0x00B7,
+    0x00B8, 0x0131, 0x015F, 0x011F, 0x0135, 0x00BD, 0xFFFD, 0x017C, 0x00C0, 0x00C1, 0x00C2, 0xFFFD,
+    0x00C4, 0x010A, 0x0108, 0x00C7, 0x00C8, 0x00C9, 0x00CA, 0x00CB, 0x00CC, 0x00CD, 0x00CE, 0x00CF,
+    0xFFFD, 0x00D1, 0x00D2, 0x00D3, 0x00D4, 0x0120, 0x00D6, 0x00D7, 0x011C, 0x00D9, 0x00DA, 0x00DB,
+    0x00DC, 0x016C, 0x015C, 0x00DF, 0x00E0, 0x00E1, 0x00E2, 0xFFFD, 0x00E4, 0x010B, 0x0109, 0x00E7,
+    0x00E8, 0x00E9, 0x00EA, 0x00EB, 0x00EC, 0x00ED, 0x00EE, 0x00EF, 0xFFFD, 0x00F1, 0x00F2, 0x00F3,
+    0x00F4, 0x0121, 0x00F6, 0x00F7, 0x011D, 0x00F9, 0x00FA, 0x00FB, 0x00FC, 0x016D, 0x015D, 0x02D9,
+];
+const LATIN_4: [u16; 96] = [
+    0x00A0, 0x0104, 0x0138, 0x0156, 0x00A4, 0x0128, 0x013B, 0x00A7, 0x00A8, 0x0160, 0x0112, 0x0122,
+    0x0166, 0x00AD, 0x017D, 0x00AF, 0x00B0, 0x0105, 0x02DB, 0x0157, 0x00B4, 0x0129, 0x013C, 0x02C7,
+    0x00B8, 0x0161, 0x0113, 0x0123, 0x0167, 0x014A, 0x017E, 0x014B, 0x0100, 0x00C1, 0x00C2, 0x00C3,
+    0x00C4, 0x00C5, 0x00C6, 0x012E, 0x010C, 0x00C9, 0x0118, 0x00CB, 0x0116, 0x00CD, 0x00CE, 0x012A,
+    0x0110, 0x0145, 0x014C, 0x0136, 0x00D4, 0x00D5, 0x00D6, 0x00D7, 0x00D8, 0x0172, 0x00DA, 0x00DB,
+    0x00DC, 0x0168, 0x016A, 0x00DF, 0x0101, 0x00E1, 0x00E2, 0x00E3, 0x00E4, 0x00E5, 0x00E6, 0x012F,
+    0x010D, 0x00E9, 0x0119, 0x00EB, 0x0117, 0x00ED, 0x00EE, 0x012B, 0x0111, 0x0146, 0x014D, 0x0137,
+    0x00F4, 0x00F5, 0x00F6, 0x00F7, 0x00F8, 0x0173, 0x00FA, 0x00FB, 0x00FC, 0x0169, 0x016B, 0x02D9,
+];
+const CYRILLIC: [u16; 96] = [
+    0x00A0, 0x0401, 0x0402, 0x0403, 0x0404, 0x0405, 0x0406, 0x0407, 0x0408, 0x0409, 0x040A, 0x040B,
+    0x040C, 0x00AD, 0x040E, 0x040F, 0x0410, 0x0411, 0x0412, 0x0413, 0x0414, 0x0415, 0x0416, 0x0417,
+    0x0418, 0x0419, 0x041A, 0x041B, 0x041C, 0x041D, 0x041E, 0x041F, 0x0420, 0x0421, 0x0422, 0x0423,
+    0x0424, 0x0425, 0x0426, 0x0427, 0x0428, 0x0429, 0x042A, 0x042B, 0x042C, 0x042D, 0x042E, 0x042F,
+    0x0430, 0x0431, 0x0432, 0x0433, 0x0434, 0x0435, 0x0436, 0x0437, 0x0438, 0x0439, 0x043A, 0x043B,
+    0x043C, 0x043D, 0x043E, 0x043F, 0x0440, 0x0441, 0x0442, 0x0443, 0x0444, 0x0445, 0x0446, 0x0447,
+    0x0448, 0x0449, 0x044A, 0x044B, 0x044C, 0x044D, 0x044E, 0x044F, 0x2116, 0x0451, 0x0452, 0x0453,
+    0x0454, 0x0455, 0x0456, 0x0457, 0x0458, 0x0459, 0x045A, 0x045B, 0x045C, 0x00A7, 0x045E, 0x045F,
+];
+const ARABIC: [u16; 96] = [
+    0x00A0, 0xFFFD, 0xFFFD, 0xFFFD, 0x00A4, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD,
+    0x060C, 0x00AD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD,
+    0xFFFD, 0xFFFD, 0xFFFD, 0x061B, 0xFFFD, 0xFFFD, 0xFFFD, 0x061F, 0xFFFD, 0x0621, 0x0622, 0x0623,
+    0x0624, 0x0625, 0x0626, 0x0627, 0x0628, 0x0629, 0x062A, 0x062B, 0x062C, 0x062D, 0x062E, 0x062F,
+    0x0630, 0x0631, 0x0632, 0x0633, 0x0634, 0x0635, 0x0636, 0x0637, 0x0638, 0x0639, 0x063A, 0xFFFD,
+    0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0x0640, 0x0641, 0x0642, 0x0643, 0x0644, 0x0645, 0x0646, 0x0647,
+    0x0648, 0x0649, 0x064A, 0x064B, 0x064C, 0x064D, 0x064E, 0x064F, 0x0650, 0x0651, 0x0652, 0xFFFD,
+    0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD,
+];
+const GREEK: [u16; 96] = [
+    0x00A0, 0x2018, 0x2019, 0x00A3, 0x20AC, 0x20AF, 0x00A6, 0x00A7, 0x00A8, 0x00A9, 0x037A, 0x00AB,
+    0x00AC, 0x00AD, 0xFFFD, 0x2015, 0x00B0, 0x00B1, 0x00B2, 0x00B3, 0x0384, 0x0385, 0x0386, 0x00B7,
+    0x0388, 0x0389, 0x038A, 0x00BB, 0x038C, 0x00BD, 0x038E, 0x038F, 0x0390, 0x0391, 0x0392, 0x0393,
+    0x0394, 0x0395, 0x0396, 0x0397, 0x0398, 0x0399, 0x039A, 0x039B, 0x039C, 0x039D, 0x039E, 0x039F,
+    0x03A0, 0x03A1, 0xFFFD, 0x03A3, 0x03A4, 0x03A5, 0x03A6, 0x03A7, 0x03A8, 0x03A9, 0x03AA, 0x03AB,
+    0x03AC, 0x03AD, 0x03AE, 0x03AF, 0x03B0, 0x03B1, 0x03B2, 0x03B3, 0x03B4, 0x03B5, 0x03B6, 0x03B7,
+    0x03B8, 0x03B9, 0x03BA, 0x03BB, 0x03BC, 0x03BD, 0x03BE, 0x03BF, 0x03C0, 0x03C1, 0x03C2, 0x03C3,
+    0x03C4, 0x03C5, 0x03C6, 0x03C7, 0x03C8, 0x03C9, 0x03CA, 0x03CB, 0x03CC, 0x03CD, 0x03CE, 0xFFFD,
+];
+const HEBREW: [u16; 96] = [
+    0x00A0, 0xFFFD, 0x00A2, 0x00A3, 0x00A4, 0x00A5, 0x00A6, 0x00A7, 0x00A8, 0x00A9, 0x00D7, 0x00AB,
+    0x00AC, 0x00AD, 0x00AE, 0x00AF, 0x00B0, 0x00B1, 0x00B2, 0x00B3, 0x00B4, 0x00B5, 0x00B6, 0x00B7,
+    0x00B8, 0x00B9, 0x00F7, 0x00BB, 0x00BC, 0x00BD, 0x00BE, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD,
+    0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD,
+    0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD, 0xFFFD,
+    0xFFFD, 0xFFFD, 0xFFFD, 0x2017, 0x05D0, 0x05D1, 0x05D2, 0x05D3, 0x05D4, 0x05D5, 0x05D6, 0x05D7,
+    0x05D8, 0x05D9, 0x05DA, 0x05DB, 0x05DC, 0x05DD, 0x05DE, 0x05DF, 0x05E0, 0x05E1, 0x05E2, 0x05E3,
+    0x05E4, 0x05E5, 0x05E6, 0x05E7, 0x05E8, 0x05E9, 0x05EA, 0xFFFD, 0xFFFD, 0x200E, 0x200F, 0xFFFD,
+];
+const LATIN_5: [u16; 96] = [
+    0x00A0, 0x00A1, 0x00A2, 0x00A3, 0x00A4, 0x00A5, 0x00A6, 0x00A7, 0x00A8, 0x00A9, 0x00AA, 0x00AB,
+    0x00AC, 0x00AD, 0x00AE, 0x00AF, 0x00B0, 0x00B1, 0x00B2, 0x00B3, 0x00B4, 0x00B5, 0x00B6, 0x00B7,
+    0x00B8, 0x00B9, 0x00BA, 0x00BB, 0x00BC, 0x00BD, 0x00BE, 0x00BF, 0x00C0, 0x00C1, 0x00C2, 0x00C3,
+    0x00C4, 0x00C5, 0x00C6, 0x00C7, 0x00C8, 0x00C9, 0x00CA, 0x00CB, 0x00CC, 0x00CD, 0x00CE, 0x00CF,
+    0x011E, 0x00D1, 0x00D2, 0x00D3, 0x00D4, 0x00D5, 0x00D6, 0x00D7, 0x00D8, 0x00D9, 0x00DA, 0x00DB,
+    0x00DC, 0x0130, 0x015E, 0x00DF, 0x00E0, 0x00E1, 0x00E2, 0x00E3, 0x00E4, 0x00E5, 0x00E6, 0x00E7,
+    0x00E8, 0x00E9, 0x00EA, 0x00EB, 0x00EC, 0x00ED, 0x00EE, 0x00EF, 0x011F, 0x00F1, 0x00F2, 0x00F3,
+    0x00F4, 0x00F5, 0x00F6, 0x00F7, 0x00F8, 0x00F9, 0x00FA, 0x00FB, 0x00FC, 0x0131, 0x015F, 0x00FF,
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("Part1"), Ok("Part1".to_owned()));
+        assert_eq!(decode("it''s"), Ok("it's".to_owned()));
+        assert_eq!(decode(r"D:\\Desktop\\Part1.stp"), Ok(r"D:\Desktop\Part1.stp".to_owned()));
+        assert_eq!(decode(r"\X2\30BD30EA30C330C9\X0\1"), Ok("ソリッド1".to_owned()));
+        assert_eq!(decode(r"\X2\D83DDE00\X0\"), Ok("\u{1F600}".to_owned()));
+        assert_eq!(decode(r"\X4\0001F600\X0\"), Ok("\u{1F600}".to_owned()));
+        assert_eq!(decode(r"\X\E9t\X\E9"), Ok("été".to_owned()));
+        assert_eq!(decode(r"\S\i"), Ok("é".to_owned()));
+        assert_eq!(decode(r"\PE\\S\?\S\_\PA\\S\i"), Ok("Ппé".to_owned()));
+        assert_eq!(decode(r"C:\temp"), Ok(r"C:\temp".to_owned()));
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("it's"), "it''s");
+        assert_eq!(encode(r"D:\Part1.stp"), r"D:\\Part1.stp");
+        assert_eq!(encode("ソリッド1"), r"\X2\30BD30EA30C330C9\X0\1");
+        assert_eq!(encode("été\u{1F600}"), r"\X2\00E9\X0\t\X2\00E9\X0\\X4\0001F600\X0\");
+        assert_eq!(encode("a\nb"), r"a\X2\000A\X0\b");
+        for s in &["", "it's", r"C:\temp", "ソリッド1", "Ппé\u{1F600}x"] {
+            assert_eq!(decode(&encode(s)).as_deref(), Ok(*s));
+        }
+    }
+
+    #[test]
+    fn test_decode_error() {
+        assert_eq!(decode(r"ab\X2\30BD"), Err(EscapeError::Unterminated(2)));
+        assert_eq!(decode(r"\X2\30B\X0\"), Err(EscapeError::InvalidHex(0)));
+        assert_eq!(decode(r"\X2\30GD\X0\"), Err(EscapeError::InvalidHex(0)));
+        assert_eq!(decode(r"\X2\D83D\X0\"), Err(EscapeError::InvalidCodePoint(0)));
+        assert_eq!(decode(r"\X\4"), Err(EscapeError::InvalidHex(0)));
+        assert_eq!(decode(r"\X2\abcéxyz\X0\"), Err(EscapeError::InvalidHex(0)));
+        assert_eq!(decode(r"a\X4\0001F60é0001F60\X0\"), Err(EscapeError::InvalidHex(1)));
+    }
+}
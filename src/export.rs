@@ -0,0 +1,413 @@
+use super::analysis::Proc;
+use super::backend::{CNCConfig, Removal, removals};
+use super::math::V3;
+
+// Meshes of the analysis for visual checks, in the coordinates of the model.
+// The result of machining is simulated in voxels of the stock.
+
+const CYLINDER_SEGMENTS: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum ExportError {
+    // the resolution is not positive or too fine for the stock
+    Resolution(f64),
+}
+
+// at most 2^28 voxels, 2^24 unless the resolution is given
+const MAX_VOXELS: f64 = 268_435_456.0;
+const DEFAULT_VOXELS: f64 = 16_777_216.0;
+
+// center of the end of the pipe, positions are relative to it along the axes of Proc
+struct Frame<'a> {
+    proc: &'a Proc,
+    origin: V3,
+}
+
+impl<'a> Frame<'a> {
+    fn new(proc: &'a Proc) -> Self {
+        let (x, y, z) = &proc.axes;
+        let c = &proc.center;
+        let origin = x.scale(c.x()).add(&y.scale(c.y())).add(&z.scale(c.z()));
+        Frame { proc, origin }
+    }
+
+    // from the coordinates along the axes to the model
+    fn local(&self, p: [f64; 3]) -> V3 {
+        let (x, y, z) = &self.proc.axes;
+        let aligned = x.scale(p[0]).add(&y.scale(p[1])).add(&z.scale(p[2]));
+        self.model(&aligned)
+    }
+
+    // from the coordinates relative to the origin to the model
+    fn model(&self, p: &V3) -> V3 {
+        self.proc.rotation.transpose().prod_vec(&self.origin.add(p))
+    }
+}
+
+// corners of a face of a box, counterclockwise seen from outside
+fn box_face(lo: [f64; 3], hi: [f64; 3], k: usize, positive: bool) -> [[f64; 3]; 4] {
+    let (e1, e2) = ((k + 1) % 3, (k + 2) % 3);
+    let corner = |u: bool, v: bool| {
+        let mut p = lo;
+        if positive {
+            p[k] = hi[k];
+        }
+        if u {
+            p[e1] = hi[e1];
+        }
+        if v {
+            p[e2] = hi[e2];
+        }
+        p
+    };
+    let face = [corner(false, false), corner(true, false), corner(true, true), corner(false, true)];
+    if positive { face } else { [face[0], face[3], face[2], face[1]] }
+}
+
+fn quad(frame: &Frame, corners: [[f64; 3]; 4]) -> [[V3; 3]; 2] {
+    let [a, b, c, d] = corners.map(|p| frame.local(p));
+    [[a.clone(), b, c.clone()], [a, c, d]]
+}
+
+// the tube of Proc.section with sharp corners
+pub fn stock(proc: &Proc) -> Vec<[V3; 3]> {
+    let frame = Frame::new(proc);
+    let (xs, ys, l) = (&proc.section.x, &proc.section.y, proc.size.z());
+    let outer = ([xs[0], ys[0], 0.0], [xs[3], ys[3], l]);
+    let inner = ([xs[1], ys[1], 0.0], [xs[2], ys[2], l]);
+    let mut faces = Vec::new();
+    for k in 0..2 {
+        for &positive in &[false, true] {
+            faces.push(box_face(outer.0, outer.1, k, positive));
+            // facing the cavity
+            let [a, b, c, d] = box_face(inner.0, inner.1, k, positive);
+            faces.push([a, d, c, b]);
+        }
+    }
+    // rings at the ends between the corners counterclockwise seen from +z
+    let corners = |(lo, hi): ([f64; 3], [f64; 3]), z: f64| [[lo[0], lo[1], z], [hi[0], lo[1], z], [hi[0], hi[1], z], [lo[0], hi[1], z]];
+    for i in 0..4 {
+        let j = (i + 1) % 4;
+        let (o, n) = (corners(outer, l), corners(inner, l));
+        faces.push([o[i], o[j], n[j], n[i]]);
+        let (o, n) = (corners(outer, 0.0), corners(inner, 0.0));
+        faces.push([o[i], n[i], n[j], o[j]]);
+    }
+    faces.into_iter().flat_map(|face| quad(&frame, face).to_vec()).collect()
+}
+
+fn cylinder(frame: &Frame, base: &V3, direction: &V3, r: f64, length: f64) -> Vec<[V3; 3]> {
+    let e1 = direction.perpendicular();
+    let e2 = direction.cross(&e1);
+    let p = |i: usize, t: f64| {
+        let phi = 2.0 * std::f64::consts::PI * (i % CYLINDER_SEGMENTS) as f64 / CYLINDER_SEGMENTS as f64;
+        frame.model(&base.add(&direction.scale(t)).add(&e1.scale(r * phi.cos())).add(&e2.scale(r * phi.sin())))
+    };
+    let bottom = frame.model(base);
+    let top = frame.model(&base.add(&direction.scale(length)));
+    let mut triangles = Vec::new();
+    for i in 0..CYLINDER_SEGMENTS {
+        triangles.push([p(i, 0.0), p(i + 1, 0.0), p(i + 1, length)]);
+        triangles.push([p(i, 0.0), p(i + 1, length), p(i, length)]);
+        triangles.push([bottom.clone(), p(i + 1, 0.0), p(i, 0.0)]);
+        triangles.push([top.clone(), p(i, length), p(i + 1, length)]);
+    }
+    triangles
+}
+
+// a cylinder at each base and the box swept between them
+fn slot(frame: &Frame, bases: &[V3; 2], direction: &V3, r: f64, length: f64) -> Vec<[V3; 3]> {
+    let mut triangles = cylinder(frame, &bases[0], direction, r, length);
+    triangles.extend(cylinder(frame, &bases[1], direction, r, length));
+    let s = bases[1].sub(&bases[0]);
+    let u = s.normalize();
+    let side = direction.cross(&u);
+    // u, side and direction are right-handed
+    let model = |p: [f64; 3]| frame.model(&bases[0].add(&u.scale(p[0])).add(&side.scale(p[1])).add(&direction.scale(p[2])));
+    let (lo, hi) = ([0.0, -r, 0.0], [s.norm(), r, length]);
+    for k in 1..3 {
+        for &positive in &[false, true] {
+            let [a, b, c, d] = box_face(lo, hi, k, positive).map(model);
+            triangles.push([a.clone(), b, c.clone()]);
+            triangles.push([a, c, d]);
+        }
+    }
+    triangles
+}
+
+// the tools of the drilling and slot jobs
+pub fn drills(proc: &Proc, cfg: &CNCConfig) -> Vec<[V3; 3]> {
+    let frame = Frame::new(proc);
+    removals(proc, cfg)
+        .iter()
+        .flat_map(|removal| match removal {
+            Removal::Cylinder { base, direction, r, length } => cylinder(&frame, base, direction, *r, *length),
+            Removal::Slot { bases, direction, r, length } => slot(&frame, bases, direction, *r, *length),
+            Removal::Slab(_, _) => Vec::new(),
+        })
+        .collect()
+}
+
+// a cylinder is swept from its base to itself
+fn swept(removal: &Removal) -> Option<([&V3; 2], &V3, f64, f64)> {
+    match removal {
+        Removal::Cylinder { base, direction, r, length } => Some(([base, base], direction, *r, *length)),
+        Removal::Slot { bases, direction, r, length } => Some(([&bases[0], &bases[1]], direction, *r, *length)),
+        Removal::Slab(_, _) => None,
+    }
+}
+
+struct Voxels {
+    lo: [f64; 3],
+    n: [usize; 3],
+    resolution: f64,
+    solid: Vec<bool>,
+}
+
+impl Voxels {
+    fn index(&self, i: [usize; 3]) -> usize {
+        (i[2] * self.n[1] + i[1]) * self.n[0] + i[0]
+    }
+
+    fn center(&self, i: [usize; 3]) -> [f64; 3] {
+        [0, 1, 2].map(|k| self.lo[k] + (i[k] as f64 + 0.5) * self.resolution)
+    }
+
+    // indices of voxels whose centers may be in lo..hi
+    fn range(&self, lo: [f64; 3], hi: [f64; 3]) -> [std::ops::Range<usize>; 3] {
+        [0, 1, 2].map(|k| {
+            let i = ((lo[k] - self.lo[k]) / self.resolution - 0.5).floor().max(0.0) as usize;
+            let j = ((hi[k] - self.lo[k]) / self.resolution + 0.5).ceil().max(0.0) as usize;
+            i.min(self.n[k])..j.min(self.n[k])
+        })
+    }
+
+    fn remove(&mut self, proc: &Proc, removal: &Removal) {
+        let (x, y, z) = &proc.axes;
+        let (lo, hi) = match (removal, swept(removal)) {
+            (_, Some((bases, direction, r, length))) => {
+                let tops = bases.map(|base| base.add(&direction.scale(length)));
+                // bounds along the axes, loose by the radius
+                let along = |v: &V3| {
+                    let ts = [bases[0].dot(v), bases[1].dot(v), tops[0].dot(v), tops[1].dot(v)];
+                    let lo = ts.iter().cloned().fold(f64::INFINITY, f64::min);
+                    let hi = ts.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                    (lo - r, hi + r)
+                };
+                let (ax, ay, az) = (along(x), along(y), along(z));
+                ([ax.0, ay.0, az.0], [ax.1, ay.1, az.1])
+            },
+            (Removal::Slab(from, to), _) => ([f64::NEG_INFINITY, f64::NEG_INFINITY, *from], [f64::INFINITY, f64::INFINITY, *to]),
+            _ => return,
+        };
+        let [ri, rj, rk] = self.range(lo, hi);
+        for k in rk {
+            for j in rj.clone() {
+                for i in ri.clone() {
+                    let c = self.center([i, j, k]);
+                    let inside = match (removal, swept(removal)) {
+                        (_, Some((bases, direction, r, length))) => {
+                            let q = x.scale(c[0]).add(&y.scale(c[1])).add(&z.scale(c[2])).sub(bases[0]);
+                            let t = q.dot(direction);
+                            let q = q.sub(&direction.scale(t));
+                            // nearest on the way between the bases
+                            let s = bases[1].sub(bases[0]);
+                            let w = if s.norm() > 0.0 { (q.dot(&s) / s.dot(&s)).clamp(0.0, 1.0) } else { 0.0 };
+                            t >= 0.0 && t <= length && q.sub(&s.scale(w)).norm() <= r
+                        },
+                        (Removal::Slab(from, to), _) => c[2] >= *from && c[2] <= *to,
+                        _ => false,
+                    };
+                    if inside {
+                        let index = self.index([i, j, k]);
+                        self.solid[index] = false;
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_solid(&self, i: [usize; 3], k: usize, step: isize) -> bool {
+        let mut i = i;
+        let j = i[k] as isize + step;
+        if j < 0 || j >= self.n[k] as isize {
+            return false;
+        }
+        i[k] = j as usize;
+        self.solid[self.index(i)]
+    }
+
+    // voxels of the layer along k over the other axes, from index 0 of them
+    fn layer(&self, k: usize, layer: usize, u: usize, v: usize) -> [usize; 3] {
+        let mut i = [0; 3];
+        i[k] = layer;
+        i[(k + 1) % 3] = u;
+        i[(k + 2) % 3] = v;
+        i
+    }
+
+    // faces between solid and empty voxels, coplanar ones merged into rectangles
+    fn boundary(&self, frame: &Frame) -> Vec<[V3; 3]> {
+        let mut triangles = Vec::new();
+        for k in 0..3 {
+            let (n1, n2) = (self.n[(k + 1) % 3], self.n[(k + 2) % 3]);
+            for &positive in &[false, true] {
+                for layer in 0..self.n[k] {
+                    let mut faces = vec![false; n1 * n2];
+                    for v in 0..n2 {
+                        for u in 0..n1 {
+                            let i = self.layer(k, layer, u, v);
+                            faces[v * n1 + u] = self.solid[self.index(i)] && !self.is_solid(i, k, if positive { 1 } else { -1 });
+                        }
+                    }
+                    // as wide and then as high as the faces go
+                    for v in 0..n2 {
+                        let mut u = 0;
+                        while u < n1 {
+                            if !faces[v * n1 + u] {
+                                u += 1;
+                                continue;
+                            }
+                            let w = (u..n1).take_while(|&x| faces[v * n1 + x]).count();
+                            let h = (v..n2).take_while(|&y| (u..u + w).all(|x| faces[y * n1 + x])).count();
+                            for y in v..v + h {
+                                faces[y * n1 + u..y * n1 + u + w].iter_mut().for_each(|face| *face = false);
+                            }
+                            let i = self.layer(k, layer, u, v);
+                            let lo = [0, 1, 2].map(|a| self.lo[a] + i[a] as f64 * self.resolution);
+                            let mut hi = lo.map(|x| x + self.resolution);
+                            hi[(k + 1) % 3] = lo[(k + 1) % 3] + w as f64 * self.resolution;
+                            hi[(k + 2) % 3] = lo[(k + 2) % 3] + h as f64 * self.resolution;
+                            triangles.extend_from_slice(&quad(frame, box_face(lo, hi, k, positive)));
+                            u += w;
+                        }
+                    }
+                }
+            }
+        }
+        triangles
+    }
+}
+
+// the stock after all jobs, the stock is longer than the box when the ends are cut off,
+// without a resolution it is about as fine as DEFAULT_VOXELS allows
+pub fn simulate(proc: &Proc, cfg: &CNCConfig, resolution: Option<f64>) -> Result<Vec<[V3; 3]>, ExportError> {
+    let removals = removals(proc, cfg);
+    let s = &proc.size;
+    let mut lo = [-s.x() / 2.0, -s.y() / 2.0, 0.0];
+    let mut hi = [s.x() / 2.0, s.y() / 2.0, s.z()];
+    for removal in &removals {
+        if let Removal::Slab(from, to) = removal {
+            lo[2] = lo[2].min(*from);
+            hi[2] = hi[2].max(*to);
+        }
+    }
+    // whole voxels across the thinner wall
+    let resolution = resolution.unwrap_or_else(|| {
+        let fine = ((0..3).map(|k| hi[k] - lo[k]).product::<f64>() / DEFAULT_VOXELS).cbrt();
+        let (tx, ty) = proc.section.thickness();
+        let t = tx.min(ty);
+        let aligned = t / (t / fine).floor().max(1.0);
+        if (fine / aligned).powi(3) * DEFAULT_VOXELS > MAX_VOXELS { fine } else { aligned }
+    });
+    let n = [0, 1, 2].map(|k| ((hi[k] - lo[k]) / resolution).ceil());
+    if resolution <= 0.0 || resolution.is_nan() || n.iter().product::<f64>() > MAX_VOXELS {
+        return Err(ExportError::Resolution(resolution));
+    }
+    let n = n.map(|x| x.max(1.0) as usize);
+    let mut voxels = Voxels { lo, n, resolution, solid: vec![true; n[0] * n[1] * n[2]] };
+    // the cavity
+    let (xs, ys) = (&proc.section.x, &proc.section.y);
+    for k in 0..n[2] {
+        for j in 0..n[1] {
+            for i in 0..n[0] {
+                let c = voxels.center([i, j, k]);
+                if c[0] > xs[1] && c[0] < xs[2] && c[1] > ys[1] && c[1] < ys[2] {
+                    let index = voxels.index([i, j, k]);
+                    voxels.solid[index] = false;
+                }
+            }
+        }
+    }
+    for removal in &removals {
+        voxels.remove(proc, removal);
+    }
+    Ok(voxels.boundary(&Frame::new(proc)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::analysis::{Drill, Hole, Section, Slot};
+    use super::super::backend::test::{cfg, tube};
+    use super::super::math::Mat3x3;
+
+    // by the divergence theorem
+    fn volume(triangles: &[[V3; 3]]) -> f64 {
+        triangles.iter().map(|[a, b, c]| a.dot(&b.cross(c)) / 6.0).sum()
+    }
+
+    // the tube along x in the model, a drill of r 2 from +y at 30 from the end
+    fn drilled() -> Proc {
+        let drill = Drill { r: 2.0, d: 30.0, theta: std::f64::consts::FRAC_PI_2, slide: 0.0, hole: Hole::Through(18.0), top: 10.0, bottom: 9.0 };
+        Proc {
+            rotation: Mat3x3([V3([0.0, 0.0, -1.0]), V3([0.0, 1.0, 0.0]), V3([1.0, 0.0, 0.0])]),
+            ..tube(vec![drill], Vec::new())
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let proc = drilled();
+        let stock = stock(&proc);
+        assert_eq!(stock.len(), 32);
+        assert!((volume(&stock) - 5600.0).abs() < 1e-9);
+        let xs = stock.iter().flat_map(|t| t.iter().map(|p| p.x())).collect::<Vec<f64>>();
+        assert_eq!(xs.iter().cloned().fold(f64::INFINITY, f64::min), 0.0);
+        assert_eq!(xs.iter().cloned().fold(f64::NEG_INFINITY, f64::max), 100.0);
+
+        // 5 above the corner of the stock down to 1 past the inner surface
+        let tools = drills(&proc, &cfg(false, "{}"));
+        let r = 125f64.sqrt() + 5.0 - 8.0;
+        let exact = std::f64::consts::PI * 4.0 * r;
+        let faceted = (CYLINDER_SEGMENTS as f64 / 2.0) * (2.0 * std::f64::consts::PI / CYLINDER_SEGMENTS as f64).sin() * 4.0 * r;
+        assert!((volume(&tools) - faceted).abs() < 1e-9 && volume(&tools) < exact);
+        assert!(tools.iter().flat_map(|t| t.iter()).all(|p| p.y() >= 8.0 - 1e-9 && (p.x() - 30.0).abs() <= 2.0 + 1e-9));
+
+        // through the upper wall
+        let drilled = 5600.0 - std::f64::consts::PI * 4.0;
+        let result = simulate(&proc, &cfg(false, "{}"), Some(0.25)).unwrap();
+        assert!((volume(&result) - drilled).abs() < 2.0);
+        // the ends of the longer stock are cut off
+        let result = simulate(&proc, &cfg(true, "{}"), Some(0.25)).unwrap();
+        assert!((volume(&result) - drilled).abs() < 2.0);
+        assert_eq!(simulate(&proc, &cfg(false, "{}"), Some(0.0)).err(), Some(ExportError::Resolution(0.0)));
+        assert_eq!(simulate(&proc, &cfg(false, "{}"), Some(1e-4)).err(), Some(ExportError::Resolution(1e-4)));
+    }
+
+    #[test]
+    fn test_slot() {
+        // 20 long instead of the drill
+        let slot = Slot { r: 2.0, theta: std::f64::consts::FRAC_PI_2, ends: [(40.0, 0.0), (60.0, 0.0)], hole: Hole::Through(18.0), top: 10.0, bottom: 9.0 };
+        let slotted = Proc { drills: Vec::new(), slots: vec![slot], ..drilled() };
+        let tools = drills(&slotted, &cfg(false, "{}"));
+        assert!(tools.iter().flat_map(|t| t.iter()).all(|p| p.y() >= 8.0 - 1e-9 && p.x() >= 38.0 - 1e-9 && p.x() <= 62.0 + 1e-9));
+        let result = simulate(&slotted, &cfg(false, "{}"), Some(0.25)).unwrap();
+        assert!((volume(&result) - (5600.0 - std::f64::consts::PI * 4.0 - 80.0)).abs() < 2.0);
+    }
+
+    #[test]
+    fn test_boundary() {
+        // flat walls are a few rectangles
+        let plain = Proc { drills: Vec::new(), ..drilled() };
+        let result = simulate(&plain, &cfg(false, "{}"), Some(0.25)).unwrap();
+        assert!((volume(&result) - 5600.0).abs() < 1e-6);
+        assert!(result.len() < 100);
+        // a long pipe at the default resolution
+        let section = Section { x: [-50.0, -46.0, 46.0, 50.0], y: [-50.0, -46.0, 46.0, 50.0], inner_corner_r: 0.0 };
+        let long = Proc { size: V3([100.0, 100.0, 6000.0]), section, ..plain };
+        let result = simulate(&long, &cfg(false, "{}"), None).unwrap();
+        assert!((volume(&result) - 16.0 * 96.0 * 6000.0).abs() < 1e-3);
+        assert!(result.len() < 100);
+    }
+}
@@ -1,36 +1,226 @@
+mod escape;
 mod preprocess;
+mod db;
 mod parser;
+mod entity;
 mod analysis;
 mod math;
+mod mesh;
+mod stl;
+mod export;
+mod nurbs;
 mod backend;
+mod error;
+mod writer;
+mod reference;
+pub mod stream;
+mod archive;
 pub mod license;
+#[cfg(test)]
 extern crate pest;
+#[cfg(test)]
 #[macro_use]
 extern crate pest_derive;
 extern crate serde_json;
+extern crate flate2;
+extern crate zip;
 #[macro_use]
 extern crate serde_derive;
 
+use std::fs;
+use std::path::Path;
 use std::result::Result;
 
 pub type CNCConfig = backend::CNCConfig;
+pub use error::{Error, Span, Warning};
+pub use db::DanglingReference;
+pub use parser::{Header, Schema};
+pub use preprocess::{Step, Data, Value};
 
-pub fn parse(s: &str, cfg: &CNCConfig) -> Result<(String, String), String> {
-    let (_, data) = parser::parse(s).map_err(
-        |e| match e {
-            parser::ParseError::DataParseError(msg) => {
-                format!("failed to parser data section: {}", msg)
-            },
-            parser::ParseError::HeaderParseError(msg) => {
-                format!("failed to parser header section: {}", msg)
-            },
-            parser::ParseError::PreprocessError(msg) => {
-                format!("failed to parser file. {}", msg)
+pub fn parse_config(s: &str) -> Result<CNCConfig, Error> {
+    let cfg: CNCConfig = serde_json::from_str(s)?;
+    cfg.validate().map_err(|field| Error::InvalidConfig(format!("{} must be positive", field)))?;
+    Ok(cfg)
+}
+
+// machining program of a body in the file
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub name: String,
+    // of the file the body is in
+    pub header: Header,
+    pub gcode: String,
+    pub report: String,
+    proc: analysis::Proc,
+}
+
+// binary STL in the coordinates of the model
+impl Program {
+    // the ideal stock box
+    pub fn stock_stl(&self) -> Vec<u8> {
+        stl::write(format!("{} stock", self.name).trim_start(), &export::stock(&self.proc))
+    }
+
+    // the drills down to their depth
+    pub fn drills_stl(&self, cfg: &CNCConfig) -> Vec<u8> {
+        stl::write(format!("{} drills", self.name).trim_start(), &export::drills(&self.proc, cfg))
+    }
+
+    // the stock after the program, simulated in voxels of resolution millimetres,
+    // by default as fine as the size of the part allows
+    pub fn simulated_stl(&self, cfg: &CNCConfig, resolution: Option<f64>) -> Result<Vec<u8>, Error> {
+        let triangles = export::simulate(&self.proc, cfg, resolution)?;
+        Ok(stl::write(format!("{} result", self.name).trim_start(), &triangles))
+    }
+}
+
+// a body which cannot be machined gives its error without failing the others
+pub type Programs = Vec<Result<Program, Error>>;
+
+// one program per body, in the order of the product structure
+pub fn parse(s: &str, cfg: &CNCConfig) -> Result<Programs, Error> {
+    parse_with(s, &no_files, cfg, None)
+}
+
+// parse skipping malformed entities which are not needed for machining
+pub fn parse_tolerant(s: &str, cfg: &CNCConfig) -> Result<(Programs, Vec<Warning>), Error> {
+    let mut warnings = Vec::new();
+    let programs = parse_with(s, &no_files, cfg, Some(&mut warnings))?;
+    Ok((programs, warnings))
+}
+
+fn no_files(_: &str) -> Result<String, String> {
+    Err("no directory to read files from".to_owned())
+}
+
+// only files under dir, which is empty for the current directory as the parent of a bare file name
+fn load_in_dir(dir: &Path, name: &str) -> Result<String, String> {
+    if name.contains("://") && !name.starts_with("file://") {
+        return Err("not a local file".to_owned());
+    }
+    let path = Path::new(name.trim_start_matches("file://"));
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let dir = dir.canonicalize().map_err(|e| e.to_string())?;
+    let path = dir.join(path).canonicalize().map_err(|e| e.to_string())?;
+    if !path.starts_with(&dir) {
+        return Err("outside the directory of the file".to_owned());
+    }
+    fs::read_to_string(path).map_err(|e| e.to_string())
+}
+
+// resolves external references to files in dir
+pub fn parse_in_dir(s: &str, dir: &Path, cfg: &CNCConfig) -> Result<Programs, Error> {
+    parse_with(s, &|name| load_in_dir(dir, name), cfg, None)
+}
+
+// STEP text of a plain, gzipped (.stpZ) or zipped file,
+// member selects the STEP file in a zip archive (the first one by default)
+pub fn decompress(bytes: Vec<u8>, member: Option<&str>) -> Result<String, Error> {
+    Ok(archive::open(bytes, member)?.text)
+}
+
+// parse_in_dir for the content of a possibly compressed file,
+// references are resolved in the zip archive first,
+// tolerant like parse_tolerant when warnings are collected
+pub fn parse_bytes(bytes: Vec<u8>, member: Option<&str>, dir: &Path, cfg: &CNCConfig, warnings: Option<&mut Vec<Warning>>)
+    -> Result<Programs, Error>
+{
+    let contents = archive::open(bytes, member)?;
+    let load = |name: &str| contents.member(name).cloned().map_or_else(|| load_in_dir(dir, name), Ok);
+    parse_with(&contents.text, &load, cfg, warnings)
+}
+
+fn parse_with(s: &str, load: &dyn Fn(&str) -> Result<String, String>, cfg: &CNCConfig, warnings: Option<&mut Vec<Warning>>)
+    -> Result<Programs, Error>
+{
+    let (header, bodies) = parser::parse(s, load, warnings)?;
+    if bodies.is_empty() {
+        return Err(Error::MissingEntity("MANIFOLD_SOLID_BREP".to_owned()));
+    }
+    Ok(bodies
+        .into_iter()
+        .map(|body| {
+            let tol = cfg.tolerance
+                .or(body.uncertainty)
+                .map(math::Tolerance::from_distance)
+                .unwrap_or_default();
+            let proc = analysis::Proc::new(&body.faces, &tol)
+                .map_err(|e| Error::NotSquarePipe(format!("{}: {}", body.name, e)))?;
+            let mut report = proc.report.clone();
+            if let Some([r, g, b]) = body.colour {
+                report.push_str(&format!("\ncolour: ({}, {}, {})", r, g, b));
             }
-        }
-    )?;
-    let proc = analysis::Proc::new(&data);
+            // one line each
+            let trace = header
+                .trace()
+                .into_iter()
+                .map(|(label, value)| format!("{}: {}", label, value.replace(['\r', '\n'], " ")))
+                .collect::<Vec<String>>();
+            for line in &trace {
+                report.push('\n');
+                report.push_str(line);
+            }
+            let gcode = backend::gen_gcode(&proc, cfg, &tol, &trace)?;
+            Ok(Program { name: body.name, header: header.clone(), gcode, report, proc })
+        })
+        .collect())
+}
+
+// program of the mesh in an STL file, unit is the length of an STL unit in millimetres
+pub fn parse_stl(bytes: &[u8], unit: f64, cfg: &CNCConfig) -> Result<Program, Error> {
+    let stl = stl::read(bytes)?;
+    let triangles = stl.triangles
+        .iter()
+        .map(|t| [t[0].scale(unit), t[1].scale(unit), t[2].scale(unit)])
+        .collect::<Vec<[math::V3; 3]>>();
+    let mesh = mesh::Mesh::new(&triangles);
+    if mesh.triangles.is_empty() {
+        return Err(Error::Stl("no triangles".to_owned()));
+    }
+    let tol = mesh::tolerance(cfg.tolerance);
+    let proc = analysis::Proc::from_mesh(&mesh, &tol)
+        .map_err(|e| Error::NotSquarePipe(if stl.name.is_empty() { e.to_string() } else { format!("{}: {}", stl.name, e) }))?;
     let report = proc.report.clone();
-    let gcode = backend::gen_gcode(proc, cfg).map_err(|_| "internal error".to_owned())?;
-    Ok((gcode, report))
+    let gcode = backend::gen_gcode(&proc, cfg, &tol, &[])?;
+    Ok(Program { name: stl.name, header: Header::default(), gcode, report, proc })
+}
+
+// entities of a STEP file without interpretation
+pub fn read_step(s: &str) -> Result<Step, Error> {
+    Ok(stream::parse(s)?)
+}
+
+pub fn write_step(step: &Step) -> Result<String, Error> {
+    Ok(writer::write(step)?)
+}
+
+// ids from 1 keeping their order
+pub fn renumber_step(step: &mut Step) {
+    writer::renumber(step)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_in_dir() {
+        let root = std::env::temp_dir().join("canorus_load_in_dir");
+        let dir = root.join("parts");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("part.stp"), "part").unwrap();
+        fs::write(root.join("secret.stp"), "secret").unwrap();
+        assert_eq!(load_in_dir(&dir, "part.stp"), Ok("part".to_owned()));
+        assert_eq!(load_in_dir(&dir, "file://part.stp"), Ok("part".to_owned()));
+        assert_eq!(load_in_dir(&dir, "./../parts/part.stp"), Ok("part".to_owned()));
+        let outside = Err("outside the directory of the file".to_owned());
+        assert_eq!(load_in_dir(&dir, "../secret.stp"), outside);
+        assert_eq!(load_in_dir(&dir, root.join("secret.stp").to_str().unwrap()), outside);
+        assert_eq!(load_in_dir(&dir, &format!("file://{}", root.join("secret.stp").display())), outside);
+        assert_eq!(load_in_dir(&dir, "http://example.com/part.stp"), Err("not a local file".to_owned()));
+        assert!(load_in_dir(&dir, "missing.stp").is_err());
+        // the parent of a bare file name
+        let bare = Path::new("example.STEP").parent().unwrap();
+        assert!(load_in_dir(bare, "config.json").is_ok());
+    }
 }
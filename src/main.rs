@@ -1,16 +1,25 @@
-use canorus;
-
 use std::fs;
 use std::io::{Read, Write};
+use std::path;
 use std::process;
-use clap;
+
+// out.gcode -> out_1.gcode
+fn numbered_path(output: &str, n: usize) -> path::PathBuf {
+    let p = path::Path::new(output);
+    let stem = p.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match p.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, n),
+    };
+    p.with_file_name(name)
+}
 
 fn main() {
     let matches = clap::App::new("canorus")
         .version("0.0.1")
         .author("Nakano Masaki <namachan10777@gmail.com>")
         .arg(clap::Arg::with_name("INPUT")
-            .help("STEP file including only one rectangle lumber")
+            .help("STEP or STL file including rectangle lumbers")
             .required(true)
             .index(1))
         .arg(clap::Arg::with_name("CONFIG")
@@ -25,6 +34,33 @@ fn main() {
             .short("o")
             .long("output")
             .takes_value(true))
+        .arg(clap::Arg::with_name("MEMBER")
+            .help("STEP file in a zip archive")
+            .required(false)
+            .short("m")
+            .long("member")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("TOLERANT")
+            .help("skip malformed entities with warnings")
+            .required(false)
+            .long("tolerant")
+            .takes_value(false))
+        .arg(clap::Arg::with_name("UNIT")
+            .help("length of an STL unit in millimetres")
+            .required(false)
+            .long("unit")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("STL")
+            .help("write the stock, drills and simulated result to PREFIX_stock.stl, PREFIX_drills.stl and PREFIX_result.stl")
+            .required(false)
+            .long("stl")
+            .value_name("PREFIX")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("RESOLUTION")
+            .help("voxel size of the simulation in millimetres (derived from the size of the part by default)")
+            .required(false)
+            .long("resolution")
+            .takes_value(true))
         .arg(clap::Arg::with_name("VERBOSE")
             .help("print report")
             .required(false)
@@ -47,7 +83,13 @@ fn main() {
         },
     };
     config_file.read_to_string(&mut buf).unwrap();
-    let cfg: canorus::CNCConfig = serde_json::from_str(&buf).unwrap();
+    let cfg = match canorus::parse_config(&buf) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(-1)
+        },
+    };
     buf.clear();
 
     let mut step_file = match fs::File::open(matches.value_of("INPUT").unwrap()) {
@@ -62,23 +104,98 @@ fn main() {
         println!("{}", canorus::license::LICENSE);
         process::exit(-1)
     }
-    step_file.read_to_string(&mut buf).unwrap();
-    let out = canorus::parse(&buf, &cfg);
+    // may be gzipped or a zip archive
+    let mut bytes = Vec::new();
+    step_file.read_to_end(&mut bytes).unwrap();
+    let input = path::Path::new(matches.value_of("INPUT").unwrap());
+    let dir = input.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| path::Path::new("."));
+    let mut warnings = Vec::new();
+    let tolerant = if matches.is_present("TOLERANT") { Some(&mut warnings) } else { None };
+    let resolution = match matches.value_of("RESOLUTION").map(|r| r.parse::<f64>()) {
+        None => None,
+        Some(Ok(r)) if r > 0.0 => Some(r),
+        _ => {
+            println!("resolution must be a positive number");
+            process::exit(-1)
+        },
+    };
+    let is_stl = input.extension().map(|ext| ext.eq_ignore_ascii_case("stl")).unwrap_or(false);
+    let out = if is_stl {
+        let unit = match matches.value_of("UNIT").unwrap_or("1").parse::<f64>() {
+            Ok(unit) if unit > 0.0 => unit,
+            _ => {
+                println!("unit must be a positive number");
+                process::exit(-1)
+            },
+        };
+        canorus::parse_stl(&bytes, unit, &cfg).map(|program| vec![Ok(program)])
+    }
+    else {
+        canorus::parse_bytes(bytes, matches.value_of("MEMBER"), dir, &cfg, tolerant)
+    };
+    for warning in &warnings {
+        eprintln!("warning: {}", warning);
+    }
     match out {
-        Ok((gcode, report)) => {
-            if let Some(output) = matches.value_of("OUTPUT") {
-                let mut f = fs::File::create(output).unwrap();
-                f.write_all(gcode.as_bytes()).unwrap();
+        Ok(programs) => {
+            // the other bodies are still written when one fails
+            let mut failed = false;
+            for (i, program) in programs.iter().enumerate() {
+                let program = match program {
+                    Ok(program) => program,
+                    Err(e) => {
+                        println!("{}", e);
+                        failed = true;
+                        continue
+                    },
+                };
+                if let Some(output) = matches.value_of("OUTPUT") {
+                    // one file per body, numbered when the file has several bodies
+                    let path = if programs.len() == 1 {
+                        path::PathBuf::from(output)
+                    }
+                    else {
+                        numbered_path(output, i + 1)
+                    };
+                    let mut f = fs::File::create(path).unwrap();
+                    f.write_all(program.gcode.as_bytes()).unwrap();
+                }
+                else {
+                    if programs.len() > 1 {
+                        println!("; {}", program.name);
+                    }
+                    println!("{}", program.gcode);
+                }
+                if let Some(prefix) = matches.value_of("STL") {
+                    let result = match program.simulated_stl(&cfg, resolution) {
+                        Ok(result) => result,
+                        Err(e) => {
+                            println!("{}", e);
+                            failed = true;
+                            continue
+                        },
+                    };
+                    let meshes = [("stock", program.stock_stl()), ("drills", program.drills_stl(&cfg)), ("result", result)];
+                    for (kind, stl) in &meshes {
+                        let name = format!("{}_{}.stl", prefix, kind);
+                        let path = if programs.len() == 1 { path::PathBuf::from(&name) } else { numbered_path(&name, i + 1) };
+                        fs::File::create(path).unwrap().write_all(stl).unwrap();
+                    }
+                }
+                if matches.is_present("VERBOSE") {
+                    if programs.len() > 1 {
+                        println!("{}:", program.name);
+                    }
+                    println!("{}", program.report);
+                }
             }
-            else {
-                println!("{}", gcode);
-            }
-            if matches.is_present("VERBOSE") {
-                println!("{}", report);
+            if failed {
+                process::exit(-1)
             }
         },
-        Err(msg) => {
-            println!("{}", msg);
+        Err(e) => {
+            println!("{}", e);
+            process::exit(-1)
         }
     }
 }
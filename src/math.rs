@@ -14,6 +14,27 @@ impl Mat3x3 {
         r
     }
 
+    pub fn det(&self) -> f64 {
+        self.0[0].dot(&self.0[1].cross(&self.0[2]))
+    }
+
+    // solves self * x = b by Cramer's rule
+    pub fn solve(&self, b: &V3) -> Option<V3> {
+        let det = self.det();
+        if det.abs() < 1e-300 {
+            return None;
+        }
+        let mut x = V3::default();
+        for i in 0..3 {
+            let mut m = self.clone();
+            for j in 0..3 {
+                m.0[j].0[i] = b.0[j];
+            }
+            x.0[i] = m.det() / det;
+        }
+        Some(x)
+    }
+
     pub fn prod_vec(&self, v: &V3) -> V3 {
         let mut r = V3::default();
         for i in 0..3 {
@@ -25,6 +46,117 @@ impl Mat3x3 {
     }
 }
 
+impl Mat3x3 {
+    pub fn identity() -> Self {
+        Mat3x3([
+            V3([1.0, 0.0, 0.0]),
+            V3([0.0, 1.0, 0.0]),
+            V3([0.0, 0.0, 1.0]),
+        ])
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut r = Self::default();
+        for i in 0..3 {
+            for j in 0..3 {
+                r.0[i].0[j] = self.0[j].0[i];
+            }
+        }
+        r
+    }
+}
+
+// geometric tolerance, distances in millimetres and angles in radians
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    pub distance: f64,
+    pub angle: f64,
+}
+
+// pipes are a few metres long, directions within the angle of the distance over a metre
+// keep their features within a few times the distance along the whole pipe
+const REACH: f64 = 1000.0;
+
+impl Tolerance {
+    // the angle moving a point at reach by the distance
+    pub fn new(distance: f64, reach: f64) -> Self {
+        Tolerance { distance, angle: distance / reach }
+    }
+
+    pub fn from_distance(distance: f64) -> Self {
+        Tolerance::new(distance, REACH)
+    }
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance::from_distance(1e-6)
+    }
+}
+
+// rigid transformation with uniform scaling, p -> scale * rot * p + trans
+// directions are only rotated, lengths are multiplied by scale
+#[derive(Debug, PartialEq, Clone)]
+pub struct Transform {
+    pub rot: Mat3x3,
+    pub trans: V3,
+    pub scale: f64,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            rot: Mat3x3::identity(),
+            trans: V3::default(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn scaling(scale: f64) -> Self {
+        Transform { scale, ..Transform::identity() }
+    }
+
+    // local coordinate system of a placement to the global one
+    pub fn from_frame(origin: &V3, z: &V3, ref_x: &V3) -> Self {
+        let z = z.normalize();
+        let x = ref_x.sub(&z.scale(ref_x.dot(&z)));
+        let x = if x.norm() < 1e-12 { z.perpendicular() } else { x.normalize() };
+        let y = z.cross(&x);
+        Transform {
+            rot: Mat3x3([x, y, z]).transpose(),
+            trans: origin.clone(),
+            scale: 1.0,
+        }
+    }
+
+    // self after t
+    pub fn compose(&self, t: &Transform) -> Self {
+        Transform {
+            rot: self.rot.prod(&t.rot),
+            trans: self.point(&t.trans),
+            scale: self.scale * t.scale,
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        let rot = self.rot.transpose();
+        let trans = rot.prod_vec(&self.trans).scale(-1.0 / self.scale);
+        Transform { rot, trans, scale: 1.0 / self.scale }
+    }
+
+    pub fn point(&self, p: &V3) -> V3 {
+        self.rot.prod_vec(p).scale(self.scale).add(&self.trans)
+    }
+
+    pub fn vector(&self, v: &V3) -> V3 {
+        self.rot.prod_vec(v)
+    }
+
+    pub fn length(&self, l: f64) -> f64 {
+        l * self.scale
+    }
+}
+
 #[derive(Default, Debug, PartialEq, Clone)]
 pub struct V3 (pub [f64;3]);
 
@@ -41,13 +173,13 @@ impl V3 {
         ])
     }
 
-    pub fn are_independent(&self, v: &Self) -> bool {
-        for i in 0..3 {
-            if self.0[i].abs() > 10e-10 && v.0[i].abs() > 10e-10 {
-                return !self.approx(&v.scale(self.0[i] / v.0[i]))
-            }
+    // not parallel within the angle tolerance, zero vectors are independent of any
+    pub fn are_independent(&self, v: &Self, tol: &Tolerance) -> bool {
+        let (a, b) = (self.norm(), v.norm());
+        if a < tol.distance || b < tol.distance {
+            return true;
         }
-        true 
+        self.cross(v).norm() / (a * b) > tol.angle
     }
 
     pub fn scale(&self, s: f64) -> Self {
@@ -58,6 +190,14 @@ impl V3 {
         ])
     }
 
+    pub fn add(&self, v: &Self) -> Self {
+        V3([
+            self.x() + v.x(),
+            self.y() + v.y(),
+            self.z() + v.z(),
+        ])
+    }
+
     pub fn sub(&self, v: &Self) -> Self {
         V3([
             self.x() - v.x(),
@@ -66,12 +206,6 @@ impl V3 {
         ])
     }
 
-    pub fn approx(&self, v: &Self) -> bool {
-        (self.x() - v.x()).abs() < 10e-15
-        && (self.y() - v.y()).abs() < 10e-15
-        && (self.z() - v.z()).abs() < 10e-15
-    }
-
     pub fn norm(&self) -> f64 {
         (self.x().powi(2) + self.y().powi(2) + self.z().powi(2)).sqrt()
     }
@@ -85,6 +219,18 @@ impl V3 {
         ])
     }
 
+    // some unit vector perpendicular to self
+    pub fn perpendicular(&self) -> Self {
+        let v = self.normalize();
+        let other = if v.x().abs() < 0.6 {
+            V3([1.0, 0.0, 0.0])
+        }
+        else {
+            V3([0.0, 1.0, 0.0])
+        };
+        v.cross(&other).normalize()
+    }
+
     pub fn x(&self) -> f64 {
         self.0[0]
     }
@@ -96,6 +242,33 @@ impl V3 {
     }
 }
 
+// algebraic least squares fit, returns center and radius
+pub fn fit_circle(points: &[(f64, f64)]) -> Option<((f64, f64), f64)> {
+    let n = points.len() as f64;
+    let mx = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let my = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let mut m = Mat3x3::default();
+    let mut b = V3::default();
+    for (x, y) in points {
+        let (x, y) = (x - mx, y - my);
+        let row = V3([x, y, 1.0]);
+        let z = x * x + y * y;
+        for i in 0..3 {
+            for j in 0..3 {
+                m.0[i].0[j] += row.0[i] * row.0[j];
+            }
+            b.0[i] -= row.0[i] * z;
+        }
+    }
+    let sol = m.solve(&b)?;
+    let (cx, cy) = (-sol.x() / 2.0, -sol.y() / 2.0);
+    let r2 = cx * cx + cy * cy - sol.z();
+    if r2 <= 0.0 {
+        return None;
+    }
+    Some(((cx + mx, cy + my), r2.sqrt()))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -128,14 +301,50 @@ mod test {
         assert_eq!(x.prod_vec(&v1), v2);
     }
 
+    #[test]
+    fn test_transform() {
+        let t = Transform::from_frame(&V3([1., 2., 3.]), &V3([0., 0., 2.]), &V3([0., 1., 0.5]));
+        assert!(t.point(&V3([1., 0., 0.])).sub(&V3([1., 3., 3.])).norm() < 1e-12);
+        assert!(t.vector(&V3([0., 1., 0.])).sub(&V3([-1., 0., 0.])).norm() < 1e-12);
+        let p = V3([0.5, -4., 7.]);
+        assert!(t.inverse().point(&t.point(&p)).sub(&p).norm() < 1e-12);
+        let u = Transform::from_frame(&V3([0., 0., 1.]), &V3([1., 0., 0.]), &V3([0., 0., 1.]));
+        assert!(t.compose(&u).point(&p).sub(&t.point(&u.point(&p))).norm() < 1e-12);
+        let s = Transform::scaling(25.4).compose(&t);
+        assert!(s.point(&V3([1., 0., 0.])).sub(&V3([25.4, 76.2, 76.2])).norm() < 1e-12);
+        assert!(s.vector(&V3([0., 1., 0.])).sub(&V3([-1., 0., 0.])).norm() < 1e-12);
+        assert!(s.inverse().point(&s.point(&p)).sub(&p).norm() < 1e-12);
+        assert!(s.inverse().compose(&s).point(&p).sub(&p).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_solve() {
+        let m = Mat3x3([
+            V3([2., 1., 0.]),
+            V3([1., 3., 1.]),
+            V3([0., 1., 4.]),
+        ]);
+        let x = m.solve(&V3([3., 5., 5.])).unwrap();
+        assert!(x.sub(&V3([1., 1., 1.])).norm() < 1e-12);
+        assert_eq!(Mat3x3::default().solve(&V3([1., 0., 0.])), None);
+        let p = V3([0.3, -2., 0.1]).perpendicular();
+        assert!(p.dot(&V3([0.3, -2., 0.1])).abs() < 1e-12);
+        assert!((p.norm() - 1.0).abs() < 1e-12);
+    }
+
     #[test]
     fn test_are_independent() {
         let v1 = V3([1.0, 0.0, 0.0]);
         let v2 = V3([2.0, 0.0, 0.0]);
         let v3 = V3([2.0, 1.0, 0.0]);
         let v4 = V3([0.0, 0.0, -1.0]);
-        assert_eq!(v1.are_independent(&v2), false);
-        assert_eq!(v1.are_independent(&v3), true);
-        assert_eq!(v1.are_independent(&v4), true);
+        let tol = Tolerance::default();
+        assert!(!v1.are_independent(&v2, &tol));
+        assert!(v1.are_independent(&v3, &tol));
+        assert!(v1.are_independent(&v4, &tol));
+        // parallel within the declared uncertainty of a model
+        let v5 = V3([1.0, 1e-6, 0.0]);
+        assert!(v1.are_independent(&v5, &tol));
+        assert!(!v1.are_independent(&v5, &Tolerance::from_distance(1e-2)));
     }
 }
@@ -0,0 +1,375 @@
+use super::math::{V3, Mat3x3, Tolerance, fit_circle};
+use super::parser::Axis;
+use std::collections::HashMap;
+
+// Feature recognition on triangle meshes.
+// Edge-connected triangles on a plane form a planar region. Planar regions meeting at
+// small angles are facets of a curved face, which is recognized if a cylinder fits them.
+
+// between the facets of a circle with 7 or more segments, not at corners of a pipe
+const SMOOTH_ANGLE: f64 = 60.0 * std::f64::consts::PI / 180.0;
+// of the facets of a twisted mesh
+const MAX_TILT: f64 = 0.2;
+// a cylinder is fitted to at least 4 points
+const MIN_FACETS: usize = 3;
+const FACET_AREA_RATIO: f64 = 4.0;
+const AXIS_ITERATIONS: usize = 8;
+// STL coordinates are single precision
+const MESH_TOLERANCE: f64 = 1e-2;
+// normals of facets about a centimetre wide are off by the distance over it
+const FACET_REACH: f64 = 10.0;
+
+// distance in millimetres, the default is loose enough for single precision coordinates
+pub fn tolerance(distance: Option<f64>) -> Tolerance {
+    Tolerance::new(distance.unwrap_or(MESH_TOLERANCE), FACET_REACH)
+}
+
+#[derive(Debug, Clone)]
+pub enum Surface {
+    Plane(Axis),
+    Cylinder(f64, Axis),
+    // not recognized, the centroid
+    Other(V3),
+}
+
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub surface: Surface,
+    pub triangles: Vec<usize>,
+}
+
+// triangles share the indices of equal vertices
+#[derive(Debug)]
+pub struct Mesh {
+    pub vertices: Vec<V3>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    // degenerate triangles are dropped
+    pub fn new(triangles: &[[V3; 3]]) -> Self {
+        let mut index = HashMap::new();
+        let mut vertices = Vec::new();
+        let mut weld = |v: &V3| {
+            let key = [v.x().to_bits(), v.y().to_bits(), v.z().to_bits()];
+            *index.entry(key).or_insert_with(|| {
+                vertices.push(v.clone());
+                vertices.len() - 1
+            })
+        };
+        let triangles = triangles
+            .iter()
+            .map(|[a, b, c]| [weld(a), weld(b), weld(c)])
+            .collect::<Vec<[usize; 3]>>();
+        let mut mesh = Mesh { vertices, triangles };
+        mesh.triangles = mesh.triangles
+            .iter()
+            .filter(|t| mesh.area_vector(t).norm() > 0.0)
+            .cloned()
+            .collect();
+        mesh
+    }
+
+    // vertices of the triangles
+    pub fn points(&self, triangles: &[usize]) -> Vec<V3> {
+        let mut indices = triangles.iter().flat_map(|&t| self.triangles[t].iter().cloned()).collect::<Vec<usize>>();
+        indices.sort_unstable();
+        indices.dedup();
+        indices.into_iter().map(|i| self.vertices[i].clone()).collect()
+    }
+
+    // normal scaled by twice the area
+    fn area_vector(&self, t: &[usize; 3]) -> V3 {
+        let [a, b, c] = t.map(|i| &self.vertices[i]);
+        b.sub(a).cross(&c.sub(a))
+    }
+
+    fn normal(&self, t: usize) -> V3 {
+        self.area_vector(&self.triangles[t]).normalize()
+    }
+
+    // triangles by their edges
+    fn edges(&self) -> HashMap<(usize, usize), Vec<usize>> {
+        let mut edges = HashMap::new();
+        for (i, t) in self.triangles.iter().enumerate() {
+            for k in 0..3 {
+                let (a, b) = (t[k], t[(k + 1) % 3]);
+                edges.entry((a.min(b), a.max(b))).or_insert_with(Vec::new).push(i);
+            }
+        }
+        edges
+    }
+
+    fn neighbours(&self, edges: &HashMap<(usize, usize), Vec<usize>>, t: usize) -> Vec<usize> {
+        let t_ = &self.triangles[t];
+        (0..3)
+            .flat_map(|k| {
+                let (a, b) = (t_[k], t_[(k + 1) % 3]);
+                edges[&(a.min(b), a.max(b))].iter().cloned()
+            })
+            .filter(|&u| u != t)
+            .collect()
+    }
+
+    // area weighted normal and centroid
+    fn normal_and_centroid(&self, triangles: &[usize]) -> (V3, V3) {
+        let mut normal = V3::default();
+        let mut centroid = V3::default();
+        let mut area = 0.0;
+        for &t in triangles {
+            let v = self.area_vector(&self.triangles[t]);
+            let a = v.norm();
+            let [p, q, r] = self.triangles[t].map(|i| &self.vertices[i]);
+            normal = normal.add(&v);
+            centroid = centroid.add(&p.add(q).add(r).scale(a / 3.0));
+            area += a;
+        }
+        (normal.normalize(), centroid.scale(1.0 / area))
+    }
+
+    // coplanar triangles connected by edges
+    fn planar_regions(&self, edges: &HashMap<(usize, usize), Vec<usize>>, tol: &Tolerance) -> Vec<Vec<usize>> {
+        let mut assigned = vec![false; self.triangles.len()];
+        let mut regions = Vec::new();
+        for seed in 0..self.triangles.len() {
+            if assigned[seed] {
+                continue;
+            }
+            let n0 = self.normal(seed);
+            let p0 = self.vertices[self.triangles[seed][0]].clone();
+            let on_plane = |t: usize| {
+                let n = self.normal(t);
+                n.dot(&n0) > 0.0
+                    && n.cross(&n0).norm() < tol.angle
+                    && self.triangles[t].iter().all(|&i| self.vertices[i].sub(&p0).dot(&n0).abs() < tol.distance)
+            };
+            assigned[seed] = true;
+            let mut region = vec![seed];
+            let mut i = 0;
+            while i < region.len() {
+                for u in self.neighbours(edges, region[i]) {
+                    if !assigned[u] && on_plane(u) {
+                        assigned[u] = true;
+                        region.push(u);
+                    }
+                }
+                i += 1;
+            }
+            regions.push(region);
+        }
+        regions
+    }
+
+    // the axis is the direction most perpendicular to the normals, the least eigenvector of
+    // their scatter matrix by inverse iteration
+    fn axis_of(normals: &[V3], tol: &Tolerance) -> Option<V3> {
+        let n0 = &normals[0];
+        let mut axis = normals
+            .iter()
+            .map(|n| n0.cross(n))
+            .max_by(|a, b| a.norm().partial_cmp(&b.norm()).unwrap_or(std::cmp::Ordering::Equal))?;
+        if axis.norm() < tol.angle {
+            return None;
+        }
+        // regularized for exact prisms
+        let mut m = Mat3x3::default();
+        for i in 0..3 {
+            m.0[i].0[i] = tol.angle;
+        }
+        for n in normals {
+            for i in 0..3 {
+                for j in 0..3 {
+                    m.0[i].0[j] += n.0[i] * n.0[j];
+                }
+            }
+        }
+        for _ in 0..AXIS_ITERATIONS {
+            axis = m.solve(&axis.normalize())?;
+        }
+        Some(axis.normalize())
+    }
+
+    // facets of twisted (antiprism) meshes are not parallel to the axis, the vertices are on the cylinder
+    fn fit_cylinder(&self, regions: &[&Vec<usize>], normals: &[&V3], tol: &Tolerance) -> Option<(f64, Axis)> {
+        let direction = Mesh::axis_of(&normals.iter().cloned().cloned().collect::<Vec<V3>>(), tol)?;
+        if normals.iter().any(|n| n.dot(&direction).abs() > MAX_TILT) {
+            return None;
+        }
+        // directions of drills are unsigned
+        let direction = if direction.0.iter().find(|x| x.abs() > tol.angle).map(|x| *x < 0.0).unwrap_or(false) {
+            direction.scale(-1.0)
+        }
+        else {
+            direction
+        };
+        let mut vertices = regions
+            .iter()
+            .flat_map(|region| region.iter())
+            .flat_map(|&t| self.triangles[t].iter().cloned())
+            .collect::<Vec<usize>>();
+        vertices.sort_unstable();
+        vertices.dedup();
+        let e1 = direction.perpendicular();
+        let e2 = direction.cross(&e1);
+        let projected = vertices
+            .iter()
+            .map(|&i| (self.vertices[i].dot(&e1), self.vertices[i].dot(&e2)))
+            .collect::<Vec<(f64, f64)>>();
+        let ((cx, cy), r) = fit_circle(&projected)?;
+        if projected.iter().any(|(x, y)| (((x - cx).powi(2) + (y - cy).powi(2)).sqrt() - r).abs() > tol.distance) {
+            return None;
+        }
+        let along = vertices.iter().map(|&i| self.vertices[i].dot(&direction)).sum::<f64>() / vertices.len() as f64;
+        Some((r, Axis {
+            p: e1.scale(cx).add(&e2.scale(cy)).add(&direction.scale(along)),
+            direction,
+            ref_direction: e1,
+        }))
+    }
+
+    // smooth neighbours of planar regions and whether they are facets of curved faces,
+    // a plane tangent to a fillet is much larger than its facets
+    fn adjacency(&self, planes: &[Vec<usize>], normals: &[V3]) -> (Vec<Vec<usize>>, Vec<bool>) {
+        let mut region_of = vec![0; self.triangles.len()];
+        for (r, plane) in planes.iter().enumerate() {
+            for &t in plane {
+                region_of[t] = r;
+            }
+        }
+        let mut smooth = vec![Vec::new(); planes.len()];
+        for ts in self.edges().values() {
+            for &t in ts {
+                for &u in ts {
+                    let (r, s) = (region_of[t], region_of[u]);
+                    if r != s && normals[r].dot(&normals[s]) > SMOOTH_ANGLE.cos() && !smooth[r].contains(&s) {
+                        smooth[r].push(s);
+                    }
+                }
+            }
+        }
+        let area = planes
+            .iter()
+            .map(|plane| plane.iter().map(|t| self.area_vector(&self.triangles[*t]).norm()).sum::<f64>())
+            .collect::<Vec<f64>>();
+        let facet = (0..planes.len())
+            .map(|r| smooth[r].iter().any(|&s| area[r] <= FACET_AREA_RATIO * area[s]))
+            .collect();
+        (smooth, facet)
+    }
+
+    pub fn segment(&self, tol: &Tolerance) -> Vec<Region> {
+        let planes = self.planar_regions(&self.edges(), tol);
+        let normals = planes.iter().map(|plane| self.normal_and_centroid(plane).0).collect::<Vec<V3>>();
+        let (smooth, facet) = self.adjacency(&planes, &normals);
+        let mut grouped = vec![false; planes.len()];
+        let mut regions = Vec::new();
+        for seed in 0..planes.len() {
+            if grouped[seed] {
+                continue;
+            }
+            grouped[seed] = true;
+            // facets connected at small angles
+            let mut group = vec![seed];
+            let mut i = 0;
+            while facet[seed] && i < group.len() {
+                for &s in &smooth[group[i]] {
+                    if !grouped[s] && facet[s] {
+                        grouped[s] = true;
+                        group.push(s);
+                    }
+                }
+                i += 1;
+            }
+            let members = group.iter().map(|&r| &planes[r]).collect::<Vec<&Vec<usize>>>();
+            let triangles = members.iter().flat_map(|plane| plane.iter().cloned()).collect::<Vec<usize>>();
+            let (normal, centroid) = self.normal_and_centroid(&triangles);
+            let surface = if group.len() == 1 {
+                Surface::Plane(Axis {
+                    p: centroid,
+                    ref_direction: normal.perpendicular(),
+                    direction: normal,
+                })
+            }
+            else if group.len() < MIN_FACETS {
+                Surface::Other(centroid)
+            }
+            else {
+                let group_normals = group.iter().map(|&r| &normals[r]).collect::<Vec<&V3>>();
+                match self.fit_cylinder(&members, &group_normals, tol) {
+                    Some((r, ax)) => Surface::Cylinder(r, ax),
+                    None => Surface::Other(centroid),
+                }
+            };
+            regions.push(Region { surface, triangles });
+        }
+        regions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn quad(a: V3, b: V3, c: V3, d: V3) -> Vec<[V3; 3]> {
+        vec![[a.clone(), b, c.clone()], [a, c, d]]
+    }
+
+    // facets of a bore of r 2 along x through (0, 0, 20)
+    fn bore(segments: usize) -> Vec<[V3; 3]> {
+        let ring = |i: usize, x: f64| {
+            let t = 2.0 * std::f64::consts::PI * (i % segments) as f64 / segments as f64;
+            V3([x, 2.0 * t.cos(), 20.0 + 2.0 * t.sin()])
+        };
+        (0..segments)
+            .flat_map(|i| quad(ring(i, -5.0), ring(i + 1, -5.0), ring(i + 1, 5.0), ring(i, 5.0)))
+            .collect()
+    }
+
+    #[test]
+    fn test_segment() {
+        let p = |x: f64, y: f64, z: f64| V3([x, y, z]);
+        // an L of two plates, with a split plate
+        let mut triangles = quad(p(0., 0., 0.), p(10., 0., 0.), p(10., 10., 0.), p(0., 10., 0.));
+        triangles.extend(quad(p(0., 0., 0.), p(0., 10., 0.), p(0., 10., 10.), p(0., 0., 10.)));
+        triangles.extend(quad(p(10., 0., 0.), p(20., 0., 0.), p(20., 10., 0.), p(10., 10., 0.)));
+        triangles.push([p(0., 0., 0.), p(0., 0., 0.), p(0., 0., 1.)]);
+        triangles.extend(bore(24));
+        let mesh = Mesh::new(&triangles);
+        assert_eq!(mesh.triangles.len(), triangles.len() - 1);
+        let regions = mesh.segment(&tolerance(None));
+        let planes = regions
+            .iter()
+            .filter_map(|r| match &r.surface {
+                Surface::Plane(ax) => Some((r.triangles.len(), ax.direction.clone())),
+                _ => None,
+            })
+            .collect::<Vec<(usize, V3)>>();
+        assert_eq!(planes, vec![(4, p(0., 0., 1.)), (2, p(1., 0., 0.))]);
+        let cylinders = regions
+            .iter()
+            .filter_map(|r| match &r.surface {
+                Surface::Cylinder(r, ax) => Some((*r, ax.clone())),
+                _ => None,
+            })
+            .collect::<Vec<(f64, Axis)>>();
+        assert_eq!(cylinders.len(), 1);
+        let (r, ax) = &cylinders[0];
+        assert!((r - 2.0).abs() < 1e-9);
+        assert!(ax.direction.sub(&p(1., 0., 0.)).norm() < 1e-9);
+        assert!(ax.p.sub(&p(0., 0., 20.)).norm() < 1e-9);
+        // too coarse to tell facets from planes
+        let regions = Mesh::new(&bore(4)).segment(&tolerance(None));
+        assert_eq!(regions.len(), 4);
+        assert!(regions.iter().all(|r| matches!(r.surface, Surface::Plane(_))));
+        // facets of a cone
+        let cone = (0..24)
+            .map(|i| {
+                let t = |i: usize| 2.0 * std::f64::consts::PI * i as f64 / 24.0;
+                [p(0., 0., 0.), p(t(i).cos(), t(i).sin(), 1.), p(t(i + 1).cos(), t(i + 1).sin(), 1.)]
+            })
+            .collect::<Vec<[V3; 3]>>();
+        let regions = Mesh::new(&cone).segment(&tolerance(None));
+        assert_eq!(regions.len(), 1);
+        assert!(matches!(regions[0].surface, Surface::Other(_)));
+    }
+}
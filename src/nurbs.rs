@@ -0,0 +1,270 @@
+use super::math::{V3, Transform};
+
+// Non-uniform rational B-spline curve and surface.
+// Knot vectors are stored with their multiplicities expanded, and
+// non-rational splines have all weights 1.
+
+#[derive(Debug, Clone)]
+pub struct NurbsCurve {
+    degree: usize,
+    knots: Vec<f64>,
+    points: Vec<V3>,
+    weights: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NurbsSurface {
+    u_degree: usize,
+    v_degree: usize,
+    u_knots: Vec<f64>,
+    v_knots: Vec<f64>,
+    // points[u][v]
+    points: Vec<Vec<V3>>,
+    weights: Vec<Vec<f64>>,
+}
+
+pub fn expand_knots(mults: &[i64], knots: &[f64]) -> Option<Vec<f64>> {
+    if mults.len() != knots.len() {
+        return None;
+    }
+    let mut expanded = Vec::new();
+    for (m, k) in mults.iter().zip(knots.iter()) {
+        if *m < 1 {
+            return None;
+        }
+        for _ in 0..*m {
+            expanded.push(*k);
+        }
+    }
+    Some(expanded)
+}
+
+fn valid_knots(degree: usize, knots: &[f64], n_points: usize) -> bool {
+    degree >= 1
+        && n_points > degree
+        && knots.len() == n_points + degree + 1
+        && knots.windows(2).all(|w| w[0] <= w[1])
+        && knots[degree] < knots[n_points]
+}
+
+// index of the knot span including t
+fn find_span(degree: usize, knots: &[f64], n_points: usize, t: f64) -> usize {
+    let n = n_points - 1;
+    if t >= knots[n + 1] {
+        return n;
+    }
+    if t <= knots[degree] {
+        return degree;
+    }
+    let mut low = degree;
+    let mut high = n + 1;
+    let mut mid = (low + high) / 2;
+    while t < knots[mid] || t >= knots[mid + 1] {
+        if t < knots[mid] {
+            high = mid;
+        }
+        else {
+            low = mid;
+        }
+        mid = (low + high) / 2;
+    }
+    mid
+}
+
+// non-vanishing basis functions N[span - degree ..= span] at t
+fn basis(degree: usize, knots: &[f64], span: usize, t: f64) -> Vec<f64> {
+    let mut n = vec![0.0; degree + 1];
+    let mut left = vec![0.0; degree + 1];
+    let mut right = vec![0.0; degree + 1];
+    n[0] = 1.0;
+    for j in 1..=degree {
+        left[j] = t - knots[span + 1 - j];
+        right[j] = knots[span + j] - t;
+        let mut saved = 0.0;
+        for r in 0..j {
+            let temp = n[r] / (right[r + 1] + left[j - r]);
+            n[r] = saved + right[r + 1] * temp;
+            saved = left[j - r] * temp;
+        }
+        n[j] = saved;
+    }
+    n
+}
+
+impl NurbsCurve {
+    pub fn new(degree: usize, knots: Vec<f64>, points: Vec<V3>, weights: Vec<f64>) -> Option<Self> {
+        if !valid_knots(degree, &knots, points.len()) || weights.len() != points.len() || weights.iter().any(|w| *w <= 0.0) {
+            return None;
+        }
+        Some(NurbsCurve { degree, knots, points, weights })
+    }
+
+    pub fn domain(&self) -> (f64, f64) {
+        (self.knots[self.degree], self.knots[self.points.len()])
+    }
+
+    pub fn eval(&self, t: f64) -> V3 {
+        let span = find_span(self.degree, &self.knots, self.points.len(), t);
+        let n = basis(self.degree, &self.knots, span, t);
+        let mut p = V3::default();
+        let mut w = 0.0;
+        for (i, n) in n.iter().enumerate() {
+            let idx = span - self.degree + i;
+            p = p.add(&self.points[idx].scale(n * self.weights[idx]));
+            w += n * self.weights[idx];
+        }
+        p.scale(1.0 / w)
+    }
+
+    pub fn transform(&self, t: &Transform) -> Self {
+        NurbsCurve {
+            points: self.points.iter().map(|p| t.point(p)).collect(),
+            ..self.clone()
+        }
+    }
+
+    // parameter of the nearest sample point
+    pub fn closest_param(&self, p: &V3, samples: usize) -> f64 {
+        let (t0, t1) = self.domain();
+        let mut best = (f64::INFINITY, t0);
+        for i in 0..=samples {
+            let t = t0 + (t1 - t0) * i as f64 / samples as f64;
+            let d = self.eval(t).sub(p).norm();
+            if d < best.0 {
+                best = (d, t);
+            }
+        }
+        best.1
+    }
+}
+
+impl NurbsSurface {
+    pub fn new(
+        u_degree: usize,
+        v_degree: usize,
+        u_knots: Vec<f64>,
+        v_knots: Vec<f64>,
+        points: Vec<Vec<V3>>,
+        weights: Vec<Vec<f64>>) -> Option<Self>
+    {
+        let v_len = points.first()?.len();
+        if points.iter().any(|row| row.len() != v_len)
+            || weights.len() != points.len()
+            || weights.iter().any(|row| row.len() != v_len || row.iter().any(|w| *w <= 0.0))
+            || !valid_knots(u_degree, &u_knots, points.len())
+            || !valid_knots(v_degree, &v_knots, v_len) {
+            return None;
+        }
+        Some(NurbsSurface { u_degree, v_degree, u_knots, v_knots, points, weights })
+    }
+
+    pub fn domain(&self) -> ((f64, f64), (f64, f64)) {
+        (
+            (self.u_knots[self.u_degree], self.u_knots[self.points.len()]),
+            (self.v_knots[self.v_degree], self.v_knots[self.points[0].len()]),
+        )
+    }
+
+    pub fn eval(&self, u: f64, v: f64) -> V3 {
+        let u_span = find_span(self.u_degree, &self.u_knots, self.points.len(), u);
+        let v_span = find_span(self.v_degree, &self.v_knots, self.points[0].len(), v);
+        let nu = basis(self.u_degree, &self.u_knots, u_span, u);
+        let nv = basis(self.v_degree, &self.v_knots, v_span, v);
+        let mut p = V3::default();
+        let mut w = 0.0;
+        for (i, nu) in nu.iter().enumerate() {
+            for (j, nv) in nv.iter().enumerate() {
+                let (ui, vj) = (u_span - self.u_degree + i, v_span - self.v_degree + j);
+                let k = nu * nv * self.weights[ui][vj];
+                p = p.add(&self.points[ui][vj].scale(k));
+                w += k;
+            }
+        }
+        p.scale(1.0 / w)
+    }
+
+    pub fn transform(&self, t: &Transform) -> Self {
+        NurbsSurface {
+            points: self.points.iter().map(|row| row.iter().map(|p| t.point(p)).collect()).collect(),
+            ..self.clone()
+        }
+    }
+
+    // unit normal by central differences, None at degenerated points
+    pub fn normal(&self, u: f64, v: f64) -> Option<V3> {
+        let ((u0, u1), (v0, v1)) = self.domain();
+        let hu = (u1 - u0) * 1e-6;
+        let hv = (v1 - v0) * 1e-6;
+        let du = self.eval((u + hu).min(u1), v).sub(&self.eval((u - hu).max(u0), v));
+        let dv = self.eval(u, (v + hv).min(v1)).sub(&self.eval(u, (v - hv).max(v0)));
+        let n = du.cross(&dv);
+        if n.norm() < 1e-12 * du.norm().max(dv.norm()).max(1e-300) {
+            None
+        }
+        else {
+            Some(n.normalize())
+        }
+    }
+
+    // points and normals on a n x n grid over the domain
+    pub fn samples(&self, n: usize) -> Vec<(V3, Option<V3>)> {
+        let ((u0, u1), (v0, v1)) = self.domain();
+        let mut samples = Vec::new();
+        for i in 0..=n {
+            for j in 0..=n {
+                let u = u0 + (u1 - u0) * i as f64 / n as f64;
+                let v = v0 + (v1 - v0) * j as f64 / n as f64;
+                samples.push((self.eval(u, v), self.normal(u, v)));
+            }
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_curve() {
+        // quarter circle as a rational quadratic
+        let w = std::f64::consts::FRAC_1_SQRT_2;
+        let c = NurbsCurve::new(
+            2,
+            expand_knots(&[3, 3], &[0.0, 1.0]).unwrap(),
+            vec![V3([1.0, 0.0, 0.0]), V3([1.0, 1.0, 0.0]), V3([0.0, 1.0, 0.0])],
+            vec![1.0, w, 1.0]).unwrap();
+        assert_eq!(c.domain(), (0.0, 1.0));
+        for i in 0..=10 {
+            assert!((c.eval(i as f64 / 10.0).norm() - 1.0).abs() < 1e-12);
+        }
+        assert!(c.eval(0.0).sub(&V3([1.0, 0.0, 0.0])).norm() < 1e-12);
+        assert!(c.eval(1.0).sub(&V3([0.0, 1.0, 0.0])).norm() < 1e-12);
+        assert!((c.closest_param(&V3([0.0, 1.0, 0.0]), 16) - 1.0).abs() < 1e-12);
+        assert!(NurbsCurve::new(2, vec![0.0, 0.0, 1.0, 1.0], vec![V3::default(); 3], vec![1.0; 3]).is_none());
+    }
+
+    #[test]
+    fn test_surface() {
+        // bilinear patch on z = 2
+        let s = NurbsSurface::new(
+            1, 1,
+            vec![0.0, 0.0, 1.0, 1.0],
+            vec![0.0, 0.0, 2.0, 2.0],
+            vec![
+                vec![V3([0.0, 0.0, 2.0]), V3([0.0, 1.0, 2.0])],
+                vec![V3([1.0, 0.0, 2.0]), V3([1.0, 1.0, 2.0])],
+            ],
+            vec![vec![1.0; 2]; 2]).unwrap();
+        assert!(s.eval(0.5, 1.0).sub(&V3([0.5, 0.5, 2.0])).norm() < 1e-12);
+        let n = s.normal(0.5, 0.5).unwrap();
+        assert!(n.sub(&V3([0.0, 0.0, 1.0])).norm() < 1e-6);
+        assert_eq!(s.samples(4).len(), 25);
+    }
+
+    #[test]
+    fn test_expand_knots() {
+        assert_eq!(expand_knots(&[2, 1, 2], &[0.0, 0.5, 1.0]), Some(vec![0.0, 0.0, 0.5, 1.0, 1.0]));
+        assert_eq!(expand_knots(&[2, 1], &[0.0, 0.5, 1.0]), None);
+    }
+}
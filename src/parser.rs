@@ -1,22 +1,82 @@
 use super::preprocess;
-use super::math::V3;
-use std::collections::HashMap;
+use super::stream;
+use super::error::Warning;
+use super::entity;
+use super::db::{DataDB, DanglingReference};
+use super::reference::{self, ReferenceError};
+use super::math::{V3, Transform};
+use super::nurbs::{self, NurbsCurve, NurbsSurface};
 
-#[derive(Default, Debug)]
+// FILE_DESCRIPTION, FILE_NAME and FILE_SCHEMA
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct Header {
-    description: Vec<String>,
-    implementation_level: String,
-    name: String,
-    time_stamp: String,
-    author: Vec<String>,
-    organization: Vec<String>,
-    preprocessor_version: String,
-    originating_system: String,
-    authorisation: String,
-    file_schema: Vec<String>,
+    pub description: Vec<String>,
+    pub implementation_level: String,
+    pub name: String,
+    pub time_stamp: String,
+    pub author: Vec<String>,
+    pub organization: Vec<String>,
+    pub preprocessor_version: String,
+    pub originating_system: String,
+    pub authorisation: String,
+    pub file_schema: Vec<String>,
 }
 
-type DataDB = HashMap<u64, preprocess::Data>;
+// application protocols which can be read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schema {
+    // CONFIG_CONTROL_DESIGN
+    Ap203,
+    Ap203e2,
+    Ap214,
+    Ap242,
+}
+
+impl Schema {
+    // by the name in FILE_SCHEMA, with or without the object identifier
+    pub fn from_name(s: &str) -> Option<Self> {
+        match schema_name(s).as_str() {
+            "CONFIG_CONTROL_DESIGN" => Some(Schema::Ap203),
+            "AP203_CONFIGURATION_CONTROLLED_3D_DESIGN_OF_MECHANICAL_PARTS_AND_ASSEMBLIES_MIM_LF" => Some(Schema::Ap203e2),
+            "AUTOMOTIVE_DESIGN" | "AUTOMOTIVE_DESIGN_CC2" => Some(Schema::Ap214),
+            "AP242_MANAGED_MODEL_BASED_3D_ENGINEERING_MIM_LF" => Some(Schema::Ap242),
+            _ => None,
+        }
+    }
+}
+
+fn schema_name(s: &str) -> String {
+    s.split(|c: char| c.is_whitespace() || c == '{').next().unwrap_or("").to_ascii_uppercase()
+}
+
+impl Header {
+    // the first declared schema, None if not declared,
+    // the name of the first unsupported one as an error
+    pub fn schema(&self) -> Result<Option<Schema>, String> {
+        let mut schemas = Vec::new();
+        for s in &self.file_schema {
+            schemas.push(Schema::from_name(s).ok_or_else(|| schema_name(s))?);
+        }
+        Ok(schemas.first().copied())
+    }
+
+    // labelled fields to trace a program back to the CAD file, empty ones are omitted
+    pub fn trace(&self) -> Vec<(&'static str, String)> {
+        let list = |v: &[String]| v.iter().filter(|s| !s.is_empty()).cloned().collect::<Vec<String>>().join(", ");
+        vec![
+            ("file", self.name.clone()),
+            ("time stamp", self.time_stamp.clone()),
+            ("author", list(&self.author)),
+            ("organization", list(&self.organization)),
+            ("originating system", self.originating_system.clone()),
+            ("preprocessor", self.preprocessor_version.clone()),
+            ("schema", list(&self.file_schema)),
+        ]
+            .into_iter()
+            .filter(|(_, v)| !v.is_empty())
+            .collect()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Axis {
@@ -29,19 +89,232 @@ pub struct Axis {
 pub enum FaceElement {
     Cylinder(f64, Axis),
     Plane(Axis),
+    // radius at the placement and semi-angle
+    Cone(f64, f64, Axis),
+    Sphere(f64, Axis),
+    // major and minor radius
+    Torus(f64, f64, Axis),
+    BSpline(NurbsSurface),
+}
+
+// a bounded line is determined by its vertices
+#[derive(Debug, Clone)]
+pub enum Curve {
+    Line,
+    Circle(f64, Axis),
+    BSpline(NurbsCurve),
+}
+
+#[derive(Debug, Clone)]
+pub struct EdgeCurve {
+    pub start: V3,
+    pub end: V3,
+    pub curve: Curve,
+    pub same_sense: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrientedEdge {
+    pub orientation: bool,
+    pub edge: EdgeCurve,
+}
+
+#[derive(Debug, Clone)]
+pub enum EdgeLoop {
+    Edges(Vec<OrientedEdge>),
+    // degenerated loop like an apex of a cone
+    Vertex(V3),
+}
+
+#[derive(Debug, Clone)]
+pub struct FaceBound {
+    pub outer: bool,
+    pub orientation: bool,
+    pub edge_loop: EdgeLoop,
 }
 
 #[derive(Debug, Clone)]
 pub struct AdvancedFace {
+    pub bounds: Vec<FaceBound>,
     pub flag: bool,
     pub elem: FaceElement,
 }
 
+// segments per full turn when an arc is approximated by a polyline
+const ARC_DIVISION: usize = 32;
+
+impl EdgeCurve {
+    // points from start to the end of the edge, the last point is excluded
+    pub fn points(&self) -> Vec<V3> {
+        match &self.curve {
+            Curve::Line => vec![self.start.clone()],
+            Curve::Circle(r, ax) => {
+                let x = ax.ref_direction.normalize();
+                let y = ax.direction.normalize().cross(&x);
+                let angle = |p: &V3| {
+                    let v = p.sub(&ax.p);
+                    v.dot(&y).atan2(v.dot(&x))
+                };
+                let a0 = angle(&self.start);
+                let mut sweep = angle(&self.end) - a0;
+                if self.same_sense {
+                    if sweep <= 0.0 { sweep += 2.0 * std::f64::consts::PI; }
+                }
+                else if sweep >= 0.0 {
+                    sweep -= 2.0 * std::f64::consts::PI;
+                }
+                let n = ((sweep.abs() / (2.0 * std::f64::consts::PI) * ARC_DIVISION as f64).ceil() as usize).max(1);
+                (0..n)
+                    .map(|i| {
+                        let a = a0 + sweep * i as f64 / n as f64;
+                        ax.p.add(&x.scale(r * a.cos())).add(&y.scale(r * a.sin()))
+                    })
+                    .collect()
+            },
+            Curve::BSpline(c) => {
+                let t0 = c.closest_param(&self.start, ARC_DIVISION * 4);
+                let mut t1 = c.closest_param(&self.end, ARC_DIVISION * 4);
+                // closed curve
+                if (t1 - t0).abs() < 1e-12 {
+                    let (d0, d1) = c.domain();
+                    t1 = if self.same_sense { t0 + (d1 - d0) } else { t0 - (d1 - d0) };
+                }
+                let (d0, d1) = c.domain();
+                let wrap = |t: f64| if t > d1 { t - (d1 - d0) } else if t < d0 { t + (d1 - d0) } else { t };
+                (0..ARC_DIVISION)
+                    .map(|i| c.eval(wrap(t0 + (t1 - t0) * i as f64 / ARC_DIVISION as f64)))
+                    .collect()
+            },
+        }
+    }
+}
+
+impl FaceBound {
+    // closed polyline along the loop in the direction of the bound
+    pub fn points(&self) -> Vec<V3> {
+        match &self.edge_loop {
+            EdgeLoop::Vertex(p) => vec![p.clone()],
+            EdgeLoop::Edges(edges) => {
+                let mut points = Vec::new();
+                for oriented in edges {
+                    let mut edge_points = oriented.edge.points();
+                    if !oriented.orientation {
+                        edge_points.push(oriented.edge.end.clone());
+                        edge_points.reverse();
+                        edge_points.pop();
+                    }
+                    points.append(&mut edge_points);
+                }
+                if !self.orientation {
+                    points.reverse();
+                }
+                points
+            },
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ParseError {
-    HeaderParseError(String),
-    DataParseError(String),
-    PreprocessError(String),
+    Preprocess(preprocess::PreprocessError),
+    Reference(ReferenceError),
+    Header(&'static str),
+    // no entity of this type in the file
+    MissingEntity(&'static str),
+    // the entity at this id cannot be used here
+    UnsupportedEntity(u64, String),
+    // the entity has the expected type but its arguments are malformed
+    InvalidEntity(u64, &'static str),
+    UnsupportedSchema(String),
+    DanglingReferences(Vec<DanglingReference>),
+    MissingReference(u64),
+}
+
+fn entity_name(d: &preprocess::Data) -> String {
+    match d {
+        preprocess::Data::Single(_, name, _) => name.clone(),
+        preprocess::Data::Aggregate(_, partials) => {
+            let names = partials.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>();
+            format!("({})", names.join(" "))
+        },
+    }
+}
+
+fn unsupported(id: u64, d: &preprocess::Data) -> ParseError {
+    ParseError::UnsupportedEntity(id, entity_name(d))
+}
+
+fn get(map: &DataDB, id: u64) -> Result<&preprocess::Data, ParseError> {
+    map.get(id).ok_or(ParseError::MissingReference(id))
+}
+
+impl Axis {
+    pub fn transform(&self, t: &Transform) -> Self {
+        Axis {
+            p: t.point(&self.p),
+            direction: t.vector(&self.direction),
+            ref_direction: t.vector(&self.ref_direction),
+        }
+    }
+
+    // placement as a transformation from its local coordinate system
+    pub fn to_transform(&self) -> Transform {
+        Transform::from_frame(&self.p, &self.direction, &self.ref_direction)
+    }
+}
+
+impl FaceElement {
+    pub fn transform(&self, t: &Transform) -> Self {
+        match self {
+            FaceElement::Cylinder(r, ax) => FaceElement::Cylinder(t.length(*r), ax.transform(t)),
+            FaceElement::Plane(ax) => FaceElement::Plane(ax.transform(t)),
+            FaceElement::Cone(r, semi_angle, ax) => FaceElement::Cone(t.length(*r), *semi_angle, ax.transform(t)),
+            FaceElement::Sphere(r, ax) => FaceElement::Sphere(t.length(*r), ax.transform(t)),
+            FaceElement::Torus(major_r, minor_r, ax) => FaceElement::Torus(t.length(*major_r), t.length(*minor_r), ax.transform(t)),
+            FaceElement::BSpline(surface) => FaceElement::BSpline(surface.transform(t)),
+        }
+    }
+}
+
+impl EdgeCurve {
+    pub fn transform(&self, t: &Transform) -> Self {
+        EdgeCurve {
+            start: t.point(&self.start),
+            end: t.point(&self.end),
+            curve: match &self.curve {
+                Curve::Line => Curve::Line,
+                Curve::Circle(r, ax) => Curve::Circle(t.length(*r), ax.transform(t)),
+                Curve::BSpline(c) => Curve::BSpline(c.transform(t)),
+            },
+            same_sense: self.same_sense,
+        }
+    }
+}
+
+impl AdvancedFace {
+    pub fn transform(&self, t: &Transform) -> Self {
+        let bounds = self.bounds
+            .iter()
+            .map(|bound| FaceBound {
+                edge_loop: match &bound.edge_loop {
+                    EdgeLoop::Edges(edges) => EdgeLoop::Edges(edges
+                        .iter()
+                        .map(|oriented| OrientedEdge {
+                            orientation: oriented.orientation,
+                            edge: oriented.edge.transform(t),
+                        })
+                        .collect()),
+                    EdgeLoop::Vertex(p) => EdgeLoop::Vertex(t.point(p)),
+                },
+                ..bound.clone()
+            })
+            .collect();
+        AdvancedFace {
+            bounds,
+            flag: self.flag,
+            elem: self.elem.transform(t),
+        }
+    }
 }
 
 fn parse_header(parsed_header: Vec<preprocess::Header>) -> Result<Header, ParseError> {
@@ -49,65 +322,81 @@ fn parse_header(parsed_header: Vec<preprocess::Header>) -> Result<Header, ParseE
     for (name, args) in parsed_header {
         match name.as_str() {
             "FILE_DESCRIPTION" => {
-                let e = || ParseError::HeaderParseError("FILE_DESCRIPTION".to_string());
-                header.description = args[0]
-                    .clone()
+                let e = || ParseError::Header("FILE_DESCRIPTION");
+                header.description = args
+                    .first()
+                    .ok_or_else(e)?
                     .tuple()
                     .ok_or_else(e)?
                     .iter()
                     .map(|v| v.str().cloned().ok_or_else(e))
                     .collect::<Result<Vec<String>, ParseError>>()?;
-                header.implementation_level = args[1]
+                header.implementation_level = args
+                    .get(1)
+                    .ok_or_else(e)?
                     .str()
                     .ok_or_else(e)?
                     .clone();
             },
             "FILE_NAME" => {
-                let e = || ParseError::HeaderParseError("FILE_NAME".to_string());
-                header.name = args[0]
+                let e = || ParseError::Header("FILE_NAME");
+                header.name = args
+                    .first()
+                    .ok_or_else(e)?
                     .str()
                     .ok_or_else(e)?
                     .clone();
-                header.time_stamp = args[1]
+                header.time_stamp = args
+                    .get(1)
+                    .ok_or_else(e)?
                     .str()
                     .ok_or_else(e)?
                     .clone();
-                header.author = args[2]
-                    .clone()
+                header.author = args
+                    .get(2)
+                    .ok_or_else(e)?
                     .tuple()
                     .ok_or_else(e)?
                     .iter()
                     .map(|v| v.str().cloned().ok_or_else(e))
                     .collect::<Result<Vec<String>, ParseError>>()?;
-                header.organization = args[3]
-                    .clone()
+                header.organization = args
+                    .get(3)
+                    .ok_or_else(e)?
                     .tuple()
                     .ok_or_else(e)?
                     .iter()
                     .map(|v| v.str().cloned().ok_or_else(e))
                     .collect::<Result<Vec<String>, ParseError>>()?;
-                header.preprocessor_version = args[4]
+                header.preprocessor_version = args
+                    .get(4)
+                    .ok_or_else(e)?
                     .str()
                     .ok_or_else(e)?
                     .clone();
-                header.originating_system = args[5]
+                header.originating_system = args
+                    .get(5)
+                    .ok_or_else(e)?
                     .str()
                     .ok_or_else(e)?
                     .clone();
-                header.authorisation = args[6]
+                header.authorisation = args
+                    .get(6)
+                    .ok_or_else(e)?
                     .str()
                     .ok_or_else(e)?
                     .clone();
             },
             "FILE_SCHEMA" => {
-                let e = || ParseError::HeaderParseError("FILE_SCHEMA".to_string());
-                header.file_schema = args[0]
+                let e = || ParseError::Header("FILE_SCHEMA");
+                header.file_schema = args
+                    .first()
+                    .ok_or_else(e)?
                     .tuple()
                     .ok_or_else(e)?
                     .iter()
                     .map(|v| v.str().cloned().ok_or_else(e))
-                    .collect::<Result<Vec<String>, ParseError>>()?
-                    .clone();
+                    .collect::<Result<Vec<String>, ParseError>>()?;
             },
             _ => {
             }
@@ -116,284 +405,793 @@ fn parse_header(parsed_header: Vec<preprocess::Header>) -> Result<Header, ParseE
     Ok(header)
 }
 
-fn make_db(data: Vec<preprocess::Data>) -> HashMap<u64, preprocess::Data> {
-    let mut map = HashMap::new();
-    for d in data {
-        match d {
-            preprocess::Data::Single(id, _, _) => {
-                map.insert(id, d);
-            },
-            preprocess::Data::Aggregate(id, _) => {
-                map.insert(id, d);
-            },
+const SOLIDS: [&str; 2] = ["MANIFOLD_SOLID_BREP", "BREP_WITH_VOIDS"];
+
+// outer shell and void shells of a solid, with the orientation of the shell
+fn get_closed_shell_ids(map: &DataDB, id: u64) -> Result<Vec<(u64, bool)>, ParseError> {
+    let d = get(map, id)?;
+    let solid: entity::ManifoldSolidBrep = entity::from_data(d)?;
+    let mut shells = vec![(solid.outer, true)];
+    if let preprocess::Data::Single(_, name, _) = d {
+        if name == "BREP_WITH_VOIDS" {
+            let brep: entity::BrepWithVoids = entity::from_data(d)?;
+            for void in brep.voids {
+                let shell = entity::read::<entity::OrientedClosedShell>(map, void)?;
+                shells.push((shell.closed_shell_element, shell.orientation));
+            }
         }
     }
-    map
+    Ok(shells)
 }
 
-fn find_mechanical_design_geometric_presentation_representation_id(map: &DataDB) -> Result<u64, ParseError> {
-    let e = || ParseError::DataParseError("MECHANICAL_DESIGN_GEOMETRIC_PRESENTATION_REPRESENTATION".to_string());
-    for key in map.keys() {
-        if let preprocess::Data::Single(_, desc_name, _) = &map[key] {
-            if let "MECHANICAL_DESIGN_GEOMETRIC_PRESENTATION_REPRESENTATION" = desc_name.as_str() {
-                    return Ok(*key);
-            }
-        }
+// open shells are written as the shells of solids by some exporters
+fn get_advanced_face_ids(map: &DataDB, id: u64) -> Result<Vec<u64>, ParseError> {
+    match entity::read::<entity::ClosedShell>(map, id) {
+        Err(ParseError::UnsupportedEntity(_, _)) => Ok(entity::read::<entity::OpenShell>(map, id)?.cfs_faces),
+        shell => Ok(shell?.cfs_faces),
     }
-    Err(e())
 }
 
-fn get_styled_item_ids(map: &DataDB, id: u64) -> Result<Vec<u64>, ParseError> {
-    let e = || ParseError::DataParseError("MECHANICAL_DESIGN_GEOMETRIC_PRESENTATION_REPRESENTATION".to_string());
-    match &map[&id] {
-        preprocess::Data::Single(_, name, args) => {
-            if name == "MECHANICAL_DESIGN_GEOMETRIC_PRESENTATION_REPRESENTATION" {
-                args
-                    .get(1)
-                    .ok_or_else(e)?
-                    .tuple()
-                    .ok_or_else(e)?
-                    .iter()
-                    .map(|id| id.id().copied())
-                    .collect::<Option<Vec<u64>>>()
-                    .ok_or_else(e)
-            }
-            else {
-                Err(e())
-            }
-        },
-        _ => Err(e())
+fn parse_direction(map: &DataDB, id: u64) -> Result<V3, ParseError> {
+    match entity::read::<entity::Direction>(map, id)?.direction_ratios.as_slice() {
+        [x, y, z] => Ok(V3([*x, *y, *z])),
+        _ => Err(ParseError::InvalidEntity(id, "DIRECTION")),
     }
 }
 
-fn get_manifold_solid_brep_id(map: &DataDB, id: u64) -> Result<u64, ParseError> {
-    let e = || ParseError::DataParseError("STYLED_ITEM".to_owned());
-    match &map[&id] {
-        preprocess::Data::Single(_, name, args) => {
-            if name == "STYLED_ITEM" {
-                args
-                    .get(2)
-                    .ok_or_else(e)?
-                    .id()
-                    .copied()
-                    .ok_or_else(e)
-            }
-            else {
-                Err(e())
+fn parse_cartesian_point(map: &DataDB, id: u64) -> Result<V3, ParseError> {
+    match entity::read::<entity::CartesianPoint>(map, id)?.coordinates.as_slice() {
+        [x, y, z] => Ok(V3([*x, *y, *z])),
+        _ => Err(ParseError::InvalidEntity(id, "CARTESIAN_POINT")),
+    }
+}
+
+fn parse_ref_direction_placement_3d(map: &DataDB, id: u64) -> Result<Axis, ParseError> {
+    let placement: entity::Axis2Placement3d = entity::read(map, id)?;
+    let p = parse_cartesian_point(map, placement.location)?;
+    // both directions are optional
+    let direction = match placement.axis {
+        Some(axis) => parse_direction(map, axis)?,
+        None => V3([0.0, 0.0, 1.0]),
+    };
+    let ref_direction = match placement.ref_direction {
+        Some(ref_direction) => parse_direction(map, ref_direction)?,
+        None => {
+            let x = V3([1.0, 0.0, 0.0]);
+            if x.cross(&direction).norm() < 1e-12 { direction.perpendicular() } else { x }
+        },
+    };
+    Ok(Axis { p, direction, ref_direction })
+}
+
+fn parse_points(map: &DataDB, ids: &[u64]) -> Result<Vec<V3>, ParseError> {
+    ids.iter().map(|id| parse_cartesian_point(map, *id)).collect()
+}
+
+fn partial<'a>(partials: &'a [(String, Vec<preprocess::Value>)], name: &str) -> Option<&'a [preprocess::Value]> {
+    partials.iter().find(|(n, _)| n == name).map(|(_, args)| args.as_slice())
+}
+
+fn parse_b_spline_curve(
+    map: &DataDB,
+    id: u64,
+    curve: &entity::BSplineCurveWithKnots,
+    weights: Option<Vec<f64>>) -> Result<NurbsCurve, ParseError>
+{
+    let e = || ParseError::InvalidEntity(id, "B_SPLINE_CURVE");
+    let points = parse_points(map, &curve.control_points_list)?;
+    let weights = weights.unwrap_or_else(|| vec![1.0; points.len()]);
+    let knots = nurbs::expand_knots(&curve.knot_multiplicities, &curve.knots).ok_or_else(e)?;
+    NurbsCurve::new(curve.degree as usize, knots, points, weights).ok_or_else(e)
+}
+
+fn parse_b_spline_surface(
+    map: &DataDB,
+    id: u64,
+    surface: &entity::BSplineSurfaceWithKnots,
+    weights: Option<Vec<Vec<f64>>>) -> Result<NurbsSurface, ParseError>
+{
+    let e = || ParseError::InvalidEntity(id, "B_SPLINE_SURFACE");
+    let points = surface.control_points_list
+        .iter()
+        .map(|row| parse_points(map, row))
+        .collect::<Result<Vec<Vec<V3>>, ParseError>>()?;
+    let weights = weights.unwrap_or_else(|| points.iter().map(|row| vec![1.0; row.len()]).collect());
+    NurbsSurface::new(
+        surface.u_degree as usize,
+        surface.v_degree as usize,
+        nurbs::expand_knots(&surface.u_multiplicities, &surface.u_knots).ok_or_else(e)?,
+        nurbs::expand_knots(&surface.v_multiplicities, &surface.v_knots).ok_or_else(e)?,
+        points,
+        weights).ok_or_else(e)
+}
+
+fn parse_face_element(map: &DataDB, id: u64) -> Result<FaceElement, ParseError> {
+    let d = get(map, id)?;
+    match d {
+        preprocess::Data::Single(_, name, _) => {
+            match name.as_str() {
+                "PLANE" => {
+                    let plane: entity::Plane = entity::from_data(d)?;
+                    Ok(FaceElement::Plane(parse_ref_direction_placement_3d(map, plane.position)?))
+                },
+                "CYLINDRICAL_SURFACE" => {
+                    let cylinder: entity::CylindricalSurface = entity::from_data(d)?;
+                    let axis = parse_ref_direction_placement_3d(map, cylinder.position)?;
+                    Ok(FaceElement::Cylinder(cylinder.radius, axis))
+                },
+                "CONICAL_SURFACE" => {
+                    let cone: entity::ConicalSurface = entity::from_data(d)?;
+                    let axis = parse_ref_direction_placement_3d(map, cone.position)?;
+                    Ok(FaceElement::Cone(cone.radius, cone.semi_angle, axis))
+                },
+                "SPHERICAL_SURFACE" => {
+                    let sphere: entity::SphericalSurface = entity::from_data(d)?;
+                    let axis = parse_ref_direction_placement_3d(map, sphere.position)?;
+                    Ok(FaceElement::Sphere(sphere.radius, axis))
+                },
+                "TOROIDAL_SURFACE" => {
+                    let torus: entity::ToroidalSurface = entity::from_data(d)?;
+                    let axis = parse_ref_direction_placement_3d(map, torus.position)?;
+                    Ok(FaceElement::Torus(torus.major_radius, torus.minor_radius, axis))
+                },
+                "B_SPLINE_SURFACE_WITH_KNOTS" => {
+                    let surface = entity::from_data(d)?;
+                    Ok(FaceElement::BSpline(parse_b_spline_surface(map, id, &surface, None)?))
+                },
+                _ => Err(ParseError::UnsupportedEntity(id, name.clone()))
             }
         },
-        _ => Err(e())
+        // complex instance like rational B-spline surface
+        preprocess::Data::Aggregate(_, partials) => {
+            let surface = entity::from_data(d)?;
+            let weights = match partial(partials, "RATIONAL_B_SPLINE_SURFACE") {
+                Some(_) => Some(entity::from_data::<entity::RationalBSplineSurface>(d)?.weights_data),
+                None => None,
+            };
+            Ok(FaceElement::BSpline(parse_b_spline_surface(map, id, &surface, weights)?))
+        },
     }
 }
 
-fn get_closed_shell_id(map: &DataDB, id: u64) -> Result<u64, ParseError> {
-    let e = || ParseError::DataParseError("MANIFOLD_SOLID_BREP".to_owned());
-    match &map[&id] {
-        preprocess::Data::Single(_, name, args) => {
-            if name == "MANIFOLD_SOLID_BREP" {
-                args
-                    .get(1)
-                    .ok_or_else(e)?
-                    .id()
-                    .copied()
-                    .ok_or_else(e)
-            }
-            else {
-                Err(e())
+fn parse_vertex_point(map: &DataDB, id: u64) -> Result<V3, ParseError> {
+    parse_cartesian_point(map, entity::read::<entity::VertexPoint>(map, id)?.vertex_geometry)
+}
+
+fn parse_curve(map: &DataDB, id: u64) -> Result<Curve, ParseError> {
+    let d = get(map, id)?;
+    match d {
+        preprocess::Data::Single(_, name, _) => {
+            match name.as_str() {
+                "LINE" => {
+                    entity::from_data::<entity::Line>(d)?;
+                    Ok(Curve::Line)
+                },
+                "CIRCLE" => {
+                    let circle: entity::Circle = entity::from_data(d)?;
+                    Ok(Curve::Circle(circle.radius, parse_ref_direction_placement_3d(map, circle.position)?))
+                },
+                // 3D curve with its parameter space representations
+                name if <entity::SurfaceCurve as entity::Entity>::accepts(name) => {
+                    parse_curve(map, entity::from_data::<entity::SurfaceCurve>(d)?.curve_3d)
+                },
+                "B_SPLINE_CURVE_WITH_KNOTS" => {
+                    let curve = entity::from_data(d)?;
+                    Ok(Curve::BSpline(parse_b_spline_curve(map, id, &curve, None)?))
+                },
+                _ => Err(ParseError::UnsupportedEntity(id, name.clone()))
             }
         },
-        _ => Err(e())
+        preprocess::Data::Aggregate(_, partials) => {
+            let curve = entity::from_data(d)?;
+            let weights = match partial(partials, "RATIONAL_B_SPLINE_CURVE") {
+                Some(_) => Some(entity::from_data::<entity::RationalBSplineCurve>(d)?.weights_data),
+                None => None,
+            };
+            Ok(Curve::BSpline(parse_b_spline_curve(map, id, &curve, weights)?))
+        },
     }
 }
 
-fn get_advanced_face_ids(map: &DataDB, id: u64) -> Result<Vec<u64>, ParseError> {
-    let e = || ParseError::DataParseError("CLOSED_SHELL".to_owned());
-    match &map[&id] {
-        preprocess::Data::Single(_, name, args) => {
-            if name == "CLOSED_SHELL" {
-                args
-                    .get(1)
-                    .ok_or_else(e)?
-                    .tuple()
-                    .ok_or_else(e)?
-                    .iter()
-                    .map(|id| id.id().copied())
-                    .collect::<Option<Vec<u64>>>()
-                    .ok_or_else(e)
-            }
-            else {
-                Err(e())
+fn parse_edge_curve(map: &DataDB, id: u64) -> Result<EdgeCurve, ParseError> {
+    let edge: entity::EdgeCurve = entity::read(map, id)?;
+    Ok(EdgeCurve {
+        start: parse_vertex_point(map, edge.edge_start)?,
+        end: parse_vertex_point(map, edge.edge_end)?,
+        curve: parse_curve(map, edge.edge_geometry)?,
+        same_sense: edge.same_sense,
+    })
+}
+
+fn parse_oriented_edge(map: &DataDB, id: u64) -> Result<OrientedEdge, ParseError> {
+    let edge: entity::OrientedEdge = entity::read(map, id)?;
+    Ok(OrientedEdge { orientation: edge.orientation, edge: parse_edge_curve(map, edge.edge_element)? })
+}
+
+fn parse_edge_loop(map: &DataDB, id: u64) -> Result<EdgeLoop, ParseError> {
+    let d = get(map, id)?;
+    match d {
+        preprocess::Data::Single(_, name, _) => {
+            match name.as_str() {
+                "EDGE_LOOP" => {
+                    let edges = entity::from_data::<entity::EdgeLoop>(d)?
+                        .edge_list
+                        .iter()
+                        .map(|edge_id| parse_oriented_edge(map, *edge_id))
+                        .collect::<Result<Vec<OrientedEdge>, ParseError>>()?;
+                    Ok(EdgeLoop::Edges(edges))
+                },
+                "VERTEX_LOOP" => {
+                    let p = parse_vertex_point(map, entity::from_data::<entity::VertexLoop>(d)?.loop_vertex)?;
+                    Ok(EdgeLoop::Vertex(p))
+                },
+                _ => Err(ParseError::UnsupportedEntity(id, name.clone()))
             }
         },
-        _ => Err(e())
+        d => Err(unsupported(id, d))
     }
 }
-fn parse_direction(map: &DataDB, id: u64) -> Result<V3, ParseError> {
-    let e = || ParseError::DataParseError("DIRECTION".to_owned());
-    match &map[&id] {
-        preprocess::Data::Single(_, name, args) => {
-            if name == "DIRECTION" {
-                let scalars =
-                    args.get(1).ok_or_else(e)?
-                    .tuple().ok_or_else(e)?
-                    .iter().map(|x| x.float().copied()).collect::<Option<Vec<f64>>>().ok_or_else(e)?;
-                if scalars.len() == 3 {
-                    Ok(V3 ([
-                        scalars[0],
-                        scalars[1],
-                        scalars[2],
-                    ]))
-                }
-                else {
-                    Err(e())
-                }
-            }
-            else {
-                Err(e())
-            }
+
+fn parse_face_bound(map: &DataDB, id: u64) -> Result<FaceBound, ParseError> {
+    let d = get(map, id)?;
+    let bound: entity::FaceBound = entity::from_data(d)?;
+    let outer = matches!(d, preprocess::Data::Single(_, name, _) if name == "FACE_OUTER_BOUND");
+    let edge_loop = parse_edge_loop(map, bound.bound)?;
+    Ok(FaceBound { outer, orientation: bound.orientation, edge_loop })
+}
+
+fn parse_advanced_face(map: &DataDB, id: u64) -> Result<AdvancedFace, ParseError> {
+    let face: entity::AdvancedFace = entity::read(map, id)?;
+    let bounds = face.bounds
+        .iter()
+        .map(|bound_id| parse_face_bound(map, *bound_id))
+        .collect::<Result<Vec<FaceBound>, ParseError>>()?;
+    Ok(AdvancedFace {
+        bounds,
+        flag: face.same_sense,
+        elem: parse_face_element(map, face.face_geometry)?,
+    })
+}
+
+// millimetres and radians per unit of a representation context
+#[derive(Debug, Clone, PartialEq)]
+pub struct Units {
+    pub length: f64,
+    pub plane_angle: f64,
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Units { length: 1.0, plane_angle: 1.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UnitKind {
+    Length,
+    PlaneAngle,
+    Other,
+}
+
+fn si_prefix(prefix: &str) -> Option<f64> {
+    let exp = match prefix {
+        "EXA" => 18,
+        "PETA" => 15,
+        "TERA" => 12,
+        "GIGA" => 9,
+        "MEGA" => 6,
+        "KILO" => 3,
+        "HECTO" => 2,
+        "DECA" => 1,
+        "DECI" => -1,
+        "CENTI" => -2,
+        "MILLI" => -3,
+        "MICRO" => -6,
+        "NANO" => -9,
+        "PICO" => -12,
+        "FEMTO" => -15,
+        "ATTO" => -18,
+        _ => return None,
+    };
+    Some(10f64.powi(exp))
+}
+
+// kind of a NAMED_UNIT and its size in millimetres or radians
+fn parse_unit(map: &DataDB, id: u64, depth: usize) -> Result<(UnitKind, f64), ParseError> {
+    let e = || ParseError::InvalidEntity(id, "NAMED_UNIT");
+    // conversion based units defined by each other
+    if depth > 8 {
+        return Err(e());
+    }
+    let partials = match get(map, id)? {
+        preprocess::Data::Aggregate(_, partials) => partials,
+        d => return Err(unsupported(id, d)),
+    };
+    let kind = if partial(partials, "LENGTH_UNIT").is_some() {
+        UnitKind::Length
+    }
+    else if partial(partials, "PLANE_ANGLE_UNIT").is_some() {
+        UnitKind::PlaneAngle
+    }
+    else {
+        return Ok((UnitKind::Other, 1.0));
+    };
+    if let Some(args) = partial(partials, "SI_UNIT") {
+        let e = || ParseError::InvalidEntity(id, "SI_UNIT");
+        let prefix = match args.first().ok_or_else(e)? {
+            preprocess::Value::Undefined => 1.0,
+            preprocess::Value::Enum(prefix) => si_prefix(prefix).ok_or_else(e)?,
+            _ => return Err(e()),
+        };
+        let base = match args.get(1).ok_or_else(e)? {
+            preprocess::Value::Enum(name) if name == "METRE" && kind == UnitKind::Length => 1000.0,
+            preprocess::Value::Enum(name) if name == "RADIAN" && kind == UnitKind::PlaneAngle => 1.0,
+            _ => return Err(e()),
+        };
+        Ok((kind, prefix * base))
+    }
+    else if let Some(args) = partial(partials, "CONVERSION_BASED_UNIT") {
+        // e.g. INCH as LENGTH_MEASURE_WITH_UNIT(LENGTH_MEASURE(25.4),#(mm))
+        let e = || ParseError::InvalidEntity(id, "CONVERSION_BASED_UNIT");
+        let measure_id = arg_id(args, 1).ok_or_else(e)?;
+        let e = || ParseError::InvalidEntity(measure_id, "MEASURE_WITH_UNIT");
+        let measure = match get(map, measure_id)? {
+            preprocess::Data::Single(_, _, args) => args,
+            d => return Err(unsupported(measure_id, d)),
+        };
+        let value = match measure.first().ok_or_else(e)? {
+            preprocess::Value::Desc(_, args) => match args.first() {
+                Some(preprocess::Value::Float(f)) => *f,
+                Some(preprocess::Value::Int(i)) => *i as f64,
+                _ => return Err(e()),
+            },
+            _ => return Err(e()),
+        };
+        let (base_kind, base) = parse_unit(map, arg_id(measure, 1).ok_or_else(e)?, depth + 1)?;
+        if base_kind != kind {
+            return Err(e());
+        }
+        Ok((kind, value * base))
+    }
+    else {
+        Err(ParseError::UnsupportedEntity(id, "NAMED_UNIT".to_owned()))
+    }
+}
+
+fn representation_context(map: &DataDB, representation_id: u64) -> Result<&preprocess::Data, ParseError> {
+    let e = || ParseError::InvalidEntity(representation_id, "SHAPE_REPRESENTATION");
+    let context_id = match get(map, representation_id)? {
+        preprocess::Data::Single(_, _, args) => arg_id(args, 2).ok_or_else(e)?,
+        d => return Err(unsupported(representation_id, d)),
+    };
+    get(map, context_id)
+}
+
+// the first length measure of GLOBAL_UNCERTAINTY_ASSIGNED_CONTEXT in millimetres
+fn representation_uncertainty(map: &DataDB, representation_id: u64) -> Result<Option<f64>, ParseError> {
+    let (context_id, uncertainties) = match representation_context(map, representation_id)? {
+        preprocess::Data::Aggregate(id, partials) => match partial(partials, "GLOBAL_UNCERTAINTY_ASSIGNED_CONTEXT") {
+            Some(args) => (*id, args),
+            None => return Ok(None),
         },
-        _ => Err(e())
+        preprocess::Data::Single(_, _, _) => return Ok(None),
+    };
+    let e = || ParseError::InvalidEntity(context_id, "GLOBAL_UNCERTAINTY_ASSIGNED_CONTEXT");
+    for uncertainty in uncertainties.first().and_then(|v| v.tuple()).ok_or_else(e)? {
+        let id = *uncertainty.id().ok_or_else(e)?;
+        let e = || ParseError::InvalidEntity(id, "UNCERTAINTY_MEASURE_WITH_UNIT");
+        let args = entity_args(map, id, "UNCERTAINTY_MEASURE_WITH_UNIT")?;
+        let value = match args.first().ok_or_else(e)? {
+            preprocess::Value::Desc(_, args) => match args.first() {
+                Some(preprocess::Value::Float(f)) => *f,
+                Some(preprocess::Value::Int(i)) => *i as f64,
+                _ => return Err(e()),
+            },
+            _ => return Err(e()),
+        };
+        if let (UnitKind::Length, mm) = parse_unit(map, arg_id(args, 1).ok_or_else(e)?, 0)? {
+            return Ok(Some(value * mm));
+        }
     }
+    Ok(None)
 }
 
-fn parse_cartesian_point(map: &DataDB, id: u64) -> Result<V3, ParseError> {
-    let e = || ParseError::DataParseError("CARTESIAN_POINT".to_owned());
-    match &map[&id] {
-        preprocess::Data::Single(_, name, args) => {
-            if name == "CARTESIAN_POINT" {
-                let scalars =
-                    args.get(1).ok_or_else(e)?
-                    .tuple().ok_or_else(e)?
-                    .iter().map(|x| x.float().copied()).collect::<Option<Vec<f64>>>().ok_or_else(e)?;
-                if scalars.len() == 3 {
-                    Ok(V3 ([
-                        scalars[0],
-                        scalars[1],
-                        scalars[2],
-                    ]))
-                }
-                else {
-                    Err(e())
-                }
-            }
-            else {
-                Err(e())
-            }
+// units of the context of a representation, millimetres and radians if not given
+fn representation_units(map: &DataDB, representation_id: u64) -> Result<Units, ParseError> {
+    let mut units = Units::default();
+    let (context_id, unit_ids) = match representation_context(map, representation_id)? {
+        preprocess::Data::Aggregate(id, partials) => match partial(partials, "GLOBAL_UNIT_ASSIGNED_CONTEXT") {
+            Some(args) => (*id, args),
+            None => return Ok(units),
         },
-        _ => Err(e())
+        preprocess::Data::Single(_, _, _) => return Ok(units),
+    };
+    let e = || ParseError::InvalidEntity(context_id, "GLOBAL_UNIT_ASSIGNED_CONTEXT");
+    for unit in unit_ids.first().and_then(|v| v.tuple()).ok_or_else(e)? {
+        match parse_unit(map, *unit.id().ok_or_else(e)?, 0)? {
+            (UnitKind::Length, mm) => units.length = mm,
+            (UnitKind::PlaneAngle, rad) => units.plane_angle = rad,
+            (UnitKind::Other, _) => (),
+        }
     }
+    Ok(units)
 }
 
-fn parse_ref_direction_placement_3d(map: &DataDB, id: u64) -> Result<Axis, ParseError> {
-    let e = || ParseError::DataParseError("AXIS2_PLACEMENT_3D".to_owned());
-    match &map[&id] {
-        preprocess::Data::Single(_, name, args) => {
-            if name == "AXIS2_PLACEMENT_3D" {
-                let p = parse_cartesian_point(&map, *args.get(1).ok_or_else(e)?.id().ok_or_else(e)?)?;
-                let direction = parse_direction(&map, *args.get(2).ok_or_else(e)?.id().ok_or_else(e)?)?;
-                let ref_direction = parse_direction(&map, *args.get(3).ok_or_else(e)?.id().ok_or_else(e)?)?;
-                Ok(Axis { p, direction, ref_direction })
+#[derive(Debug, Clone)]
+pub struct Body {
+    pub name: String,
+    // in the coordinate system of the root assembly
+    pub faces: Vec<AdvancedFace>,
+    // RGB in [0, 1] if styled
+    pub colour: Option<[f64; 3]>,
+    // declared uncertainty of distances in millimetres
+    pub uncertainty: Option<f64>,
+}
+
+fn entity_args<'a>(map: &'a DataDB, id: u64, entity: &'static str) -> Result<&'a [preprocess::Value], ParseError> {
+    match get(map, id)? {
+        preprocess::Data::Single(_, name, args) if name == entity => Ok(args),
+        d => Err(unsupported(id, d)),
+    }
+}
+
+fn arg_id(args: &[preprocess::Value], i: usize) -> Option<u64> {
+    args.get(i).and_then(|v| v.id()).copied()
+}
+
+fn product_name(map: &DataDB, product_definition_id: u64) -> Result<String, ParseError> {
+    let e = || ParseError::InvalidEntity(product_definition_id, "PRODUCT_DEFINITION");
+    let args = entity_args(map, product_definition_id, "PRODUCT_DEFINITION")?;
+    let formation_id = arg_id(args, 2).ok_or_else(e)?;
+    let product_id = match get(map, formation_id)? {
+        preprocess::Data::Single(_, _, args) => arg_id(args, 2).ok_or_else(e)?,
+        d => return Err(unsupported(formation_id, d)),
+    };
+    let args = entity_args(map, product_id, "PRODUCT")?;
+    let name = args.get(1).and_then(|v| v.str()).filter(|name| !name.is_empty());
+    let id = args.first().and_then(|v| v.str());
+    Ok(name.or(id).cloned().unwrap_or_default())
+}
+
+// SHAPE_REPRESENTATIONs of a product definition or an assembly usage
+fn shape_representations(map: &DataDB, definition_id: u64) -> Vec<u64> {
+    map.referrers("PRODUCT_DEFINITION_SHAPE", 2, definition_id)
+        .into_iter()
+        .flat_map(|pds| map.referrers("SHAPE_DEFINITION_REPRESENTATION", 0, pds))
+        .filter_map(|sdr| match map.get(sdr) {
+            Some(preprocess::Data::Single(_, _, args)) => arg_id(args, 1),
+            _ => None,
+        })
+        .collect()
+}
+
+// solids in a representation and in representations related to it,
+// with the representation including each
+fn solid_ids(map: &DataDB, representation_id: u64) -> Result<Vec<(u64, u64)>, ParseError> {
+    let e = || ParseError::InvalidEntity(representation_id, "SHAPE_REPRESENTATION");
+    let mut representations = vec![representation_id];
+    let mut srrs = map.referrers("SHAPE_REPRESENTATION_RELATIONSHIP", 2, representation_id);
+    srrs.extend(map.referrers("SHAPE_REPRESENTATION_RELATIONSHIP", 3, representation_id));
+    srrs.sort();
+    for srr in srrs {
+        if let Some(preprocess::Data::Single(_, _, args)) = map.get(srr) {
+            match (arg_id(args, 2), arg_id(args, 3)) {
+                (Some(a), Some(b)) if a == representation_id => representations.push(b),
+                (Some(a), Some(b)) if b == representation_id => representations.push(a),
+                _ => (),
             }
-            else {
-                Err(e())
+        }
+    }
+    let mut solids = Vec::new();
+    for id in representations {
+        let items = match get(map, id)? {
+            preprocess::Data::Single(_, _, args) => args.get(1).and_then(|v| v.tuple()).ok_or_else(e)?,
+            d => return Err(unsupported(id, d)),
+        };
+        for item in items {
+            let item = *item.id().ok_or_else(e)?;
+            if let preprocess::Data::Single(_, name, _) = get(map, item)? {
+                if SOLIDS.contains(&name.as_str()) && !solids.iter().any(|(solid, _)| *solid == item) {
+                    solids.push((item, id));
+                }
             }
-        },
-        _ => Err(e())
+        }
     }
+    Ok(solids)
 }
 
-fn parse_face_element(map: &DataDB, id: u64) -> Result<FaceElement, ParseError> {
-    let e = || ParseError::DataParseError("face element".to_owned());
-    match &map[&id] {
-        preprocess::Data::Single(_, name, args) => {
-            match name.as_str() {
-                "PLANE" => {
-                    let axis = parse_ref_direction_placement_3d(&map, *(args.get(1).ok_or_else(e)?.id().ok_or_else(e)?))?;
-                    Ok(FaceElement::Plane(axis))
-                },
-                "CYLINDRICAL_SURFACE" => {
-                    let r = args.get(2).ok_or_else(e)?.float().ok_or_else(e)?;
-                    let axis = parse_ref_direction_placement_3d(&map, *(args.get(1).ok_or_else(e)?.id().ok_or_else(e)?))?;
-                    Ok(FaceElement::Cylinder(*r, axis))
-                },
-                _ => Err(e())
+// placement of the child in the parent of a NEXT_ASSEMBLY_USAGE_OCCURRENCE
+fn usage_transform(map: &DataDB, nauo_id: u64, child_representations: &[u64]) -> Result<Transform, ParseError> {
+    let cdsr = map.referrers("PRODUCT_DEFINITION_SHAPE", 2, nauo_id)
+        .into_iter()
+        .flat_map(|pds| map.referrers("CONTEXT_DEPENDENT_SHAPE_REPRESENTATION", 1, pds))
+        .next();
+    // a usage without placement
+    let cdsr = match cdsr {
+        Some(cdsr) => cdsr,
+        None => return Ok(Transform::identity()),
+    };
+    let e = || ParseError::InvalidEntity(cdsr, "CONTEXT_DEPENDENT_SHAPE_REPRESENTATION");
+    let rr_id = arg_id(entity_args(map, cdsr, "CONTEXT_DEPENDENT_SHAPE_REPRESENTATION")?, 0).ok_or_else(e)?;
+    let partials = match get(map, rr_id)? {
+        preprocess::Data::Aggregate(_, partials) => partials,
+        d => return Err(unsupported(rr_id, d)),
+    };
+    let e = || ParseError::InvalidEntity(rr_id, "REPRESENTATION_RELATIONSHIP_WITH_TRANSFORMATION");
+    let rr = partial(partials, "REPRESENTATION_RELATIONSHIP").ok_or_else(e)?;
+    let transformation = partial(partials, "REPRESENTATION_RELATIONSHIP_WITH_TRANSFORMATION").ok_or_else(e)?;
+    let idt_id = arg_id(transformation, 0).ok_or_else(e)?;
+    let e = || ParseError::InvalidEntity(idt_id, "ITEM_DEFINED_TRANSFORMATION");
+    let idt = entity_args(map, idt_id, "ITEM_DEFINED_TRANSFORMATION")?;
+    let item_1 = parse_ref_direction_placement_3d(map, arg_id(idt, 2).ok_or_else(e)?)?;
+    let item_2 = parse_ref_direction_placement_3d(map, arg_id(idt, 3).ok_or_else(e)?)?;
+    // moves item_1 in rep_1 onto item_2 in rep_2, rep_1 is usually the child
+    let t = item_2.to_transform().compose(&item_1.to_transform().inverse());
+    // in millimetres on both sides
+    let e = || ParseError::InvalidEntity(rr_id, "REPRESENTATION_RELATIONSHIP");
+    let rep_1_units = representation_units(map, arg_id(rr, 2).ok_or_else(e)?)?;
+    let rep_2_units = representation_units(map, arg_id(rr, 3).ok_or_else(e)?)?;
+    let t = Transform::scaling(rep_2_units.length)
+        .compose(&t)
+        .compose(&Transform::scaling(rep_1_units.length).inverse());
+    match arg_id(rr, 2) {
+        Some(rep) if !child_representations.contains(&rep) => Ok(t.inverse()),
+        _ => Ok(t),
+    }
+}
+
+// faces in millimetres and radians
+fn parse_solid(map: &DataDB, id: u64, units: &Units, placement: &Transform) -> Result<Vec<AdvancedFace>, ParseError> {
+    let t = placement.compose(&Transform::scaling(units.length));
+    let mut faces = Vec::new();
+    for (closed_shell_id, orientation) in get_closed_shell_ids(map, id)? {
+        for face_id in get_advanced_face_ids(map, closed_shell_id)? {
+            let mut face = parse_advanced_face(map, face_id)?.transform(&t);
+            // faces of a reversed shell are reversed
+            face.flag = face.flag == orientation;
+            if let FaceElement::Cone(_, semi_angle, _) = &mut face.elem {
+                *semi_angle *= units.plane_angle;
             }
-        },
-        _ => Err(e())
+            faces.push(face);
+        }
     }
-}/*
+    Ok(faces)
+}
+
+fn parse_colour_rgb(map: &DataDB, id: u64) -> Option<[f64; 3]> {
+    let colour = entity::read::<entity::ColourRgb>(map, id).ok()?;
+    Some([colour.red, colour.green, colour.blue])
+}
 
-type EdgeLoop = Vec<OrientedEdge>;
-type OrientedEdge = Vec<EdgeCurve>;
-type EdgeCurve = Vec<V3>;
+// the first colour found along
+// STYLED_ITEM -> PRESENTATION_STYLE_ASSIGNMENT -> SURFACE_STYLE_USAGE -> SURFACE_SIDE_STYLE
+// -> SURFACE_STYLE_FILL_AREA -> FILL_AREA_STYLE -> FILL_AREA_STYLE_COLOUR -> COLOUR_RGB
+fn style_colour(map: &DataDB, id: u64, depth: usize) -> Option<[f64; 3]> {
+    if depth > 8 {
+        return None;
+    }
+    let args = match map.get(id)? {
+        preprocess::Data::Single(_, name, _) if name == "COLOUR_RGB" => return parse_colour_rgb(map, id),
+        preprocess::Data::Single(_, _, args) => args,
+        _ => return None,
+    };
+    let mut ids = Vec::new();
+    for arg in args {
+        match arg {
+            preprocess::Value::Id(id) => ids.push(*id),
+            preprocess::Value::Tuple(vs) => ids.extend(vs.iter().filter_map(|v| v.id())),
+            _ => (),
+        }
+    }
+    ids.into_iter().find_map(|id| style_colour(map, id, depth + 1))
+}
 
-enum FaceBound {
-    Outer(EdgeLoop),
-    Inner(EdgeLoop),
+// AP203 files have no presentation in the schema, but exporters often write it anyway
+fn solid_colour(map: &DataDB, solid_id: u64) -> Option<[f64; 3]> {
+    map.referrers("STYLED_ITEM", 2, solid_id)
+        .into_iter()
+        .chain(map.referrers("OVER_RIDING_STYLED_ITEM", 2, solid_id))
+        .filter_map(|styled_item| match map.get(styled_item) {
+            Some(preprocess::Data::Single(_, _, args)) => args.get(1).and_then(|v| v.tuple()),
+            _ => None,
+        })
+        .flatten()
+        .find_map(|style| style_colour(map, *style.id()?, 0))
 }
 
-fn parse_face_outer(map: &DataDB, id: u64) -> FaceBound {
+fn solid_name(map: &DataDB, id: u64) -> String {
+    match map.get(id) {
+        Some(preprocess::Data::Single(_, _, args)) => args.first().and_then(|v| v.str()).cloned().unwrap_or_default(),
+        _ => String::new(),
+    }
 }
 
-fn parse_edge_loop(map: &DataDB, id: u64) -> EdgeLoop {
+fn walk_product(
+    map: &DataDB,
+    product_definition_id: u64,
+    placement: &Transform,
+    path: &mut Vec<u64>,
+    bodies: &mut Vec<Body>) -> Result<(), ParseError>
+{
+    // an assembly including itself
+    if path.contains(&product_definition_id) {
+        return Err(ParseError::InvalidEntity(product_definition_id, "NEXT_ASSEMBLY_USAGE_OCCURRENCE"));
+    }
+    path.push(product_definition_id);
+    let name = product_name(map, product_definition_id)?;
+    let representations = shape_representations(map, product_definition_id);
+    let mut solids = Vec::new();
+    for representation in &representations {
+        solids.append(&mut solid_ids(map, *representation)?);
+    }
+    for (solid, representation) in &solids {
+        let name = if solids.len() == 1 { name.clone() } else { format!("{}/{}", name, solid_name(map, *solid)) };
+        bodies.push(Body {
+            name,
+            faces: parse_solid(map, *solid, &representation_units(map, *representation)?, placement)?,
+            colour: solid_colour(map, *solid),
+            uncertainty: representation_uncertainty(map, *representation)?,
+        });
+    }
+    for nauo in map.referrers("NEXT_ASSEMBLY_USAGE_OCCURRENCE", 3, product_definition_id) {
+        let e = || ParseError::InvalidEntity(nauo, "NEXT_ASSEMBLY_USAGE_OCCURRENCE");
+        let child = arg_id(entity_args(map, nauo, "NEXT_ASSEMBLY_USAGE_OCCURRENCE")?, 4).ok_or_else(e)?;
+        let child_representations = shape_representations(map, child);
+        let t = usage_transform(map, nauo, &child_representations)?;
+        walk_product(map, child, &placement.compose(&t), path, bodies)?;
+    }
+    path.pop();
+    Ok(())
+}
+
+// bodies of the product structure in depth first order
+fn find_bodies_in_products(map: &DataDB) -> Result<Vec<Body>, ParseError> {
+    let children = map.find("NEXT_ASSEMBLY_USAGE_OCCURRENCE")
+        .into_iter()
+        .filter_map(|nauo| match map.get(nauo) {
+            Some(preprocess::Data::Single(_, _, args)) => arg_id(args, 4),
+            _ => None,
+        })
+        .collect::<Vec<u64>>();
+    let mut bodies = Vec::new();
+    for root in map.find("PRODUCT_DEFINITION").into_iter().filter(|pd| !children.contains(pd)) {
+        walk_product(map, root, &Transform::identity(), &mut Vec::new(), &mut bodies)?;
+    }
+    // instances of the same part
+    let names = bodies.iter().map(|body| body.name.clone()).collect::<Vec<String>>();
+    for (i, body) in bodies.iter_mut().enumerate() {
+        if names.iter().filter(|name| **name == body.name).count() > 1 {
+            let n = names[..i].iter().filter(|name| **name == body.name).count() + 1;
+            body.name = format!("{} ({})", body.name, n);
+        }
+    }
+    Ok(bodies)
 }
 
-fn parse_oriented_edge(map: &DataDB, id: u64) -> OrientedEdge {
+// files without product structure, solids are found through shape representations
+// and, failing that, taken from all solids in the file
+fn find_bodies_in_representations(map: &DataDB) -> Result<Vec<Body>, ParseError> {
+    let mut solids = Vec::new();
+    for representation in map.find("ADVANCED_BREP_SHAPE_REPRESENTATION").into_iter().chain(map.find("SHAPE_REPRESENTATION")) {
+        for (solid, representation) in solid_ids(map, representation)? {
+            if !solids.iter().any(|(s, _)| *s == solid) {
+                solids.push((solid, Some(representation)));
+            }
+        }
+    }
+    if solids.is_empty() {
+        solids = SOLIDS.iter().flat_map(|name| map.find(name)).map(|solid| (solid, None)).collect();
+    }
+    solids
+        .into_iter()
+        .map(|(solid, representation)| {
+            let (units, uncertainty) = match representation {
+                Some(representation) => (representation_units(map, representation)?, representation_uncertainty(map, representation)?),
+                None => (Units::default(), None),
+            };
+            Ok(Body {
+                name: solid_name(map, solid),
+                faces: parse_solid(map, solid, &units, &Transform::identity())?,
+                colour: solid_colour(map, solid),
+                uncertainty,
+            })
+        })
+        .collect()
 }
 
-fn parse_edge_curve(map: &DataDB, id: u64) -> EdgeCurve {
+fn find_bodies(map: &DataDB) -> Result<Vec<Body>, ParseError> {
+    let bodies = find_bodies_in_products(map)?;
+    if bodies.is_empty() {
+        find_bodies_in_representations(map)
+    }
+    else {
+        Ok(bodies)
+    }
 }
 
-fn parse_vertex_point(map: &DataDB, id: u64) -> V3 {
-}*/
+fn parse_data(parsed_data: Vec<preprocess::Data>) -> Result<Vec<Body>, ParseError> {
+    let map = DataDB::new(parsed_data);
+    map.validate().map_err(ParseError::DanglingReferences)?;
+    find_bodies(&map)
+}
 
-fn parse_advanced_face(map: &DataDB, id: u64) -> Result<AdvancedFace, ParseError> {
-    let e = || ParseError::DataParseError("ADVANCED_FACE".to_owned());
-    match &map[&id] {
-        preprocess::Data::Single(_, name, args) => {
-            if name == "ADVANCED_FACE" {
-                let flag = args.get(3).ok_or_else(e)?.boolean().ok_or_else(e)?;
-                let element_id = args.get(2).ok_or_else(e)?.id().ok_or_else(e)?;
-                Ok(AdvancedFace {
-                    flag: *flag,
-                    elem: parse_face_element(&map, *element_id)?
-                })
+// true if an integer is converted
+fn to_real(v: &mut preprocess::Value) -> bool {
+    match v {
+        preprocess::Value::Int(i) => {
+            *v = preprocess::Value::Float(*i as f64);
+            true
+        },
+        preprocess::Value::Tuple(vs) => {
+            // all of the elements
+            let mut converted = false;
+            for v in vs.iter_mut() {
+                converted |= to_real(v);
             }
-            else {
-                Err(e())
+            converted
+        },
+        _ => false,
+    }
+}
+
+// integers written where reals are expected (without the dot), for the tolerant mode
+fn relax_reals(data: &mut [preprocess::Data], warnings: &mut Vec<Warning>) {
+    // partial entities have only their own attributes, the last ones
+    let relax = |id: u64, name: &str, args: &mut Vec<preprocess::Value>, partial: bool, warnings: &mut Vec<Warning>| {
+        let reals = entity::reals(name).unwrap_or(&[]);
+        let offset = if partial { reals.len().saturating_sub(args.len()) } else { 0 };
+        for (i, arg) in args.iter_mut().enumerate() {
+            if reals.get(offset + i) == Some(&true) && to_real(arg) {
+                warnings.push(Warning {
+                    span: None,
+                    id: Some(id),
+                    message: format!("integer as real in argument {} of {}", i, name),
+                });
             }
         }
-        _ => Err(e())
-    }
-}
-
-fn parse_data(parsed_data: Vec<preprocess::Data>) -> Result<Vec<AdvancedFace>, ParseError> {
-    let map = make_db(parsed_data);
-    // ad-hoc
-    let root_id = find_mechanical_design_geometric_presentation_representation_id(&map)?;
-    let styled_item_ids = get_styled_item_ids(&map, root_id)?;
-    let manifold_solid_brep_id = get_manifold_solid_brep_id(&map, styled_item_ids[0])?;
-    let closed_shell_id = get_closed_shell_id(&map, manifold_solid_brep_id)?;
-    let advanced_face_ids = get_advanced_face_ids(&map, closed_shell_id)?;
-    advanced_face_ids.iter().map(|face_id| parse_advanced_face(&map, *face_id)).collect()
-}
-
-pub fn parse(s : &str) -> Result<(Header, Vec<AdvancedFace>), ParseError> {
-    let parsed = preprocess::parse(s).map_err(
-        |e| match e {
-            preprocess::PreprocessError::Fail(info) => {
-                match info {
-                    preprocess::PreprocessErrorInfo::LineCol((l, c)) =>
-                        ParseError::PreprocessError(format!("irregular syntax at {}:{}", l, c)),
-                    preprocess::PreprocessErrorInfo::Span((l1, c1), (l2, c2)) =>
-                        ParseError::PreprocessError(format!("irregular syntax in {}:{} -- {}:{}", l1, c1, l2, c2))
+    };
+    for d in data.iter_mut() {
+        match d {
+            preprocess::Data::Single(id, name, args) => relax(*id, name, args, false, warnings),
+            preprocess::Data::Aggregate(id, partials) => {
+                for (name, args) in partials.iter_mut() {
+                    relax(*id, name, args, true, warnings);
                 }
             },
-            preprocess::PreprocessError::InternalError => {
-                ParseError::PreprocessError("internal parser error".to_owned())
-            }
-        })?;
-    Ok((parse_header(parsed.header)?, parse_data(parsed.data)?))
+        }
+    }
+}
+
+// load reads files referred from the REFERENCE section,
+// the tolerant mode collecting warnings skips malformed entities which are not needed
+pub fn parse(s : &str, load: &dyn Fn(&str) -> Result<String, String>, mut warnings: Option<&mut Vec<Warning>>)
+    -> Result<(Header, Vec<Body>), ParseError>
+{
+    let mut parsed = match warnings.as_deref_mut() {
+        Some(warnings) => {
+            let (parsed, skipped) = stream::parse_tolerant(s).map_err(ParseError::Preprocess)?;
+            warnings.extend(skipped);
+            parsed
+        },
+        None => stream::parse(s).map_err(ParseError::Preprocess)?,
+    };
+    reference::resolve(&mut parsed, load).map_err(ParseError::Reference)?;
+    let header = parse_header(parsed.header)?;
+    header.schema().map_err(ParseError::UnsupportedSchema)?;
+    match warnings {
+        Some(warnings) => {
+            relax_reals(&mut parsed.data, warnings);
+            let map = DataDB::new(parsed.data);
+            // missing entities are an error only where they are read
+            warnings.extend(map.dangling_references().into_iter().map(|r| Warning {
+                span: None,
+                id: Some(r.from),
+                message: format!("argument {} refers to missing #{}", r.arg, r.to),
+            }));
+            Ok((header, find_bodies(&map)?))
+        },
+        None => Ok((header, parse_data(parsed.data)?)),
+    }
 }
 
 #[cfg(test)]
@@ -421,7 +1219,7 @@ mod test {
             "2;1");
         assert_eq!(
             header.name,
-            r"D:\\Desktop\\Part1.stp");
+            r"D:\Desktop\Part1.stp");
         assert_eq!(
             header.time_stamp,
             "2020-01-17T21:29:41+09:00");
@@ -443,18 +1241,396 @@ mod test {
         assert_eq!(
             header.file_schema,
             vec!["AUTOMOTIVE_DESIGN { 1 0 10303 214 3 1 1 }"]);
+        assert_eq!(
+            header.trace(),
+            vec![
+                ("file", r"D:\Desktop\Part1.stp".to_owned()),
+                ("time stamp", "2020-01-17T21:29:41+09:00".to_owned()),
+                ("author", "Tsuba".to_owned()),
+                ("originating system", "Autodesk Inventor 2018".to_owned()),
+                ("preprocessor", "ST-DEVELOPER v17".to_owned()),
+                ("schema", "AUTOMOTIVE_DESIGN { 1 0 10303 214 3 1 1 }".to_owned()),
+            ]);
     }
 
     #[test]
-    fn test_find_mechanical_design_geometric_presentation_representation_id() {
-        let data = make_db(prepare_test_data().data);
-        assert_eq!(find_mechanical_design_geometric_presentation_representation_id(&data),
-            Ok(10));
+    fn test_unsupported_entity() {
+        let data = preprocess::parse(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            DATA;
+            #1=CARTESIAN_POINT('',(0.,0.,0.));
+            #2=DIRECTION('',(0.,0.,1.));
+            #3=AXIS2_PLACEMENT_3D('',#1,#2,#2);
+            #4=CONICAL_SURFACE('',#3,1.,0.5);
+            #5=DIRECTION('',(0.,1.));
+            #6=SURFACE_OF_REVOLUTION('',#7,#3);
+            #7=CIRCLE('',#3,1.);
+            #8=TOROIDAL_SURFACE('',#3,10.,2.);
+            #9=SPHERICAL_SURFACE('',#3);
+            #10=CLOSED_SHELL('',(#4));
+            #11=OPEN_SHELL('',(#8));
+            #12=CONNECTED_FACE_SET('',(#4));
+            ENDSEC;
+            END-ISO-10303-21;").unwrap();
+        let map = DataDB::new(data.data);
+        match parse_face_element(&map, 4).unwrap() {
+            FaceElement::Cone(r, semi_angle, _) => assert_eq!((r, semi_angle), (1.0, 0.5)),
+            elem => panic!("{:?}", elem),
+        }
+        match parse_face_element(&map, 8).unwrap() {
+            FaceElement::Torus(major_r, minor_r, _) => assert_eq!((major_r, minor_r), (10.0, 2.0)),
+            elem => panic!("{:?}", elem),
+        }
+        assert_eq!(parse_face_element(&map, 9).unwrap_err(), ParseError::InvalidEntity(9, "SPHERICAL_SURFACE"));
+        assert_eq!(parse_face_element(&map, 6).unwrap_err(), ParseError::UnsupportedEntity(6, "SURFACE_OF_REVOLUTION".to_owned()));
+        assert_eq!(parse_direction(&map, 1).unwrap_err(), ParseError::UnsupportedEntity(1, "CARTESIAN_POINT".to_owned()));
+        assert_eq!(parse_direction(&map, 5).unwrap_err(), ParseError::InvalidEntity(5, "DIRECTION"));
+        // shells of solids
+        assert_eq!(get_advanced_face_ids(&map, 10).unwrap(), vec![4]);
+        assert_eq!(get_advanced_face_ids(&map, 11).unwrap(), vec![8]);
+        assert_eq!(get_advanced_face_ids(&map, 12).unwrap_err(), ParseError::UnsupportedEntity(12, "CONNECTED_FACE_SET".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_b_spline() {
+        let data = preprocess::parse(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            DATA;
+            #1=CARTESIAN_POINT('',(15.,0.,0.));
+            #2=CARTESIAN_POINT('',(15.,0.,10.));
+            #3=CARTESIAN_POINT('',(15.,5.,0.));
+            #4=CARTESIAN_POINT('',(15.,5.,10.));
+            #5=CARTESIAN_POINT('',(10.,5.,0.));
+            #6=CARTESIAN_POINT('',(10.,5.,10.));
+            #7=(
+            BOUNDED_SURFACE()
+            B_SPLINE_SURFACE(2,1,((#1,#2),(#3,#4),(#5,#6)),.UNSPECIFIED.,.F.,.F.,.F.)
+            B_SPLINE_SURFACE_WITH_KNOTS((3,3),(2,2),(0.,1.),(0.,10.),.UNSPECIFIED.)
+            GEOMETRIC_REPRESENTATION_ITEM()
+            RATIONAL_B_SPLINE_SURFACE(((1.,1.),(0.707106781186548,0.707106781186548),(1.,1.)))
+            REPRESENTATION_ITEM('')
+            SURFACE()
+            );
+            #8=B_SPLINE_CURVE_WITH_KNOTS('',1,(#1,#3,#5),.POLYLINE_FORM.,.F.,.F.,(2,1,2),(0.,0.5,1.),.UNSPECIFIED.);
+            #9=B_SPLINE_SURFACE_WITH_KNOTS('',2,1,((#1,#2),(#3,#4)),.UNSPECIFIED.,.F.,.F.,.F.,(3,3),(2,2),(0.,1.),(0.,10.),.UNSPECIFIED.);
+            ENDSEC;
+            END-ISO-10303-21;").unwrap();
+        let map = DataDB::new(data.data);
+        match parse_face_element(&map, 7).unwrap() {
+            FaceElement::BSpline(surface) => {
+                let p = surface.eval(0.5, 5.0);
+                assert!((p.sub(&V3([10.0, 0.0, 5.0])).norm() - 5.0).abs() < 1e-9);
+            },
+            elem => panic!("{:?}", elem),
+        }
+        match parse_curve(&map, 8).unwrap() {
+            Curve::BSpline(curve) => assert!(curve.eval(0.75).sub(&V3([12.5, 5.0, 0.0])).norm() < 1e-12),
+            curve => panic!("{:?}", curve),
+        }
+        assert_eq!(parse_face_element(&map, 9).unwrap_err(), ParseError::InvalidEntity(9, "B_SPLINE_SURFACE"));
     }
 
     #[test]
     fn test_parse_data() {
         let data = prepare_test_data();
-        parse_data(data.data).unwrap();
+        let bodies = parse_data(data.data).unwrap();
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0].colour, Some([0.749019607843137; 3]));
+        assert_eq!(bodies[0].uncertainty, Some(0.01));
+        let faces = &bodies[0].faces;
+        assert_eq!(faces.len(), 38);
+        assert!(faces.iter().all(|face| face.bounds.iter().filter(|bound| bound.outer).count() == 1));
+        assert_eq!(faces.iter().map(|face| face.bounds.len()).sum::<usize>(), 38 + 58);
+    }
+
+    #[test]
+    fn test_parse_assembly() {
+        // a part placed twice in an assembly, the second one at x = 10
+        let data = preprocess::parse(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            DATA;
+            #1=CARTESIAN_POINT('',(0.,0.,0.));
+            #2=DIRECTION('',(0.,0.,1.));
+            #3=DIRECTION('',(1.,0.,0.));
+            #4=AXIS2_PLACEMENT_3D('',#1,#2,#3);
+            #5=PLANE('',#4);
+            #6=VERTEX_POINT('',#1);
+            #7=VERTEX_LOOP('',#6);
+            #8=FACE_OUTER_BOUND('',#7,.T.);
+            #9=ADVANCED_FACE('',(#8),#5,.T.);
+            #10=CLOSED_SHELL('',(#9));
+            #11=MANIFOLD_SOLID_BREP('body',#10);
+            #12=SHAPE_REPRESENTATION('',(#11,#4),#13);
+            #13=REPRESENTATION_CONTEXT('','3D');
+            #20=PRODUCT('part','pipe','',(#21));
+            #21=PRODUCT_CONTEXT('',#22,'mechanical');
+            #22=APPLICATION_CONTEXT('');
+            #23=PRODUCT_DEFINITION_FORMATION('','',#20);
+            #24=PRODUCT_DEFINITION('design','',#23,#25);
+            #25=PRODUCT_DEFINITION_CONTEXT('part definition',#22,'design');
+            #26=PRODUCT_DEFINITION_SHAPE('','',#24);
+            #27=SHAPE_DEFINITION_REPRESENTATION(#26,#12);
+            #30=PRODUCT('asm','frame','',(#21));
+            #33=PRODUCT_DEFINITION_FORMATION('','',#30);
+            #34=PRODUCT_DEFINITION('design','',#33,#25);
+            #36=PRODUCT_DEFINITION_SHAPE('','',#34);
+            #37=SHAPE_DEFINITION_REPRESENTATION(#36,#38);
+            #38=SHAPE_REPRESENTATION('',(#4,#41),#13);
+            #40=CARTESIAN_POINT('',(10.,0.,0.));
+            #41=AXIS2_PLACEMENT_3D('',#40,#2,$);
+            #50=NEXT_ASSEMBLY_USAGE_OCCURRENCE('1','','',#34,#24,$);
+            #51=PRODUCT_DEFINITION_SHAPE('','',#50);
+            #52=CONTEXT_DEPENDENT_SHAPE_REPRESENTATION(#53,#51);
+            #53=(REPRESENTATION_RELATIONSHIP('','',#12,#38)REPRESENTATION_RELATIONSHIP_WITH_TRANSFORMATION(#54)SHAPE_REPRESENTATION_RELATIONSHIP());
+            #54=ITEM_DEFINED_TRANSFORMATION('','',#4,#4);
+            #60=NEXT_ASSEMBLY_USAGE_OCCURRENCE('2','','',#34,#24,$);
+            #61=PRODUCT_DEFINITION_SHAPE('','',#60);
+            #62=CONTEXT_DEPENDENT_SHAPE_REPRESENTATION(#63,#61);
+            #63=(REPRESENTATION_RELATIONSHIP('','',#12,#38)REPRESENTATION_RELATIONSHIP_WITH_TRANSFORMATION(#64)SHAPE_REPRESENTATION_RELATIONSHIP());
+            #64=ITEM_DEFINED_TRANSFORMATION('','',#4,#41);
+            ENDSEC;
+            END-ISO-10303-21;").unwrap();
+        let bodies = parse_data(data.data).unwrap();
+        assert_eq!(bodies.iter().map(|body| body.name.as_str()).collect::<Vec<&str>>(), vec!["pipe (1)", "pipe (2)"]);
+        let origin = |body: &Body| match &body.faces[0].elem {
+            FaceElement::Plane(ax) => ax.p.clone(),
+            elem => panic!("{:?}", elem),
+        };
+        assert_eq!(origin(&bodies[0]), V3([0.0, 0.0, 0.0]));
+        assert!(origin(&bodies[1]).sub(&V3([10.0, 0.0, 0.0])).norm() < 1e-12);
+        match &bodies[1].faces[0].bounds[0].edge_loop {
+            EdgeLoop::Vertex(p) => assert!(p.sub(&V3([10.0, 0.0, 0.0])).norm() < 1e-12),
+            edge_loop => panic!("{:?}", edge_loop),
+        }
+    }
+
+    #[test]
+    fn test_parse_without_presentation() {
+        let solid = r"
+            #1=CARTESIAN_POINT('',(0.,0.,0.));
+            #2=DIRECTION('',(0.,0.,1.));
+            #3=DIRECTION('',(1.,0.,0.));
+            #4=AXIS2_PLACEMENT_3D('',#1,#2,#3);
+            #5=PLANE('',#4);
+            #6=VERTEX_POINT('',#1);
+            #7=VERTEX_LOOP('',#6);
+            #8=FACE_OUTER_BOUND('',#7,.T.);
+            #9=ADVANCED_FACE('',(#8),#5,.T.);
+            #10=CLOSED_SHELL('',(#9));
+            #11=ADVANCED_FACE('',(#8),#5,.F.);
+            #12=CLOSED_SHELL('',(#11));
+            #13=ORIENTED_CLOSED_SHELL('',*,#12,.F.);
+            #14=BREP_WITH_VOIDS('hollow',#10,(#13));";
+        let step = |data: &str| preprocess::parse(&format!(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            DATA;
+            {}
+            {}
+            ENDSEC;
+            END-ISO-10303-21;", solid, data)).unwrap();
+        // a bare solid
+        let bodies = parse_data(step("").data).unwrap();
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0].name, "hollow");
+        assert_eq!(bodies[0].faces.len(), 2);
+        // the reversed face of the reversed void shell faces the same way
+        assert!(bodies[0].faces.iter().all(|face| face.flag));
+        assert_eq!(bodies[0].colour, None);
+        // through a shape representation, one solid is not in it
+        let styled = r"
+            #20=ADVANCED_BREP_SHAPE_REPRESENTATION('',(#4,#21),#22);
+            #21=MANIFOLD_SOLID_BREP('solid',#10);
+            #22=REPRESENTATION_CONTEXT('','3D');
+            #23=STYLED_ITEM('',(#24),#21);
+            #24=PRESENTATION_STYLE_ASSIGNMENT((#25));
+            #25=SURFACE_STYLE_USAGE(.BOTH.,#26);
+            #26=SURFACE_SIDE_STYLE('',(#27));
+            #27=SURFACE_STYLE_FILL_AREA(#28);
+            #28=FILL_AREA_STYLE('',(#29));
+            #29=FILL_AREA_STYLE_COLOUR('',#30);
+            #30=COLOUR_RGB('',1.,0.5,0.);";
+        let bodies = parse_data(step(styled).data).unwrap();
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0].name, "solid");
+        assert_eq!(bodies[0].faces.len(), 1);
+        assert_eq!(bodies[0].colour, Some([1.0, 0.5, 0.0]));
+        // written by exporters of AP203 too
+        let ap203 = format!(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            FILE_SCHEMA(('CONFIG_CONTROL_DESIGN'));
+            ENDSEC;
+            DATA;
+            {}
+            {}
+            ENDSEC;
+            END-ISO-10303-21;", solid, styled);
+        let (_, bodies) = parse(&ap203, &|_| Err(String::new()), None).unwrap();
+        assert_eq!(bodies[0].colour, Some([1.0, 0.5, 0.0]));
+    }
+
+    #[test]
+    fn test_schema() {
+        let header = |schemas: &[&str]| Header {
+            file_schema: schemas.iter().map(|s| s.to_string()).collect(),
+            ..Header::default()
+        };
+        assert_eq!(header(&[]).schema(), Ok(None));
+        assert_eq!(header(&["AUTOMOTIVE_DESIGN { 1 0 10303 214 3 1 1 }"]).schema(), Ok(Some(Schema::Ap214)));
+        assert_eq!(header(&["config_control_design"]).schema(), Ok(Some(Schema::Ap203)));
+        assert_eq!(header(&["AP242_MANAGED_MODEL_BASED_3D_ENGINEERING_MIM_LF { 1 0 10303 442 1 1 4 }"]).schema(), Ok(Some(Schema::Ap242)));
+        assert_eq!(header(&["AP209_MULTIDISCIPLINARY_ANALYSIS_AND_DESIGN_MIM_LF"]).schema(),
+            Err("AP209_MULTIDISCIPLINARY_ANALYSIS_AND_DESIGN_MIM_LF".to_owned()));
+        let ifc = fs::read_to_string("./example.STEP").unwrap()
+            .replace("AUTOMOTIVE_DESIGN { 1 0 10303 214 3 1 1 }", "IFC4");
+        assert_eq!(parse(&ifc, &|_| Err(String::new()), None).unwrap_err(), ParseError::UnsupportedSchema("IFC4".to_owned()));
+    }
+
+    #[test]
+    fn test_units() {
+        let data = preprocess::parse(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            DATA;
+            #1=CARTESIAN_POINT('',(1.,0.,0.));
+            #2=DIRECTION('',(0.,0.,1.));
+            #3=DIRECTION('',(1.,0.,0.));
+            #4=AXIS2_PLACEMENT_3D('',#1,#2,#3);
+            #5=CONICAL_SURFACE('',#4,2.,45.);
+            #6=VERTEX_POINT('',#1);
+            #7=VERTEX_LOOP('',#6);
+            #8=FACE_OUTER_BOUND('',#7,.T.);
+            #9=ADVANCED_FACE('',(#8),#5,.T.);
+            #10=CLOSED_SHELL('',(#9));
+            #11=MANIFOLD_SOLID_BREP('',#10);
+            #12=ADVANCED_BREP_SHAPE_REPRESENTATION('',(#11),#13);
+            #13=(
+            GEOMETRIC_REPRESENTATION_CONTEXT(3)
+            GLOBAL_UNIT_ASSIGNED_CONTEXT((#14,#16,#18))
+            REPRESENTATION_CONTEXT('','3D')
+            );
+            #14=(CONVERSION_BASED_UNIT('INCH',#15)LENGTH_UNIT()NAMED_UNIT(*));
+            #15=LENGTH_MEASURE_WITH_UNIT(LENGTH_MEASURE(25.4),#19);
+            #16=(CONVERSION_BASED_UNIT('DEGREE',#17)NAMED_UNIT(*)PLANE_ANGLE_UNIT());
+            #17=PLANE_ANGLE_MEASURE_WITH_UNIT(PLANE_ANGLE_MEASURE(0.0174532925199433),#20);
+            #18=(NAMED_UNIT(*)SI_UNIT($,.STERADIAN.)SOLID_ANGLE_UNIT());
+            #19=(LENGTH_UNIT()NAMED_UNIT(*)SI_UNIT(.MILLI.,.METRE.));
+            #20=(NAMED_UNIT(*)PLANE_ANGLE_UNIT()SI_UNIT($,.RADIAN.));
+            #21=(LENGTH_UNIT()NAMED_UNIT(*)SI_UNIT(.CENTI.,.METRE.));
+            #22=(LENGTH_UNIT()NAMED_UNIT(*)SI_UNIT(.CENTI.,.RADIAN.));
+            ENDSEC;
+            END-ISO-10303-21;").unwrap();
+        let map = DataDB::new(data.data);
+        assert_eq!(parse_unit(&map, 21, 0).unwrap(), (UnitKind::Length, 10.0));
+        assert_eq!(parse_unit(&map, 22, 0).unwrap_err(), ParseError::InvalidEntity(22, "SI_UNIT"));
+        let units = representation_units(&map, 12).unwrap();
+        assert!((units.length - 25.4).abs() < 1e-12);
+        let bodies = find_bodies_in_representations(&map).unwrap();
+        match &bodies[0].faces[0].elem {
+            FaceElement::Cone(r, semi_angle, ax) => {
+                assert!((r - 50.8).abs() < 1e-12);
+                assert!((semi_angle - std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+                assert!(ax.p.sub(&V3([25.4, 0.0, 0.0])).norm() < 1e-12);
+            },
+            elem => panic!("{:?}", elem),
+        }
+    }
+
+    #[test]
+    fn test_edge_points() {
+        let axis = Axis {
+            p: V3([0.0, 0.0, 0.0]),
+            direction: V3([0.0, 0.0, 1.0]),
+            ref_direction: V3([1.0, 0.0, 0.0]),
+        };
+        let half = EdgeCurve {
+            start: V3([1.0, 0.0, 0.0]),
+            end: V3([-1.0, 0.0, 0.0]),
+            curve: Curve::Circle(1.0, axis.clone()),
+            same_sense: true,
+        };
+        let points = half.points();
+        assert_eq!(points.len(), ARC_DIVISION / 2);
+        assert!(points.iter().all(|p| p.y() > -1e-9));
+        let reversed = EdgeCurve { same_sense: false, ..half.clone() };
+        assert!(reversed.points().iter().all(|p| p.y() < 1e-9));
+        let full = EdgeCurve {
+            end: V3([1.0, 0.0, 0.0]),
+            ..half.clone()
+        };
+        assert_eq!(full.points().len(), ARC_DIVISION);
+        let bound = FaceBound {
+            outer: true,
+            orientation: false,
+            edge_loop: EdgeLoop::Edges(vec![
+                OrientedEdge { orientation: true, edge: half.clone() },
+                OrientedEdge {
+                    orientation: false,
+                    edge: EdgeCurve { curve: Curve::Line, ..half },
+                },
+            ]),
+        };
+        let points = bound.points();
+        assert_eq!(points.len(), ARC_DIVISION / 2 + 1);
+        assert_eq!(points[0], V3([-1.0, 0.0, 0.0]));
+        assert_eq!(points[ARC_DIVISION / 2], V3([1.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn test_dangling_reference() {
+        let data = preprocess::parse(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            DATA;
+            #10=MECHANICAL_DESIGN_GEOMETRIC_PRESENTATION_REPRESENTATION('',(#13),#11);
+            #13=STYLED_ITEM('',(#12),#14);
+            #12=PRESENTATION_STYLE_ASSIGNMENT((#15));
+            ENDSEC;
+            END-ISO-10303-21;").unwrap();
+        assert_eq!(parse_data(data.data).unwrap_err(), ParseError::DanglingReferences(vec![
+            DanglingReference { from: 10, arg: 2, to: 11 },
+            DanglingReference { from: 12, arg: 0, to: 15 },
+            DanglingReference { from: 13, arg: 2, to: 14 },
+        ]));
+        let map = DataDB::new(Vec::new());
+        assert_eq!(parse_direction(&map, 3).unwrap_err(), ParseError::MissingReference(3));
+    }
+
+    #[test]
+    fn test_tolerant() {
+        let buf = fs::read_to_string("./example.STEP").unwrap()
+            .replace("DATA;", "DATA;\n#90000=FOO(;\n;\n#90001=VERTEX_POINT('',#90000);")
+            .replace(".T.", ".t.")
+            .replace("#1307=CARTESIAN_POINT('',(0.,0.,0.));", "#1307=CARTESIAN_POINT('',(0,0.,0));");
+        assert!(parse(&buf, &|_| Err(String::new()), None).is_err());
+        let mut warnings = Vec::new();
+        let (_, bodies) = parse(&buf, &|_| Err(String::new()), Some(&mut warnings)).unwrap();
+        assert_eq!(bodies[0].faces.len(), 38);
+        assert_eq!(warnings[0].id, Some(90000));
+        assert_eq!(warnings[1].message, "stray semicolon");
+        assert!(warnings.iter().any(|w| w.id == Some(15) && w.message == "lowercase boolean"));
+        assert!(warnings.contains(&Warning {
+            span: None,
+            id: Some(1307),
+            message: "integer as real in argument 1 of CARTESIAN_POINT".to_owned(),
+        }));
+        assert_eq!(warnings.last().unwrap(), &Warning {
+            span: None,
+            id: Some(90001),
+            message: "argument 1 refers to missing #90000".to_owned(),
+        });
+        // needed entities are not skipped
+        let buf = buf.replace("#73=PLANE('',#1027);", "#73=PLANE('',#1027;");
+        assert!(parse(&buf, &|_| Err(String::new()), Some(&mut Vec::new())).is_err());
     }
 }
@@ -1,7 +1,11 @@
+use super::escape;
+#[cfg(test)]
 use pest::Parser;
+#[cfg(test)]
 use pest::iterators::{Pair, Pairs};
 use std::vec::Vec;
 
+#[cfg(test)]
 #[derive(Parser)]
 #[grammar = "step.pest"]
 struct StepParser;
@@ -29,6 +33,7 @@ pub enum PreprocessErrorInfo {
 #[derive(Debug, PartialEq)]
 pub enum PreprocessError {
     Fail(PreprocessErrorInfo),
+    InvalidString(PreprocessErrorInfo, escape::EscapeError),
     InternalError,
 }
 
@@ -44,6 +49,7 @@ impl From<std::num::ParseIntError> for PreprocessError {
     }
 }
 
+#[cfg(test)]
 impl From<pest::error::Error<Rule>> for PreprocessError {
     fn from (e: pest::error::Error<Rule>) -> Self {
         match e.line_col {
@@ -54,6 +60,15 @@ impl From<pest::error::Error<Rule>> for PreprocessError {
 }
 
 impl Value {
+    pub fn map_ids(&mut self, f: &dyn Fn(u64) -> u64) {
+        match self {
+            Value::Id(id) => *id = f(*id),
+            Value::Tuple(vs) => vs.iter_mut().for_each(|v| v.map_ids(f)),
+            Value::Desc(_, vs) => vs.iter_mut().for_each(|v| v.map_ids(f)),
+            _ => (),
+        }
+    }
+
     pub fn str(&self) -> Option<&String> {
         match self {
             Value::String(s) => Some(s),
@@ -88,6 +103,13 @@ impl Value {
             _ => None,
         }
     }
+
+    pub fn int(&self) -> Option<&i64> {
+        match self {
+            Value::Int(i) => Some(i),
+            _ => None,
+        }
+    }
 }
 
 pub type Header = (String, Vec<Value>);
@@ -98,12 +120,74 @@ pub enum Data {
     Aggregate(u64, Vec<(String, Vec<Value>)>),
 }
 
+impl Data {
+    pub fn id(&self) -> u64 {
+        match self {
+            Data::Single(id, _, _) => *id,
+            Data::Aggregate(id, _) => *id,
+        }
+    }
+
+    // changes the id of the instance and its references
+    pub fn map_ids(&mut self, f: &dyn Fn(u64) -> u64) {
+        match self {
+            Data::Single(id, _, args) => {
+                *id = f(*id);
+                args.iter_mut().for_each(|v| v.map_ids(f));
+            },
+            Data::Aggregate(id, partials) => {
+                *id = f(*id);
+                partials
+                    .iter_mut()
+                    .flat_map(|(_, args)| args.iter_mut())
+                    .for_each(|v| v.map_ids(f));
+            },
+        }
+    }
+}
+
+// a DATA section, params are the name and the schema in edition 3 files
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataSection {
+    pub params: Vec<Value>,
+    pub ids: Vec<u64>,
+}
+
+// target of an anchor, an entity (or a value) in the file or another resource
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnchorItem {
+    Value(Value),
+    Resource(String),
+}
+
+// resources are written without the angle brackets,
+// data of all sections are in data
 #[derive(Debug)]
 pub struct Step {
     pub header: Vec<Header>,
+    pub anchors: Vec<(String, AnchorItem)>,
+    // instances defined in other files, e.g. #100=<part.stp#body>
+    pub references: Vec<(u64, String)>,
+    pub sections: Vec<DataSection>,
     pub data: Vec<Data>,
+    pub signatures: Vec<String>,
 }
 
+impl Step {
+    pub fn new(header: Vec<Header>, data: Vec<Data>) -> Self {
+        let ids = data.iter().map(Data::id).collect();
+        Step {
+            header,
+            anchors: Vec::new(),
+            references: Vec::new(),
+            sections: vec![DataSection { params: Vec::new(), ids }],
+            data,
+            signatures: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
 fn value(v: Pair<Rule>) -> Result<Value, PreprocessError> {
     match v.as_rule() {
         Rule::value => {
@@ -119,7 +203,11 @@ fn value(v: Pair<Rule>) -> Result<Value, PreprocessError> {
                 },
                 Rule::string => {
                     let s = v.as_str();
-                    Ok(Value::String(s.get(1..s.len()-1).ok_or(PreprocessError::InternalError)?.to_string()))
+                    let pos = v.peek().ok_or(PreprocessError::InternalError)?.as_span().start_pos().line_col();
+                    let inner = s.get(1..s.len()-1).ok_or(PreprocessError::InternalError)?;
+                    escape::decode(inner)
+                        .map(Value::String)
+                        .map_err(|e| PreprocessError::InvalidString(PreprocessErrorInfo::LineCol(pos), e))
                 },
                 Rule::id => {
                     let id: Result<u64, PreprocessError> = v
@@ -161,6 +249,7 @@ fn value(v: Pair<Rule>) -> Result<Value, PreprocessError> {
     }
 }
 
+#[cfg(test)]
 fn desc(d: Pair<Rule>) -> Result<(String, Vec<Value>), PreprocessError> {
     match d.as_rule() {
         Rule::desc => {
@@ -181,6 +270,7 @@ fn desc(d: Pair<Rule>) -> Result<(String, Vec<Value>), PreprocessError> {
     }
 }
 
+#[cfg(test)]
 fn header(h: Pair<Rule>) -> Result<Vec<Header>, PreprocessError> {
     match h.as_rule() {
         Rule::header => {
@@ -191,11 +281,22 @@ fn header(h: Pair<Rule>) -> Result<Vec<Header>, PreprocessError> {
     }
 }
 
+#[cfg(test)]
+fn data_params(d: &Pair<Rule>) -> Result<Vec<Value>, PreprocessError> {
+    match d.clone().into_inner().next() {
+        Some(p) if p.as_rule() == Rule::data_params => p.into_inner().map(value).collect(),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
 fn data(d: Pair<Rule>) -> Result<Vec<Data>, PreprocessError> {
     match d.as_rule() {
         Rule::data => Ok(d
             .into_inner()
+            .filter(|d| d.as_rule() != Rule::data_params)
             .map(|d| {
+                let rule = d.as_rule();
                 let mut inner = d.into_inner();
                 let id : Result<u64, PreprocessError> = inner
                     .next()
@@ -206,16 +307,12 @@ fn data(d: Pair<Rule>) -> Result<Vec<Data>, PreprocessError> {
                     .parse::<u64>()
                     .map_err(From::from);
                 let id = id?;
-                let right = inner
-                    .next()
-                    .ok_or(PreprocessError::InternalError)?;
-                match right.as_rule() {
-                    Rule::desc =>
-                        desc(right)
+                match rule {
+                    Rule::elem =>
+                        desc(inner.next().ok_or(PreprocessError::InternalError)?)
                         .map(|(name, args)| Data::Single(id, name, args)),
                     Rule::aggregate =>
-                        right
-                        .into_inner()
+                        inner
                         .map(desc)
                         .collect::<Result<Vec<(String, Vec<Value>)>, PreprocessError>>()
                         .map(|aggregated| Data::Aggregate(id, aggregated)),
@@ -227,21 +324,83 @@ fn data(d: Pair<Rule>) -> Result<Vec<Data>, PreprocessError> {
     }
 }
 
+// text between angle brackets
+#[cfg(test)]
+fn resource(r: Pair<Rule>) -> Result<String, PreprocessError> {
+    let s = r.as_str();
+    s.get(1..s.len()-1).map(|s| s.to_string()).ok_or(PreprocessError::InternalError)
+}
+
+#[cfg(test)]
+fn anchors(a: Pair<Rule>) -> Result<Vec<(String, AnchorItem)>, PreprocessError> {
+    a.into_inner()
+        .map(|anchor| {
+            let mut inner = anchor.into_inner();
+            let name = resource(inner.next().ok_or(PreprocessError::InternalError)?)?;
+            let item = inner.next().ok_or(PreprocessError::InternalError)?;
+            let item = match item.as_rule() {
+                Rule::resource => AnchorItem::Resource(resource(item)?),
+                _ => AnchorItem::Value(value(item)?),
+            };
+            Ok((name, item))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn references(r: Pair<Rule>) -> Result<Vec<(u64, String)>, PreprocessError> {
+    r.into_inner()
+        .map(|reference| {
+            let mut inner = reference.into_inner();
+            let id = inner
+                .next()
+                .ok_or(PreprocessError::InternalError)?
+                .as_str()
+                .get(1..)
+                .ok_or(PreprocessError::InternalError)?
+                .parse::<u64>()?;
+            Ok((id, resource(inner.next().ok_or(PreprocessError::InternalError)?)?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
 fn step(s: Pair<Rule>) -> Result<Step, PreprocessError> {
     match s.as_rule() {
         Rule::step => {
             let mut inner = s.into_inner();
             let header = header(inner.next().ok_or(PreprocessError::InternalError)?)?;
-            let data = data(inner.next().ok_or(PreprocessError::InternalError)?)?;
-            Ok(Step {
-                header,
-                data,
-            })
+            let mut step = Step::new(header, Vec::new());
+            step.sections.clear();
+            for section in inner {
+                match section.as_rule() {
+                    Rule::anchor_section => step.anchors = anchors(section)?,
+                    Rule::reference_section => step.references = references(section)?,
+                    Rule::data => {
+                        let params = data_params(&section)?;
+                        let mut data = data(section)?;
+                        step.sections.push(DataSection {
+                            params,
+                            ids: data.iter().map(Data::id).collect(),
+                        });
+                        step.data.append(&mut data);
+                    },
+                    Rule::signature => {
+                        let content = section.into_inner().next().ok_or(PreprocessError::InternalError)?;
+                        step.signatures.push(content.as_str().trim().to_string());
+                    },
+                    Rule::EOI => (),
+                    _ => return Err(PreprocessError::InternalError),
+                }
+            }
+            Ok(step)
         },
         _ => Err(PreprocessError::InternalError)
     }
 }
 
+// the stream reader is checked against this grammar
+#[cfg(test)]
 pub fn parse(input: &str) -> Result<Step, PreprocessError> {
     let parsed: Result<Pairs<Rule>, PreprocessError> = StepParser::parse(Rule::step, input)
         .map_err(From::from);
@@ -262,6 +421,12 @@ mod test {
             Ok(Value::Tuple(vec![Value::Float(1.0), Value::String(String::new()), Value::Id(12)])));
         assert_eq!(StepParser::parse(Rule::value, "('')").map(|v| value(v.peek().unwrap()).unwrap()), 
             Ok(Value::Tuple(vec![Value::String(String::new())])));
+        assert_eq!(StepParser::parse(Rule::value, r"'\X2\30BD30EA30C330C9\X0\1'").map(|v| value(v.peek().unwrap()).unwrap()),
+            Ok(Value::String("ソリッド1".to_string())));
+        assert_eq!(StepParser::parse(Rule::value, r"('it''s', 'C:\\')").map(|v| value(v.peek().unwrap()).unwrap()),
+            Ok(Value::Tuple(vec![Value::String("it's".to_string()), Value::String(r"C:\".to_string())])));
+        assert_eq!(StepParser::parse(Rule::value, r"'\X2\30B\X0\'").map(|v| value(v.peek().unwrap())),
+            Ok(Err(PreprocessError::InvalidString(PreprocessErrorInfo::LineCol((1, 1)), escape::EscapeError::InvalidHex(0)))));
         assert_eq!(StepParser::parse(Rule::data,
             r"DATA;
             #10=MECHANICAL_DESIGN_GEOMETRIC_PRESENTATION_REPRESENTATION('',(#13),#501);
@@ -278,6 +443,69 @@ mod test {
                     Value::Id(501),
                 ])
             ]));
+        assert_eq!(StepParser::parse(Rule::data,
+            r"DATA;
+            #1558=(
+            LENGTH_UNIT()
+            NAMED_UNIT(*)
+            SI_UNIT(.MILLI.,.METRE.)
+            );
+            ENDSEC;"
+            ).map(|v| data(v.peek().unwrap()).unwrap()),
+            Ok(vec![
+                Data::Aggregate(1558, vec![
+                    ("LENGTH_UNIT".to_string(), vec![]),
+                    ("NAMED_UNIT".to_string(), vec![Value::Xplicit]),
+                    ("SI_UNIT".to_string(), vec![Value::Enum("MILLI".to_string()), Value::Enum("METRE".to_string())]),
+                ])
+            ]));
+    }
+
+    #[test]
+    fn test_edition_3() {
+        let step = parse(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            ANCHOR;
+            <body>=#2;
+            <origin>=<other.stp#origin>;
+            ENDSEC;
+            REFERENCE;
+            #100=<part.stp#body>;
+            ENDSEC;
+            DATA('geometry',('AP242_MANAGED_MODEL_BASED_3D_ENGINEERING_MIM_LF'));
+            #1=CARTESIAN_POINT('',(0.,0.,0.));
+            #2=CLOSED_SHELL('',(#100));
+            ENDSEC;
+            DATA;
+            #3=CARTESIAN_POINT('',(-1,0,+1.E+2));
+            ENDSEC;
+            SIGNATURE;
+            MIIB
+            ENDSEC;
+            END-ISO-10303-21;").unwrap();
+        assert_eq!(step.anchors, vec![
+            ("body".to_string(), AnchorItem::Value(Value::Id(2))),
+            ("origin".to_string(), AnchorItem::Resource("other.stp#origin".to_string())),
+        ]);
+        assert_eq!(step.references, vec![(100, "part.stp#body".to_string())]);
+        assert_eq!(step.sections, vec![
+            DataSection {
+                params: vec![
+                    Value::String("geometry".to_string()),
+                    Value::Tuple(vec![Value::String("AP242_MANAGED_MODEL_BASED_3D_ENGINEERING_MIM_LF".to_string())]),
+                ],
+                ids: vec![1, 2],
+            },
+            DataSection { params: vec![], ids: vec![3] },
+        ]);
+        assert_eq!(step.data.len(), 3);
+        assert_eq!(step.data[2], Data::Single(3, "CARTESIAN_POINT".to_string(), vec![
+            Value::String(String::new()),
+            Value::Tuple(vec![Value::Int(-1), Value::Int(0), Value::Float(100.0)]),
+        ]));
+        assert_eq!(step.signatures, vec!["MIIB".to_string()]);
     }
 }
 
@@ -0,0 +1,287 @@
+use super::preprocess::{AnchorItem, Data, PreprocessError, Step, Value};
+use super::stream;
+use std::collections::HashMap;
+
+// External references of edition 3 files.
+// An instance in the REFERENCE section, e.g. #100=<part.stp#body>, is replaced with
+// the entity anchored as body in part.stp. The entities of a referenced file are
+// merged into the step with new ids, each file once.
+// Files are named relative to the directory of the file referring to them.
+
+#[derive(Debug, PartialEq)]
+pub enum ReferenceError {
+    // the file cannot be read, or is not local, with the reason
+    NotFound(u64, String, String),
+    Preprocess(String, PreprocessError),
+    // the anchor does not exist or is not an entity
+    MissingAnchor(u64, String),
+    // a file referring to itself through others
+    Cyclic(String),
+}
+
+// file and anchor of <file#anchor>, the file is empty in a reference to the same file
+fn split(uri: &str) -> (&str, &str) {
+    match uri.find('#') {
+        Some(i) => (&uri[..i], &uri[i + 1..]),
+        None => (uri, ""),
+    }
+}
+
+fn anchored(step: &Step, anchor: &str) -> Option<u64> {
+    step.anchors.iter().find_map(|(name, item)| match item {
+        AnchorItem::Value(Value::Id(id)) if name == anchor => Some(*id),
+        _ => None,
+    })
+}
+
+fn max_id(step: &Step) -> u64 {
+    step.data
+        .iter()
+        .map(Data::id)
+        .chain(step.references.iter().map(|(id, _)| *id))
+        .max()
+        .unwrap_or(0)
+}
+
+// name of a file referred from the file from, both relative to the directory of the first file
+fn joined(from: &str, file: &str) -> String {
+    let file = file.strip_prefix("file://").unwrap_or(file);
+    if file.starts_with('/') || file.contains("://") {
+        return file.to_owned();
+    }
+    let mut parts = from.split('/').collect::<Vec<&str>>();
+    // the directory of from
+    parts.pop();
+    for part in file.split('/') {
+        match part {
+            "" | "." => (),
+            ".." if parts.last().map(|p| *p != "..").unwrap_or(false) => {
+                parts.pop();
+            },
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+// files merged into the first one, each once
+struct Merge<'a> {
+    load: &'a dyn Fn(&str) -> Result<String, String>,
+    next: u64,
+    // anchors of merged files with their new ids
+    merged: HashMap<String, Vec<(String, AnchorItem)>>,
+    // files being merged, which refer to each other when one of them is referred again
+    path: Vec<String>,
+    data: Vec<Data>,
+}
+
+impl<'a> Merge<'a> {
+    // replaces the references of step, the file of the name, with the anchored entities
+    fn resolve(&mut self, step: &mut Step, name: &str) -> Result<(), ReferenceError> {
+        let mut targets = HashMap::new();
+        for (id, uri) in step.references.clone() {
+            let (file, anchor) = split(&uri);
+            let target = if file.is_empty() {
+                anchored(step, anchor)
+            }
+            else {
+                let file = joined(name, file);
+                if !self.merged.contains_key(&file) {
+                    self.merge(&file, id, &uri)?;
+                }
+                self.merged[&file].iter().find_map(|(name, item)| match item {
+                    AnchorItem::Value(Value::Id(id)) if name == anchor => Some(*id),
+                    _ => None,
+                })
+            };
+            targets.insert(id, target.ok_or_else(|| ReferenceError::MissingAnchor(id, uri.clone()))?);
+        }
+        let f = |id| targets.get(&id).copied().unwrap_or(id);
+        step.data.iter_mut().for_each(|d| d.map_ids(&f));
+        for (_, item) in step.anchors.iter_mut() {
+            if let AnchorItem::Value(v) = item {
+                v.map_ids(&f);
+            }
+        }
+        step.references.clear();
+        Ok(())
+    }
+
+    // id refers to the file by uri
+    fn merge(&mut self, file: &str, id: u64, uri: &str) -> Result<(), ReferenceError> {
+        if self.path.iter().any(|f| f == file) {
+            return Err(ReferenceError::Cyclic(file.to_owned()));
+        }
+        let text = (self.load)(file).map_err(|reason| ReferenceError::NotFound(id, uri.to_owned(), reason))?;
+        let mut other = stream::parse(&text).map_err(|e| ReferenceError::Preprocess(file.to_owned(), e))?;
+        let offset = self.next;
+        let shift = |id| id + offset;
+        for d in other.data.iter_mut() {
+            d.map_ids(&shift);
+        }
+        for (_, item) in other.anchors.iter_mut() {
+            if let AnchorItem::Value(v) = item {
+                v.map_ids(&shift);
+            }
+        }
+        for (id, _) in other.references.iter_mut() {
+            *id += offset;
+        }
+        self.next = self.next.max(max_id(&other) + 1);
+        self.path.push(file.to_owned());
+        self.resolve(&mut other, file)?;
+        self.path.pop();
+        self.data.append(&mut other.data);
+        self.merged.insert(file.to_owned(), other.anchors);
+        Ok(())
+    }
+}
+
+// files which the file of the name refers to, read from the sections before the data
+pub fn referred_files(text: &str, name: &str) -> Vec<String> {
+    let mut files = Vec::new();
+    for record in stream::Records::new(text) {
+        let uri = match record {
+            Ok(stream::Record::Reference(_, uri)) | Ok(stream::Record::Anchor(_, stream::RawAnchorItem::Resource(uri))) => uri,
+            Ok(stream::Record::Header(_, _)) | Ok(stream::Record::Anchor(_, _)) => continue,
+            _ => break,
+        };
+        match split(uri) {
+            ("", _) => (),
+            (file, _) => files.push(joined(name, file)),
+        }
+    }
+    files
+}
+
+// load returns the content of a file by its name relative to the directory of the step or why it cannot
+pub fn resolve(step: &mut Step, load: &dyn Fn(&str) -> Result<String, String>) -> Result<(), ReferenceError> {
+    let mut merge = Merge { load, next: max_id(step) + 1, merged: HashMap::new(), path: Vec::new(), data: Vec::new() };
+    merge.resolve(step, "")?;
+    if let Some(section) = step.sections.first_mut() {
+        section.ids.extend(merge.data.iter().map(Data::id));
+    }
+    step.data.append(&mut merge.data);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PART: &str = r"ISO-10303-21;
+        HEADER;
+        FILE_DESCRIPTION((''),'2;1');
+        ENDSEC;
+        ANCHOR;
+        <origin>=#2;
+        ENDSEC;
+        DATA;
+        #1=CARTESIAN_POINT('',(1.,2.,3.));
+        #2=VERTEX_POINT('',#1);
+        ENDSEC;
+        END-ISO-10303-21;";
+
+    fn assembly(references: &str) -> Step {
+        stream::parse(&format!(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            ANCHOR;
+            <loop>=#3;
+            ENDSEC;
+            REFERENCE;
+            {}
+            ENDSEC;
+            DATA;
+            #3=VERTEX_LOOP('',#10);
+            #4=FACE_OUTER_BOUND('',#11,.T.);
+            ENDSEC;
+            END-ISO-10303-21;", references)).unwrap()
+    }
+
+    fn load(name: &str) -> Result<String, String> {
+        match name {
+            "part.stp" => Ok(PART.to_owned()),
+            "self.stp" => Ok(r"ISO-10303-21;
+                HEADER;
+                FILE_DESCRIPTION((''),'2;1');
+                ENDSEC;
+                ANCHOR;
+                <a>=#1;
+                ENDSEC;
+                REFERENCE;
+                #2=<self.stp#a>;
+                ENDSEC;
+                DATA;
+                #1=VERTEX_LOOP('',#2);
+                ENDSEC;
+                END-ISO-10303-21;".to_owned()),
+            _ => Err("no such file".to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut step = assembly("#10=<part.stp#origin>; #11=<#loop>;");
+        resolve(&mut step, &load).unwrap();
+        assert!(step.references.is_empty());
+        assert_eq!(step.data, vec![
+            Data::Single(3, "VERTEX_LOOP".to_owned(), vec![Value::String(String::new()), Value::Id(14)]),
+            Data::Single(4, "FACE_OUTER_BOUND".to_owned(), vec![Value::String(String::new()), Value::Id(3), Value::Bool(true)]),
+            Data::Single(13, "CARTESIAN_POINT".to_owned(), vec![
+                Value::String(String::new()),
+                Value::Tuple(vec![Value::Float(1.0), Value::Float(2.0), Value::Float(3.0)]),
+            ]),
+            Data::Single(14, "VERTEX_POINT".to_owned(), vec![Value::String(String::new()), Value::Id(13)]),
+        ]);
+        assert_eq!(step.sections[0].ids, vec![3, 4, 13, 14]);
+
+        let mut step = assembly("#10=<other.stp#origin>;");
+        assert_eq!(resolve(&mut step, &load), Err(ReferenceError::NotFound(10, "other.stp#origin".to_owned(), "no such file".to_owned())));
+        let mut step = assembly("#10=<part.stp#body>;");
+        assert_eq!(resolve(&mut step, &load), Err(ReferenceError::MissingAnchor(10, "part.stp#body".to_owned())));
+        let mut step = assembly("#10=<self.stp#a>;");
+        assert_eq!(resolve(&mut step, &load), Err(ReferenceError::Cyclic("self.stp".to_owned())));
+    }
+
+    #[test]
+    fn test_nested() {
+        // a part in the directory of the file referring to it, referred twice through two files
+        let file = |references: &str, anchor: u64| format!(r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            ANCHOR;
+            <p>=#{};
+            ENDSEC;
+            REFERENCE;
+            {}
+            ENDSEC;
+            DATA;
+            #1=VERTEX_LOOP('',#2);
+            ENDSEC;
+            END-ISO-10303-21;", anchor, references);
+        let load = |name: &str| match name {
+            "sub/a.stp" => Ok(file("#2=<b.stp#p>;", 1)),
+            "sub/b.stp" => Ok(file("#2=<../c/d.stp#p>;", 1)),
+            "c/d.stp" => Ok(PART.replace("origin", "p")),
+            _ => Err(name.to_owned()),
+        };
+        let mut step = assembly("#10=<sub/a.stp#p>; #11=<./c/d.stp#p>;");
+        resolve(&mut step, &load).unwrap();
+        let names = step.data.iter().map(|d| match d {
+            Data::Single(id, name, _) => (*id, name.as_str()),
+            d => panic!("{:?}", d),
+        }).collect::<Vec<(u64, &str)>>();
+        // a.stp shifted by 12, b.stp by 15 and d.stp once by 18
+        assert_eq!(names, vec![
+            (3, "VERTEX_LOOP"), (4, "FACE_OUTER_BOUND"),
+            (19, "CARTESIAN_POINT"), (20, "VERTEX_POINT"), (16, "VERTEX_LOOP"), (13, "VERTEX_LOOP"),
+        ]);
+        let vertex_loop = |id, to| Data::Single(id, "VERTEX_LOOP".to_owned(), vec![Value::String(String::new()), Value::Id(to)]);
+        assert_eq!((&step.data[0], &step.data[4], &step.data[5]), (&vertex_loop(3, 13), &vertex_loop(16, 20), &vertex_loop(13, 16)));
+        assert_eq!(step.data[1], Data::Single(4, "FACE_OUTER_BOUND".to_owned(), vec![Value::String(String::new()), Value::Id(20), Value::Bool(true)]));
+        assert_eq!(joined("sub/a.stp", "../../x.stp"), "../x.stp");
+    }
+}
@@ -0,0 +1,171 @@
+use super::math::V3;
+use std::convert::TryInto;
+
+// STL files, binary or ASCII.
+// Stored normals are not trusted, they are recomputed from the vertices in the analysis.
+
+#[derive(Debug, PartialEq)]
+pub enum StlError {
+    // the binary file is shorter than its triangle count
+    Truncated,
+    // line of the ASCII file
+    Syntax(usize),
+    NonFinite(usize),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Stl {
+    // of the first solid in an ASCII file
+    pub name: String,
+    pub triangles: Vec<[V3; 3]>,
+}
+
+const HEADER_LEN: usize = 80;
+const FACET_LEN: usize = 50;
+
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < HEADER_LEN + 4 {
+        return false;
+    }
+    let n = u32::from_le_bytes(bytes[HEADER_LEN..HEADER_LEN + 4].try_into().unwrap()) as usize;
+    // some exporters start the binary header with "solid" as well
+    bytes.len() == HEADER_LEN + 4 + n * FACET_LEN
+}
+
+fn read_binary(bytes: &[u8]) -> Result<Stl, StlError> {
+    if bytes.len() < HEADER_LEN + 4 {
+        return Err(StlError::Truncated);
+    }
+    let n = u32::from_le_bytes(bytes[HEADER_LEN..HEADER_LEN + 4].try_into().unwrap()) as usize;
+    let body = &bytes[HEADER_LEN + 4..];
+    if body.len() < n * FACET_LEN {
+        return Err(StlError::Truncated);
+    }
+    let f = |b: &[u8], i: usize| f32::from_le_bytes(b[i * 4..i * 4 + 4].try_into().unwrap()) as f64;
+    let triangles = body
+        .chunks(FACET_LEN)
+        .take(n)
+        .map(|facet| {
+            // the normal, 3 vertices and an attribute byte count
+            let v = |k: usize| V3([f(facet, 3 + k * 3), f(facet, 4 + k * 3), f(facet, 5 + k * 3)]);
+            [v(0), v(1), v(2)]
+        })
+        .collect();
+    Ok(Stl { name: String::new(), triangles })
+}
+
+fn read_ascii(text: &str) -> Result<Stl, StlError> {
+    let mut name = None;
+    let mut triangles = Vec::new();
+    let mut vertices = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("solid") => {
+                if name.is_none() {
+                    name = Some(words.collect::<Vec<&str>>().join(" "));
+                }
+            },
+            Some("facet") | Some("outer") | Some("endloop") | Some("endsolid") | None => (),
+            Some("vertex") => {
+                let mut v = V3::default();
+                for k in 0..3 {
+                    let x = words
+                        .next()
+                        .and_then(|w| w.parse::<f64>().ok())
+                        .ok_or(StlError::Syntax(line_no))?;
+                    if !x.is_finite() {
+                        return Err(StlError::NonFinite(line_no));
+                    }
+                    v.0[k] = x;
+                }
+                vertices.push(v);
+            },
+            Some("endfacet") => {
+                if vertices.len() != 3 {
+                    return Err(StlError::Syntax(line_no));
+                }
+                let mut vs = vertices.drain(..);
+                triangles.push([vs.next().unwrap(), vs.next().unwrap(), vs.next().unwrap()]);
+            },
+            Some(_) => return Err(StlError::Syntax(line_no)),
+        }
+    }
+    if !vertices.is_empty() {
+        return Err(StlError::Syntax(text.lines().count()));
+    }
+    Ok(Stl { name: name.unwrap_or_default(), triangles })
+}
+
+pub fn read(bytes: &[u8]) -> Result<Stl, StlError> {
+    if is_binary(bytes) {
+        return read_binary(bytes);
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) if text.trim_start().starts_with("solid") => read_ascii(text),
+        _ => read_binary(bytes),
+    }
+}
+
+// binary STL, the name in the header
+pub fn write(name: &str, triangles: &[[V3; 3]]) -> Vec<u8> {
+    let mut bytes = name.as_bytes().iter().cloned().take(HEADER_LEN).collect::<Vec<u8>>();
+    bytes.resize(HEADER_LEN, b' ');
+    bytes.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+    for [a, b, c] in triangles {
+        let n = b.sub(a).cross(&c.sub(a));
+        let n = if n.norm() > 0.0 { n.normalize() } else { n };
+        for v in &[&n, a, b, c] {
+            for x in &v.0 {
+                bytes.extend_from_slice(&(*x as f32).to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&[0, 0]);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ASCII: &str = "solid cube corner
+  facet normal 0 0 -1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1.5e+0 0
+    endloop
+  endfacet
+endsolid cube corner
+";
+
+    #[test]
+    fn test_read() {
+        let stl = read(ASCII.as_bytes()).unwrap();
+        assert_eq!(stl.name, "cube corner");
+        assert_eq!(stl.triangles, vec![[V3([0., 0., 0.]), V3([1., 0., 0.]), V3([0., 1.5, 0.])]]);
+        assert_eq!(read(ASCII.replace("vertex 0 0 0", "vertex 0 0").as_bytes()), Err(StlError::Syntax(4)));
+        assert_eq!(read(ASCII.replace("vertex 1 0 0", "vertex inf 0 0").as_bytes()), Err(StlError::NonFinite(5)));
+        assert_eq!(read(ASCII.replace("endloop", "vertex 0 0 1\nendloop").as_bytes()), Err(StlError::Syntax(9)));
+
+        let mut binary = b"solid but binary".to_vec();
+        binary.resize(HEADER_LEN, 0);
+        binary.extend_from_slice(&1u32.to_le_bytes());
+        for x in &[0f32, 0., -1., 0., 0., 0., 1., 0., 0., 0., 1.5, 0.] {
+            binary.extend_from_slice(&x.to_le_bytes());
+        }
+        binary.extend_from_slice(&[0, 0]);
+        let stl = read(&binary).unwrap();
+        assert_eq!(stl.triangles, vec![[V3([0., 0., 0.]), V3([1., 0., 0.]), V3([0., 1.5, 0.])]]);
+        binary.pop();
+        assert_eq!(read(&binary), Err(StlError::Truncated));
+
+        let example = read(&std::fs::read("example.stl").unwrap()).unwrap();
+        assert_eq!(example.triangles.len(), 1152);
+        assert_eq!(read(&write("", &example.triangles)).unwrap(), example);
+        // not taken as ASCII
+        assert_eq!(read(&write("solid", &example.triangles)).unwrap(), example);
+    }
+}
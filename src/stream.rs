@@ -0,0 +1,906 @@
+use super::escape;
+use super::preprocess::{AnchorItem, Data, DataSection, PreprocessError, PreprocessErrorInfo, Step, Value};
+use super::error::{Span, Warning};
+
+// Hand-written Part 21 reader.
+// Records are read one by one from the input without building a parse tree,
+// strings and names borrow the input, and strings are decoded only on demand.
+// parse still collects the records into an owned Step, which the parser needs whole
+// to resolve references, so it saves the parse tree of pest but not the Step itself.
+// It accepts the same syntax as the pest grammar in step.pest.
+// In the tolerant mode, malformed entity instances in DATA sections are skipped
+// and lowercase booleans are read, both with warnings.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawValue<'a> {
+    Float(f64),
+    Int(i64),
+    // without quotes and still encoded
+    String(&'a str),
+    Id(u64),
+    Bool(bool),
+    Enum(&'a str),
+    Tuple(Vec<RawValue<'a>>),
+    Xplicit,
+    Undefined,
+    Desc(&'a str, Vec<RawValue<'a>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawData<'a> {
+    Single(u64, &'a str, Vec<RawValue<'a>>),
+    Aggregate(u64, Vec<(&'a str, Vec<RawValue<'a>>)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawAnchorItem<'a> {
+    Value(RawValue<'a>),
+    Resource(&'a str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record<'a> {
+    Header(&'a str, Vec<RawValue<'a>>),
+    Anchor(&'a str, RawAnchorItem<'a>),
+    Reference(u64, &'a str),
+    // start of a DATA section
+    Section(Vec<RawValue<'a>>),
+    Data(RawData<'a>),
+    Signature(&'a str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Keyword(&'a str),
+    Float(f64),
+    Int(i64),
+    String(&'a str),
+    Id(u64),
+    Enum(&'a str),
+    Resource(&'a str),
+    Open,
+    Close,
+    Comma,
+    Semicolon,
+    Equal,
+    Star,
+    Dollar,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Start,
+    Header,
+    Sections,
+    Anchor,
+    Reference,
+    Data,
+    End,
+}
+
+// position in the input where the syntax is broken
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Fail(usize);
+
+pub struct Records<'a> {
+    input: &'a str,
+    pos: usize,
+    state: State,
+    // sections come in the order ANCHOR, REFERENCE, DATA and SIGNATURE
+    rank: u8,
+    // token read ahead and its position
+    peeked: Option<(usize, Token<'a>)>,
+    tolerant: bool,
+    warnings: Vec<Warning>,
+    // start and id of the entity instance being read
+    entity_start: usize,
+    entity: Option<u64>,
+    // positions of the strings of the last record
+    strings: Vec<usize>,
+    // the last position warned about with its line and column, counted on from there
+    cursor: (usize, (usize, usize)),
+}
+
+fn is_symbol_start(b: u8) -> bool {
+    b.is_ascii_alphabetic()
+}
+
+fn is_symbol(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'-'
+}
+
+fn line_col(input: &str, pos: usize) -> (usize, usize) {
+    let before = &input.as_bytes()[..pos.min(input.len())];
+    let line = before.iter().filter(|b| **b == b'\n').count() + 1;
+    let line_start = before.iter().rposition(|b| *b == b'\n').map(|i| i + 1).unwrap_or(0);
+    (line, input[line_start..pos.min(input.len())].chars().count() + 1)
+}
+
+impl<'a> Records<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Records {
+            input,
+            pos: 0,
+            state: State::Start,
+            rank: 0,
+            peeked: None,
+            tolerant: false,
+            warnings: Vec::new(),
+            entity_start: 0,
+            entity: None,
+            strings: Vec::new(),
+            cursor: (0, (1, 1)),
+        }
+    }
+
+    pub fn tolerant(input: &'a str) -> Self {
+        Records { tolerant: true, ..Records::new(input) }
+    }
+
+    // problems skipped so far in the tolerant mode
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    // as line_col without rescanning the input for each of many warnings in order
+    fn count_line_col(&mut self, pos: usize) -> (usize, usize) {
+        let pos = pos.min(self.input.len());
+        let (from, (mut line, mut col)) = if self.cursor.0 <= pos { self.cursor } else { (0, (1, 1)) };
+        for c in self.input[from..pos].chars() {
+            if c == '\n' {
+                line += 1;
+                col = 1;
+            }
+            else {
+                col += 1;
+            }
+        }
+        self.cursor = (pos, (line, col));
+        (line, col)
+    }
+
+    fn warn(&mut self, pos: usize, message: &str) {
+        let p = self.count_line_col(pos);
+        self.warnings.push(Warning {
+            span: Some(Span { start: p, end: p }),
+            id: self.entity,
+            message: message.to_owned(),
+        });
+    }
+
+    fn error(&self, Fail(pos): Fail) -> PreprocessError {
+        PreprocessError::Fail(PreprocessErrorInfo::LineCol(line_col(self.input, pos)))
+    }
+
+    fn skip_ignored(&mut self) -> Result<(), Fail> {
+        let bytes = self.input.as_bytes();
+        loop {
+            match bytes.get(self.pos) {
+                Some(b' ') | Some(b'\r') | Some(b'\t') | Some(b'\n') => self.pos += 1,
+                Some(b'/') if bytes.get(self.pos + 1) == Some(&b'*') => {
+                    let start = self.pos;
+                    match self.input[self.pos + 2..].find("*/") {
+                        Some(end) => self.pos += end + 4,
+                        None => return Err(Fail(start)),
+                    }
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn digits(&mut self) -> usize {
+        let bytes = self.input.as_bytes();
+        let start = self.pos;
+        while bytes.get(self.pos).map(|b| b.is_ascii_digit()).unwrap_or(false) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn number(&mut self, start: usize) -> Result<Token<'a>, Fail> {
+        let bytes = self.input.as_bytes();
+        if bytes[self.pos] == b'+' || bytes[self.pos] == b'-' {
+            self.pos += 1;
+        }
+        if self.digits() == 0 {
+            return Err(Fail(start));
+        }
+        if bytes.get(self.pos) != Some(&b'.') {
+            return self.input[start..self.pos].parse().map(Token::Int).map_err(|_| Fail(start));
+        }
+        self.pos += 1;
+        self.digits();
+        if bytes.get(self.pos) == Some(&b'E') {
+            let exp = self.pos;
+            self.pos += 1;
+            if bytes.get(self.pos) == Some(&b'+') || bytes.get(self.pos) == Some(&b'-') {
+                self.pos += 1;
+            }
+            // not an exponent, as the grammar leaves E to the next token
+            if self.digits() == 0 {
+                self.pos = exp;
+            }
+        }
+        self.input[start..self.pos].parse().map(Token::Float).map_err(|_| Fail(start))
+    }
+
+    fn token(&mut self) -> Result<(usize, Token<'a>), Fail> {
+        if let Some(peeked) = self.peeked.take() {
+            return Ok(peeked);
+        }
+        self.skip_ignored()?;
+        let bytes = self.input.as_bytes();
+        let start = self.pos;
+        let b = *bytes.get(start).ok_or(Fail(start))?;
+        let token = match b {
+            b'(' => { self.pos += 1; Token::Open },
+            b')' => { self.pos += 1; Token::Close },
+            b',' => { self.pos += 1; Token::Comma },
+            b';' => { self.pos += 1; Token::Semicolon },
+            b'=' => { self.pos += 1; Token::Equal },
+            b'*' => { self.pos += 1; Token::Star },
+            b'$' => { self.pos += 1; Token::Dollar },
+            b'\'' => {
+                self.pos += 1;
+                loop {
+                    match bytes.get(self.pos) {
+                        Some(b'\'') if bytes.get(self.pos + 1) == Some(&b'\'') => self.pos += 2,
+                        Some(b'\'') => break,
+                        Some(b'\\') if bytes.get(self.pos + 1) == Some(&b'\\') => self.pos += 2,
+                        Some(_) => self.pos += 1,
+                        None => return Err(Fail(start)),
+                    }
+                }
+                self.pos += 1;
+                self.strings.push(start);
+                Token::String(&self.input[start + 1..self.pos - 1])
+            },
+            b'#' => {
+                self.pos += 1;
+                if self.digits() == 0 {
+                    return Err(Fail(start));
+                }
+                Token::Id(self.input[start + 1..self.pos].parse().map_err(|_| Fail(start))?)
+            },
+            b'.' => {
+                self.pos += 1;
+                if !bytes.get(self.pos).map(|b| is_symbol_start(*b)).unwrap_or(false) {
+                    return Err(Fail(start));
+                }
+                while bytes.get(self.pos).map(|b| is_symbol(*b)).unwrap_or(false) {
+                    self.pos += 1;
+                }
+                if bytes.get(self.pos) != Some(&b'.') {
+                    return Err(Fail(start));
+                }
+                self.pos += 1;
+                Token::Enum(&self.input[start + 1..self.pos - 1])
+            },
+            b'<' => {
+                match self.input[start..].find('>') {
+                    Some(end) => self.pos += end + 1,
+                    None => return Err(Fail(start)),
+                }
+                Token::Resource(&self.input[start + 1..self.pos - 1])
+            },
+            b'+' | b'-' | b'0'..=b'9' => self.number(start)?,
+            b if is_symbol_start(b) => {
+                while bytes.get(self.pos).map(|b| is_symbol(*b)).unwrap_or(false) {
+                    self.pos += 1;
+                }
+                Token::Keyword(&self.input[start..self.pos])
+            },
+            _ => return Err(Fail(start)),
+        };
+        Ok((start, token))
+    }
+
+    fn peek(&mut self) -> Result<(usize, Token<'a>), Fail> {
+        let t = self.token()?;
+        self.peeked = Some(t);
+        Ok(t)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), Fail> {
+        let (pos, t) = self.token()?;
+        if t == expected {
+            Ok(())
+        }
+        else {
+            Err(Fail(pos))
+        }
+    }
+
+    // comma separated values up to the closing parenthesis
+    fn values(&mut self, allow_empty: bool) -> Result<Vec<RawValue<'a>>, Fail> {
+        let mut values = Vec::new();
+        if let (pos, Token::Close) = self.peek()? {
+            if !allow_empty {
+                return Err(Fail(pos));
+            }
+            self.token()?;
+            return Ok(values);
+        }
+        loop {
+            values.push(self.value()?);
+            match self.token()? {
+                (_, Token::Comma) => (),
+                (_, Token::Close) => return Ok(values),
+                (pos, _) => return Err(Fail(pos)),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<RawValue<'a>, Fail> {
+        let (pos, t) = self.token()?;
+        Ok(match t {
+            Token::Float(f) => RawValue::Float(f),
+            Token::Int(i) => RawValue::Int(i),
+            Token::String(s) => RawValue::String(s),
+            Token::Id(id) => RawValue::Id(id),
+            Token::Enum("T") => RawValue::Bool(true),
+            Token::Enum("F") => RawValue::Bool(false),
+            Token::Enum(b @ "t") | Token::Enum(b @ "f") if self.tolerant => {
+                self.warn(pos, "lowercase boolean");
+                RawValue::Bool(b == "t")
+            },
+            Token::Enum(e) => RawValue::Enum(e),
+            Token::Star => RawValue::Xplicit,
+            Token::Dollar => RawValue::Undefined,
+            Token::Open => RawValue::Tuple(self.values(false)?),
+            Token::Keyword(name) => {
+                self.expect(Token::Open)?;
+                RawValue::Desc(name, self.values(true)?)
+            },
+            _ => return Err(Fail(pos)),
+        })
+    }
+
+    fn desc(&mut self) -> Result<(&'a str, Vec<RawValue<'a>>), Fail> {
+        match self.token()? {
+            (_, Token::Keyword(name)) => {
+                self.expect(Token::Open)?;
+                Ok((name, self.values(true)?))
+            },
+            (pos, _) => Err(Fail(pos)),
+        }
+    }
+
+    fn entity(&mut self, id: u64) -> Result<RawData<'a>, Fail> {
+        self.expect(Token::Equal)?;
+        let data = match self.peek()? {
+            (_, Token::Open) => {
+                self.token()?;
+                let mut partials = vec![self.desc()?];
+                while let (_, Token::Keyword(_)) = self.peek()? {
+                    partials.push(self.desc()?);
+                }
+                self.expect(Token::Close)?;
+                RawData::Aggregate(id, partials)
+            },
+            _ => {
+                let (name, args) = self.desc()?;
+                RawData::Single(id, name, args)
+            },
+        };
+        self.expect(Token::Semicolon)?;
+        Ok(data)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), Fail> {
+        match self.token()? {
+            (_, Token::Keyword(k)) if k == keyword => Ok(()),
+            (pos, _) => Err(Fail(pos)),
+        }
+    }
+
+    fn end_section(&mut self) -> Result<(), Fail> {
+        self.keyword("ENDSEC")?;
+        self.expect(Token::Semicolon)
+    }
+
+    fn record(&mut self) -> Result<Option<Record<'a>>, Fail> {
+        self.strings.clear();
+        loop {
+            match self.state {
+                State::Start => {
+                    self.keyword("ISO-10303-21")?;
+                    self.expect(Token::Semicolon)?;
+                    self.keyword("HEADER")?;
+                    self.expect(Token::Semicolon)?;
+                    // at least one header entity
+                    let (name, args) = self.desc()?;
+                    self.expect(Token::Semicolon)?;
+                    self.state = State::Header;
+                    return Ok(Some(Record::Header(name, args)));
+                },
+                State::Header => match self.peek()? {
+                    (_, Token::Keyword("ENDSEC")) => {
+                        self.end_section()?;
+                        self.state = State::Sections;
+                    },
+                    _ => {
+                        let (name, args) = self.desc()?;
+                        self.expect(Token::Semicolon)?;
+                        return Ok(Some(Record::Header(name, args)));
+                    },
+                },
+                State::Sections => {
+                    let (pos, t) = self.token()?;
+                    let rank = match t {
+                        Token::Keyword("ANCHOR") => 1,
+                        Token::Keyword("REFERENCE") => 2,
+                        Token::Keyword("DATA") => 3,
+                        Token::Keyword("SIGNATURE") => 4,
+                        // at least one DATA section
+                        Token::Keyword("END-ISO-10303-21") => 5,
+                        _ => return Err(Fail(pos)),
+                    };
+                    if rank < self.rank || (rank == self.rank && rank < 3) || (rank == 5 && self.rank < 3) {
+                        return Err(Fail(pos));
+                    }
+                    self.rank = rank.min(4);
+                    match t {
+                        Token::Keyword("ANCHOR") => {
+                            self.expect(Token::Semicolon)?;
+                            self.state = State::Anchor;
+                        },
+                        Token::Keyword("REFERENCE") => {
+                            self.expect(Token::Semicolon)?;
+                            self.state = State::Reference;
+                        },
+                        Token::Keyword("DATA") => {
+                            let params = match self.token()? {
+                                (_, Token::Semicolon) => Vec::new(),
+                                (_, Token::Open) => {
+                                    let params = self.values(false)?;
+                                    self.expect(Token::Semicolon)?;
+                                    params
+                                },
+                                (pos, _) => return Err(Fail(pos)),
+                            };
+                            self.state = State::Data;
+                            return Ok(Some(Record::Section(params)));
+                        },
+                        Token::Keyword("SIGNATURE") => {
+                            self.expect(Token::Semicolon)?;
+                            let end = self.input[self.pos..].find("ENDSEC;").ok_or(Fail(pos))?;
+                            let content = self.input[self.pos..self.pos + end].trim();
+                            self.pos += end + "ENDSEC;".len();
+                            return Ok(Some(Record::Signature(content)));
+                        },
+                        Token::Keyword("END-ISO-10303-21") => {
+                            self.expect(Token::Semicolon)?;
+                            self.skip_ignored()?;
+                            if self.pos != self.input.len() {
+                                return Err(Fail(self.pos));
+                            }
+                            self.state = State::End;
+                        },
+                        _ => return Err(Fail(pos)),
+                    }
+                },
+                State::Anchor => match self.token()? {
+                    (_, Token::Keyword("ENDSEC")) => {
+                        self.expect(Token::Semicolon)?;
+                        self.state = State::Sections;
+                    },
+                    (_, Token::Resource(name)) => {
+                        self.expect(Token::Equal)?;
+                        let item = match self.peek()? {
+                            (_, Token::Resource(r)) => {
+                                self.token()?;
+                                RawAnchorItem::Resource(r)
+                            },
+                            _ => RawAnchorItem::Value(self.value()?),
+                        };
+                        self.expect(Token::Semicolon)?;
+                        return Ok(Some(Record::Anchor(name, item)));
+                    },
+                    (pos, _) => return Err(Fail(pos)),
+                },
+                State::Reference => match self.token()? {
+                    (_, Token::Keyword("ENDSEC")) => {
+                        self.expect(Token::Semicolon)?;
+                        self.state = State::Sections;
+                    },
+                    (_, Token::Id(id)) => {
+                        self.expect(Token::Equal)?;
+                        let uri = match self.token()? {
+                            (_, Token::Resource(r)) => r,
+                            (pos, _) => return Err(Fail(pos)),
+                        };
+                        self.expect(Token::Semicolon)?;
+                        return Ok(Some(Record::Reference(id, uri)));
+                    },
+                    (pos, _) => return Err(Fail(pos)),
+                },
+                State::Data => match self.data() {
+                    Ok(Some(data)) => return Ok(Some(Record::Data(data))),
+                    Ok(None) => (),
+                    Err(fail) if self.tolerant => self.recover(fail)?,
+                    Err(e) => return Err(e),
+                },
+                State::End => return Ok(None),
+            }
+        }
+    }
+
+    // an entity instance, None at the end of the section
+    fn data(&mut self) -> Result<Option<RawData<'a>>, Fail> {
+        self.entity = None;
+        let (pos, t) = self.token()?;
+        self.entity_start = pos;
+        match t {
+            Token::Keyword("ENDSEC") => {
+                self.expect(Token::Semicolon)?;
+                self.state = State::Sections;
+                Ok(None)
+            },
+            Token::Id(id) => {
+                self.entity = Some(id);
+                self.entity(id).map(Some)
+            },
+            Token::Semicolon if self.tolerant => {
+                self.warn(pos, "stray semicolon");
+                Ok(None)
+            },
+            _ => Err(Fail(pos)),
+        }
+    }
+
+    fn is_entity_start(&self, pos: usize) -> bool {
+        let rest = &self.input[pos + 1..];
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        digits > 0 && rest[digits..].trim_start().starts_with('=')
+    }
+
+    // skips the entity instance failed at error_pos to the next one or the end of the section,
+    // nothing is left to read after a failure at the end of a truncated input
+    fn recover(&mut self, fail: Fail) -> Result<(), Fail> {
+        let Fail(error_pos) = fail;
+        let bytes = self.input.as_bytes();
+        if error_pos >= bytes.len() {
+            return Err(fail);
+        }
+        self.warn(error_pos, "malformed entity instance skipped");
+        self.peeked = None;
+        let mut i = error_pos.max(self.entity_start + 1);
+        while i < bytes.len() {
+            match bytes[i] {
+                b';' => {
+                    i += 1;
+                    break;
+                },
+                b'\'' => {
+                    i += 1;
+                    while i < bytes.len() && (bytes[i] != b'\'' || bytes.get(i + 1) == Some(&b'\'')) {
+                        i += if bytes[i] == b'\'' { 2 } else { 1 };
+                    }
+                    i += 1;
+                },
+                b'/' if bytes.get(i + 1) == Some(&b'*') =>
+                    i = self.input[i + 2..].find("*/").map(|end| i + end + 4).unwrap_or(bytes.len()),
+                b'#' if self.is_entity_start(i) => break,
+                b'E' if self.input[i..].starts_with("ENDSEC") && !is_symbol(bytes[i - 1]) => break,
+                _ => i += 1,
+            }
+        }
+        self.pos = i.min(bytes.len());
+        Ok(())
+    }
+
+    // position of the first string of the last record which cannot be decoded
+    fn string_pos(&mut self, record: &Record) -> (usize, usize) {
+        let i = record.strings().iter().position(|s| escape::decode(s).is_err()).unwrap_or(0);
+        self.count_line_col(self.strings.get(i).copied().unwrap_or(self.pos))
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, PreprocessError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(fail) => {
+                // no more records after an error
+                self.state = State::End;
+                Some(Err(self.error(fail)))
+            },
+        }
+    }
+}
+
+impl<'a> RawValue<'a> {
+    // owned value with decoded strings
+    pub fn to_value(&self) -> Result<Value, escape::EscapeError> {
+        Ok(match self {
+            RawValue::Float(f) => Value::Float(*f),
+            RawValue::Int(i) => Value::Int(*i),
+            RawValue::String(s) => Value::String(escape::decode(s)?),
+            RawValue::Id(id) => Value::Id(*id),
+            RawValue::Bool(b) => Value::Bool(*b),
+            RawValue::Enum(e) => Value::Enum(e.to_string()),
+            RawValue::Tuple(vs) => Value::Tuple(values(vs)?),
+            RawValue::Xplicit => Value::Xplicit,
+            RawValue::Undefined => Value::Undefined,
+            RawValue::Desc(name, vs) => Value::Desc(name.to_string(), values(vs)?),
+        })
+    }
+
+    // strings in the order they are written
+    fn strings(&self, strings: &mut Vec<&'a str>) {
+        match self {
+            RawValue::String(s) => strings.push(s),
+            RawValue::Tuple(vs) | RawValue::Desc(_, vs) => vs.iter().for_each(|v| v.strings(strings)),
+            _ => (),
+        }
+    }
+}
+
+fn values(vs: &[RawValue]) -> Result<Vec<Value>, escape::EscapeError> {
+    vs.iter().map(|v| v.to_value()).collect()
+}
+
+impl<'a> Record<'a> {
+    // strings in the order they are written
+    fn strings(&self) -> Vec<&'a str> {
+        let vs = match self {
+            Record::Header(_, args) | Record::Section(args) | Record::Data(RawData::Single(_, _, args)) => args.iter().collect(),
+            Record::Anchor(_, RawAnchorItem::Value(v)) => vec![v],
+            Record::Data(RawData::Aggregate(_, partials)) => partials.iter().flat_map(|(_, args)| args).collect(),
+            _ => Vec::new(),
+        };
+        let mut strings = Vec::new();
+        vs.into_iter().for_each(|v: &RawValue<'a>| v.strings(&mut strings));
+        strings
+    }
+}
+
+impl<'a> RawData<'a> {
+    pub fn to_data(&self) -> Result<Data, escape::EscapeError> {
+        Ok(match self {
+            RawData::Single(id, name, args) => Data::Single(*id, name.to_string(), values(args)?),
+            RawData::Aggregate(id, partials) => Data::Aggregate(
+                *id,
+                partials
+                    .iter()
+                    .map(|(name, args)| Ok((name.to_string(), values(args)?)))
+                    .collect::<Result<Vec<(String, Vec<Value>)>, escape::EscapeError>>()?),
+        })
+    }
+}
+
+fn read(mut records: Records) -> Result<(Step, Vec<Warning>), PreprocessError> {
+    let mut step = Step::new(Vec::new(), Vec::new());
+    step.sections.clear();
+    let decode = |records: &mut Records, record: &Record, vs: &[RawValue]| -> Result<Vec<Value>, PreprocessError> {
+        values(vs).map_err(|e| PreprocessError::InvalidString(PreprocessErrorInfo::LineCol(records.string_pos(record)), e))
+    };
+    while let Some(record) = records.next() {
+        let record = record?;
+        match &record {
+            Record::Header(name, args) => {
+                let args = decode(&mut records, &record, args)?;
+                step.header.push((name.to_string(), args));
+            },
+            Record::Anchor(name, RawAnchorItem::Value(v)) => {
+                let v = decode(&mut records, &record, std::slice::from_ref(v))?.remove(0);
+                step.anchors.push((name.to_string(), AnchorItem::Value(v)));
+            },
+            Record::Anchor(name, RawAnchorItem::Resource(r)) =>
+                step.anchors.push((name.to_string(), AnchorItem::Resource(r.to_string()))),
+            Record::Reference(id, uri) => step.references.push((*id, uri.to_string())),
+            Record::Section(params) => {
+                let params = decode(&mut records, &record, params)?;
+                step.sections.push(DataSection { params, ids: Vec::new() });
+            },
+            Record::Data(d) => {
+                let data = match d {
+                    RawData::Single(id, name, args) => decode(&mut records, &record, args).map(|args| Data::Single(*id, name.to_string(), args)),
+                    RawData::Aggregate(id, partials) => partials
+                        .iter()
+                        .map(|(name, args)| Ok((name.to_string(), decode(&mut records, &record, args)?)))
+                        .collect::<Result<Vec<(String, Vec<Value>)>, PreprocessError>>()
+                        .map(|partials| Data::Aggregate(*id, partials)),
+                };
+                let data = match data {
+                    Ok(data) => data,
+                    Err(PreprocessError::InvalidString(PreprocessErrorInfo::LineCol(p), _)) if records.tolerant => {
+                        records.warnings.push(Warning {
+                            span: Some(Span { start: p, end: p }),
+                            id: records.entity,
+                            message: "entity instance with an invalid string skipped".to_owned(),
+                        });
+                        continue;
+                    },
+                    Err(e) => return Err(e),
+                };
+                if let Some(section) = step.sections.last_mut() {
+                    section.ids.push(data.id());
+                }
+                step.data.push(data);
+            },
+            Record::Signature(content) => step.signatures.push(content.to_string()),
+        }
+    }
+    Ok((step, records.warnings))
+}
+
+// the same Step as preprocess::parse
+pub fn parse(input: &str) -> Result<Step, PreprocessError> {
+    read(Records::new(input)).map(|(step, _)| step)
+}
+
+// skips malformed entity instances with warnings instead of failing
+pub fn parse_tolerant(input: &str) -> Result<(Step, Vec<Warning>), PreprocessError> {
+    read(Records::tolerant(input))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::{preprocess, writer};
+    use std::fs;
+    use std::time::Instant;
+
+    #[test]
+    fn test_records() {
+        let input = r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            DATA;
+            /* comment */
+            #1=CARTESIAN_POINT('it''s',(-1.5E-3,2,.T.,.UNKNOWN.,$,*));
+            #2=(LENGTH_UNIT()NAMED_UNIT(*));
+            ENDSEC;
+            END-ISO-10303-21;";
+        let records = Records::new(input).collect::<Result<Vec<Record>, PreprocessError>>().unwrap();
+        assert_eq!(records, vec![
+            Record::Header("FILE_DESCRIPTION", vec![RawValue::Tuple(vec![RawValue::String("")]), RawValue::String("2;1")]),
+            Record::Section(vec![]),
+            Record::Data(RawData::Single(1, "CARTESIAN_POINT", vec![
+                RawValue::String("it''s"),
+                RawValue::Tuple(vec![
+                    RawValue::Float(-1.5e-3),
+                    RawValue::Int(2),
+                    RawValue::Bool(true),
+                    RawValue::Enum("UNKNOWN"),
+                    RawValue::Undefined,
+                    RawValue::Xplicit,
+                ]),
+            ])),
+            Record::Data(RawData::Aggregate(2, vec![("LENGTH_UNIT", vec![]), ("NAMED_UNIT", vec![RawValue::Xplicit])])),
+        ]);
+        assert_eq!(records[2], Record::Data(RawData::Single(1, "CARTESIAN_POINT", match &records[2] {
+            Record::Data(RawData::Single(_, _, args)) => args.clone(),
+            _ => unreachable!(),
+        })));
+        match &records[2] {
+            Record::Data(d) => assert_eq!(d.to_data().unwrap().id(), 1),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_same_as_pest() {
+        let buf = fs::read_to_string("./example.STEP").unwrap();
+        let expected = preprocess::parse(&buf).unwrap();
+        let step = parse(&buf).unwrap();
+        assert_eq!(step.header, expected.header);
+        assert_eq!(step.sections, expected.sections);
+        assert_eq!(step.data, expected.data);
+        let edition_3 = r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            ANCHOR;
+            <body>=#2;
+            <origin>=<other.stp#origin>;
+            ENDSEC;
+            REFERENCE;
+            #100=<part.stp#body>;
+            ENDSEC;
+            DATA('geometry',('AP242_MANAGED_MODEL_BASED_3D_ENGINEERING_MIM_LF'));
+            #1=CARTESIAN_POINT('',(0.,0.,1.E+2));
+            #2=CLOSED_SHELL('',(#100));
+            ENDSEC;
+            SIGNATURE;
+            MIIB
+            ENDSEC;
+            END-ISO-10303-21;";
+        let expected = preprocess::parse(edition_3).unwrap();
+        let step = parse(edition_3).unwrap();
+        assert_eq!(step.anchors, expected.anchors);
+        assert_eq!(step.references, expected.references);
+        assert_eq!(step.sections, expected.sections);
+        assert_eq!(step.data, expected.data);
+        assert_eq!(step.signatures, expected.signatures);
+    }
+
+    #[test]
+    fn test_errors() {
+        let step = |data: &str| format!("ISO-10303-21;\nHEADER;\nFILE_DESCRIPTION((''),'2;1');\nENDSEC;\nDATA;\n{}\nENDSEC;\nEND-ISO-10303-21;", data);
+        for data in &["#1=A(;", "#1=A((),1.);", "#1=A('x);", "#1=A(x);", "#1=A(1.)", "#=A();", "#1=A(.B);"] {
+            let text = step(data);
+            assert!(parse(&text).is_err(), "{}", data);
+            assert!(preprocess::parse(&text).is_err(), "{}", data);
+        }
+        assert_eq!(parse(&step("#1=A(\n  '\\X2\\30B\\X0\\');")).unwrap_err(),
+            PreprocessError::InvalidString(PreprocessErrorInfo::LineCol((7, 3)), escape::EscapeError::InvalidHex(0)));
+        // in the second partial entity
+        assert_eq!(parse(&step("#1=(A('',(''))\nB('\\X2\\30B\\X0\\'));")).unwrap_err(),
+            PreprocessError::InvalidString(PreprocessErrorInfo::LineCol((7, 3)), escape::EscapeError::InvalidHex(0)));
+        assert_eq!(parse(&step("#1=A(1.)")).unwrap_err(), PreprocessError::Fail(PreprocessErrorInfo::LineCol((7, 1))));
+    }
+
+    #[test]
+    fn test_tolerant() {
+        let input = r"ISO-10303-21;
+            HEADER;
+            FILE_DESCRIPTION((''),'2;1');
+            ENDSEC;
+            DATA;
+            #1=CARTESIAN_POINT('',(0.,0.,0.));
+            ;
+            #2=A(;
+            #3=B(.t.,'a;b');
+            #4=C(1.)
+            #5=D('\X2\30B\X0\');
+            #6=E('#7=F();')ENDSEC;
+            END-ISO-10303-21;";
+        assert!(parse(input).is_err());
+        let (step, warnings) = parse_tolerant(input).unwrap();
+        assert_eq!(step.data.iter().map(Data::id).collect::<Vec<u64>>(), vec![1, 3]);
+        assert_eq!(step.sections[0].ids, vec![1, 3]);
+        let warning = |line, col, id: Option<u64>, message: &str| Warning {
+            span: Some(Span { start: (line, col), end: (line, col) }),
+            id,
+            message: message.to_owned(),
+        };
+        assert_eq!(warnings, vec![
+            warning(7, 13, None, "stray semicolon"),
+            warning(8, 18, Some(2), "malformed entity instance skipped"),
+            warning(9, 18, Some(3), "lowercase boolean"),
+            warning(11, 13, Some(4), "malformed entity instance skipped"),
+            warning(11, 18, Some(5), "entity instance with an invalid string skipped"),
+            warning(12, 28, Some(6), "malformed entity instance skipped"),
+        ]);
+        // truncated in the middle of an entity instance, or a quote left open up to the end
+        let end = input.find("#3=").unwrap();
+        assert_eq!(parse_tolerant(&input[..end + 6]).unwrap_err(), PreprocessError::Fail(PreprocessErrorInfo::LineCol((9, 19))));
+        let quoted = input.replace("#1=CARTESIAN_POINT(", "#1=CARTESIAN'_POINT(");
+        assert!(parse_tolerant(&quoted).is_err());
+    }
+
+    // cargo test --release -- --ignored bench
+    #[test]
+    #[ignore]
+    fn bench() {
+        let buf = fs::read_to_string("./example.STEP").unwrap();
+        let step = preprocess::parse(&buf).unwrap();
+        let n = step.data.iter().map(Data::id).max().unwrap();
+        let mut scaled = Step::new(step.header, Vec::new());
+        for k in 0..10 {
+            let mut copy = preprocess::parse(&buf).unwrap().data;
+            copy.iter_mut().for_each(|d| d.map_ids(&|id| id + k * n));
+            scaled.data.append(&mut copy);
+        }
+        scaled.sections[0].ids = scaled.data.iter().map(Data::id).collect();
+        let text = writer::write(&scaled).unwrap();
+        let time = |f: &dyn Fn()| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        };
+        let pest = time(&|| { preprocess::parse(&text).unwrap(); });
+        let stream = time(&|| { parse(&text).unwrap(); });
+        let records = time(&|| { Records::new(&text).for_each(|r| { r.unwrap(); }); });
+        println!("{} bytes, {} entities", text.len(), scaled.data.len());
+        println!("pest: {:?}, stream: {:?}, records only: {:?}", pest, stream, records);
+    }
+}
@@ -0,0 +1,330 @@
+use super::escape;
+use super::preprocess::{AnchorItem, Data, DataSection, Step, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+
+// ISO 10303-21 exchange structure from preprocessed entities.
+// Entity instances are written in ascending order of ids, and what is written
+// reads back into the same Step by preprocess::parse.
+
+#[derive(Debug, PartialEq)]
+pub enum WriteError {
+    // NaN and infinities have no representation in a STEP file
+    NonFiniteFloat(f64),
+    // an entity or enumeration name which is not a valid symbol
+    InvalidName(String),
+    Format(fmt::Error),
+}
+
+impl From<fmt::Error> for WriteError {
+    fn from(e: fmt::Error) -> Self {
+        WriteError::Format(e)
+    }
+}
+
+fn check_name(name: &str) -> Result<&str, WriteError> {
+    let mut chars = name.chars();
+    let valid = chars.next().map(|c| c.is_ascii_alphabetic()).unwrap_or(false)
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(name)
+    }
+    else {
+        Err(WriteError::InvalidName(name.to_owned()))
+    }
+}
+
+// shortest representation reading back to the same value,
+// always with a decimal point and an upper case exponent (1., 0.01, 1.5E-7)
+fn float(f: f64) -> Result<String, WriteError> {
+    if !f.is_finite() {
+        return Err(WriteError::NonFiniteFloat(f));
+    }
+    let abs = f.abs();
+    if abs == 0.0 || (1e-4..1e15).contains(&abs) {
+        let s = format!("{}", f);
+        // Display writes -0 for negative zero
+        Ok(if s.contains('.') { s } else { s + "." })
+    }
+    else {
+        let s = format!("{:E}", f);
+        let (mantissa, exp) = s.split_at(s.find('E').ok_or(WriteError::Format(fmt::Error))?);
+        if mantissa.contains('.') {
+            Ok(s)
+        }
+        else {
+            Ok(format!("{}.{}", mantissa, exp))
+        }
+    }
+}
+
+fn value(w: &mut String, v: &Value) -> Result<(), WriteError> {
+    match v {
+        Value::Float(f) => w.push_str(&float(*f)?),
+        Value::Int(i) => write!(w, "{}", i)?,
+        Value::String(s) => write!(w, "'{}'", escape::encode(s))?,
+        Value::Id(id) => write!(w, "#{}", id)?,
+        Value::Bool(b) => w.push_str(if *b { ".T." } else { ".F." }),
+        Value::Enum(e) => write!(w, ".{}.", check_name(e)?)?,
+        Value::Tuple(vs) => {
+            w.push('(');
+            values(w, vs)?;
+            w.push(')');
+        },
+        Value::Xplicit => w.push('*'),
+        Value::Undefined => w.push('$'),
+        Value::Desc(name, args) => desc(w, name, args)?,
+    }
+    Ok(())
+}
+
+fn values(w: &mut String, vs: &[Value]) -> Result<(), WriteError> {
+    for (i, v) in vs.iter().enumerate() {
+        if i > 0 {
+            w.push(',');
+        }
+        value(w, v)?;
+    }
+    Ok(())
+}
+
+fn desc(w: &mut String, name: &str, args: &[Value]) -> Result<(), WriteError> {
+    w.push_str(check_name(name)?);
+    w.push('(');
+    values(w, args)?;
+    w.push(')');
+    Ok(())
+}
+
+fn entity(w: &mut String, d: &Data) -> Result<(), WriteError> {
+    match d {
+        Data::Single(id, name, args) => {
+            write!(w, "#{}=", id)?;
+            desc(w, name, args)?;
+            w.push_str(";\n");
+        },
+        Data::Aggregate(id, partials) => {
+            // partial entities in alphabetical order as the standard requires
+            let mut partials = partials.iter().collect::<Vec<&(String, Vec<Value>)>>();
+            partials.sort_by(|a, b| a.0.cmp(&b.0));
+            writeln!(w, "#{}=(", id)?;
+            for (name, args) in partials {
+                desc(w, name, args)?;
+                w.push('\n');
+            }
+            w.push_str(");\n");
+        },
+    }
+    Ok(())
+}
+
+pub fn write(step: &Step) -> Result<String, WriteError> {
+    let mut w = String::new();
+    w.push_str("ISO-10303-21;\nHEADER;\n");
+    for (name, args) in &step.header {
+        desc(&mut w, name, args)?;
+        w.push_str(";\n");
+    }
+    w.push_str("ENDSEC;\n");
+    if !step.anchors.is_empty() {
+        w.push_str("ANCHOR;\n");
+        for (name, item) in &step.anchors {
+            write!(w, "<{}>=", name)?;
+            match item {
+                AnchorItem::Value(v) => value(&mut w, v)?,
+                AnchorItem::Resource(r) => write!(w, "<{}>", r)?,
+            }
+            w.push_str(";\n");
+        }
+        w.push_str("ENDSEC;\n");
+    }
+    if !step.references.is_empty() {
+        w.push_str("REFERENCE;\n");
+        for (id, r) in &step.references {
+            writeln!(w, "#{}=<{}>;", id, r)?;
+        }
+        w.push_str("ENDSEC;\n");
+    }
+    let mut data = step.data.iter().collect::<Vec<&Data>>();
+    data.sort_by_key(|d| d.id());
+    // entities which are not in any section go to the first one
+    let in_sections = step.sections.iter().flat_map(|section| section.ids.iter()).collect::<HashSet<&u64>>();
+    let plain = DataSection { params: Vec::new(), ids: Vec::new() };
+    let sections = if step.sections.is_empty() { vec![&plain] } else { step.sections.iter().collect() };
+    for (i, section) in sections.iter().enumerate() {
+        w.push_str("DATA");
+        if !section.params.is_empty() {
+            w.push('(');
+            values(&mut w, &section.params)?;
+            w.push(')');
+        }
+        w.push_str(";\n");
+        let ids = section.ids.iter().collect::<HashSet<&u64>>();
+        for d in &data {
+            if ids.contains(&d.id()) || (i == 0 && !in_sections.contains(&d.id())) {
+                entity(&mut w, d)?;
+            }
+        }
+        w.push_str("ENDSEC;\n");
+    }
+    for signature in &step.signatures {
+        writeln!(w, "SIGNATURE;\n{}\nENDSEC;", signature)?;
+    }
+    w.push_str("END-ISO-10303-21;\n");
+    Ok(w)
+}
+
+// ids from 1 in ascending order of the current ids, then references to other files,
+// references follow (dangling references are left as they are)
+pub fn renumber(step: &mut Step) {
+    let mut old = step.data.iter().map(Data::id).collect::<Vec<u64>>();
+    old.sort();
+    old.extend(step.references.iter().map(|(id, _)| *id));
+    let ids = old
+        .into_iter()
+        .enumerate()
+        .map(|(i, id)| (id, i as u64 + 1))
+        .collect::<HashMap<u64, u64>>();
+    let f = |id| ids.get(&id).copied().unwrap_or(id);
+    step.data.iter_mut().for_each(|d| d.map_ids(&f));
+    for (id, _) in step.references.iter_mut() {
+        *id = f(*id);
+    }
+    for section in step.sections.iter_mut() {
+        section.ids.iter_mut().for_each(|id| *id = f(*id));
+    }
+    for (_, item) in step.anchors.iter_mut() {
+        if let AnchorItem::Value(v) = item {
+            v.map_ids(&f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::preprocess;
+    use std::fs;
+
+    #[test]
+    fn test_float() {
+        assert_eq!(float(600.0), Ok("600.".to_owned()));
+        assert_eq!(float(-0.01), Ok("-0.01".to_owned()));
+        assert_eq!(float(0.0), Ok("0.".to_owned()));
+        assert_eq!(float(1.5e-7), Ok("1.5E-7".to_owned()));
+        assert_eq!(float(1e20), Ok("1.E20".to_owned()));
+        assert_eq!(float(0.749019607843137), Ok("0.749019607843137".to_owned()));
+        assert!(float(f64::NAN).is_err());
+        for f in &[1.0 / 3.0, -2.5e-300, 1.7976931348623157e308, 123456.789] {
+            let step = preprocess::parse(&format!(r"ISO-10303-21;
+                HEADER;
+                FILE_DESCRIPTION((''),'2;1');
+                ENDSEC;
+                DATA;
+                #1=LENGTH_MEASURE({});
+                ENDSEC;
+                END-ISO-10303-21;", float(*f).unwrap())).unwrap();
+            assert_eq!(step.data, vec![Data::Single(1, "LENGTH_MEASURE".to_owned(), vec![Value::Float(*f)])]);
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        let step = preprocess::parse(r"ISO-10303-21;
+            HEADER;
+            FILE_NAME('\X2\30BD30EA30C330C9\X0\.stp','2020-01-01T00:00:00',('it''s'),(''),'','','');
+            ENDSEC;
+            DATA;
+            #10=PRODUCT('a\\b','',$,(#3,*));
+            #3=(
+            NAMED_UNIT(*)
+            LENGTH_UNIT()
+            SI_UNIT(.MILLI.,.METRE.)
+            );
+            #5=B_SPLINE_CURVE_WITH_KNOTS('',2,(#10),.UNSPECIFIED.,.F.,.T.,(-3,3),(1.E-5,2.5),.UNSPECIFIED.);
+            #7=MEASURE_REPRESENTATION_ITEM('',LENGTH_MEASURE(25.4),#3);
+            ENDSEC;
+            END-ISO-10303-21;").unwrap();
+        let written = write(&step).unwrap();
+        assert_eq!(written, r"ISO-10303-21;
+HEADER;
+FILE_NAME('\X2\30BD30EA30C330C9\X0\.stp','2020-01-01T00:00:00',('it''s'),(''),'','','');
+ENDSEC;
+DATA;
+#3=(
+LENGTH_UNIT()
+NAMED_UNIT(*)
+SI_UNIT(.MILLI.,.METRE.)
+);
+#5=B_SPLINE_CURVE_WITH_KNOTS('',2,(#10),.UNSPECIFIED.,.F.,.T.,(-3,3),(1.E-5,2.5),.UNSPECIFIED.);
+#7=MEASURE_REPRESENTATION_ITEM('',LENGTH_MEASURE(25.4),#3);
+#10=PRODUCT('a\\b','',$,(#3,*));
+ENDSEC;
+END-ISO-10303-21;
+");
+        let mut step = preprocess::parse(&written).unwrap();
+        renumber(&mut step);
+        let renumbered = write(&step).unwrap();
+        assert!(renumbered.contains("#1=(\n"));
+        assert!(renumbered.contains("#4=PRODUCT('a\\\\b','',$,(#1,*));"));
+        assert!(renumbered.contains("#3=MEASURE_REPRESENTATION_ITEM('',LENGTH_MEASURE(25.4),#1);"));
+        step.data.push(Data::Single(5, "CARTESIAN_POINT".to_owned(), vec![Value::Float(f64::INFINITY)]));
+        assert_eq!(write(&step), Err(WriteError::NonFiniteFloat(f64::INFINITY)));
+    }
+
+    #[test]
+    fn test_write_edition_3() {
+        let text = r"ISO-10303-21;
+HEADER;
+FILE_DESCRIPTION((''),'2;1');
+ENDSEC;
+ANCHOR;
+<shell>=#20;
+<origin>=<other.stp#origin>;
+ENDSEC;
+REFERENCE;
+#5=<part.stp#body>;
+ENDSEC;
+DATA('geometry',('AP242_MANAGED_MODEL_BASED_3D_ENGINEERING_MIM_LF'));
+#10=CARTESIAN_POINT('',(0.,0.,0.));
+#20=CLOSED_SHELL('',(#5));
+ENDSEC;
+DATA;
+#15=CARTESIAN_POINT('',(1.,0.,0.));
+ENDSEC;
+SIGNATURE;
+MIIB
+ENDSEC;
+END-ISO-10303-21;
+";
+        let mut step = preprocess::parse(text).unwrap();
+        assert_eq!(write(&step).unwrap(), text);
+        renumber(&mut step);
+        let renumbered = write(&step).unwrap();
+        assert!(renumbered.contains("<shell>=#3;"));
+        assert!(renumbered.contains("#4=<part.stp#body>;"));
+        assert!(renumbered.contains("#3=CLOSED_SHELL('',(#4));\nENDSEC;\nDATA;\n#2=CARTESIAN_POINT"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let buf = fs::read_to_string("./example.STEP").unwrap();
+        let step = preprocess::parse(&buf).unwrap();
+        let written = write(&step).unwrap();
+        let reread = preprocess::parse(&written).unwrap();
+        assert_eq!(reread.header, step.header);
+        let mut data = step.data;
+        data.sort_by_key(Data::id);
+        let sort_partials = |d: Data| match d {
+            Data::Aggregate(id, mut partials) => {
+                partials.sort_by(|a, b| a.0.cmp(&b.0));
+                Data::Aggregate(id, partials)
+            },
+            d => d,
+        };
+        assert_eq!(
+            reread.data.into_iter().map(sort_partials).collect::<Vec<Data>>(),
+            data.into_iter().map(sort_partials).collect::<Vec<Data>>());
+        assert_eq!(write(&preprocess::parse(&written).unwrap()).unwrap(), written);
+    }
+}